@@ -1,15 +1,25 @@
-use std::{io, process::exit};
+use std::{io::{self, Write}, path::PathBuf, process::exit, str::FromStr, thread, time::{Duration, Instant}};
 use bitcoincash_addr::Address;
-use clap::{arg, Command};
-use crate::{miner::chain::Blockchain, wallet::{transaction::Transaction, wallet::Wallets}};
+use clap::{arg, ArgAction, ArgMatches, Command};
+use crate::{miner::{chain::{Blockchain, ChainEvent}, mempool::{Mempool, MAX_BLOCK_SIZE}, pow::{CancelToken, Miner}, reward::{RewardSchedule, COINBASE_MATURITY, HALVING_INTERVAL, INITIAL_SUBSIDY}}, network::{node::{Node, NodeConfig, DEFAULT_LISTEN_ADDR, DEFAULT_MAX_INBOUND}, rpc::{RpcServer, DEFAULT_RPC_ADDR}}, wallet::{amount::Amount, selection::Strategy, transaction::{Fee, SpendOptions, Transaction}, wallet::Wallets}};
+
+//? data directory used when neither --datadir nor the environment variable is given
+const DEFAULT_DATA_DIR: &str = "data";
+//? environment variable selecting the data directory
+const DATA_DIR_ENV: &str = "BCHAIN_DATADIR";
+//? environment variable selecting the address rewarded for mined blocks
+const MINE_TO_ENV: &str = "BCHAIN_MINE_TO";
+
 #[derive(Debug)]
 pub struct Cli{
-
+    data_dir: PathBuf, //directory holding the databases of the selected chain
+    threads: usize, //number of threads mining new blocks, 0 for one per core
+    mine_to: Option<String>, //address rewarded for the mined blocks
 }
 
 impl Cli {
     pub fn new() -> Result<Cli,io::Error>{
-        Ok(Cli{})
+        Ok(Cli{ data_dir: PathBuf::from(DEFAULT_DATA_DIR), threads: 0, mine_to: None })
     }
 
     pub fn run(&mut self) -> Result<(),io::Error>{
@@ -18,10 +28,37 @@ impl Cli {
             .version("1.0") // version of our chain
             .author("Jester") // author of this project
             .about("A blockchain in rust") //about our project
+            .arg(arg!(--datadir <DIR> "Directory holding the chain and wallet databases")
+                .global(true)
+                .env(DATA_DIR_ENV)
+                .default_value(DEFAULT_DATA_DIR)
+            )
+            .arg(arg!(--threads <N> "Number of threads mining new blocks, 0 for one per core")
+                .global(true)
+                .value_parser(clap::value_parser!(usize))
+                .default_value("0")
+            )
+            .arg(arg!(--"mine-to" <ADDRESS> "Address rewarded for mined blocks, the sender when not given")
+                .global(true)
+                .env(MINE_TO_ENV)
+            )
             //?Sub-commands list for the blockchain cli
 
             //* All get operations of the command line */
-            .subcommand(Command::new("print-chain").about("Pritns he blockchain"))
+            .subcommand(Command::new("print-chain").about("Pritns he blockchain")
+                .arg(arg!(--headers "Print only the block headers, without transactions"))
+            )
+            .subcommand(Command::new("get-block")
+                .about("Print a block by its height or hash")
+                .arg(arg!(<BLOCK>"'Height or hash of the block'"))
+            )
+            .subcommand(Command::new("prove-transaction")
+                .about("Print the block confirming a transaction and the merkle proof of its inclusion")
+                .arg(arg!(<TXID>"'Id of the confirmed transaction'"))
+            )
+            .subcommand(Command::new("chain-tips")
+                .about("List the last block of every stored branch, the active chain first")
+            )
             .subcommand(Command::new("list-addresses")
                 .about("Get a list of all your wallet addresses")
             )
@@ -33,35 +70,148 @@ impl Cli {
             //* All creation operations of the command line */
             .subcommand(Command::new("create")
                 .about("Create a new Blockchain")
-                .arg(arg!(<ADDRESS>"'The adress of genesis block'"))
+                .arg(arg!([ADDRESS]"'The adress of genesis block'")
+                    .required_unless_present("genesis-from")
+                )
+                .arg(arg!(--"genesis-from" <DIR> "Data directory of a chain to take the genesis block and reward rules from, to join its network")
+                    .conflicts_with_all(["ADDRESS", "subsidy", "halving-interval", "coinbase-maturity"])
+                )
+                .arg(arg!(--subsidy <COINS> "Block subsidy before the first halving [default: 100]")
+                    .value_parser(Amount::from_str)
+                )
+                .arg(arg!(--"halving-interval" <BLOCKS> "Number of blocks between two halvings of the subsidy [default: 1000]")
+                    .value_parser(clap::value_parser!(usize))
+                )
+                .arg(arg!(--"coinbase-maturity" <BLOCKS> "Number of blocks before a coinbase can be spent [default: 100]")
+                    .value_parser(clap::value_parser!(usize))
+                )
             )
             .subcommand(Command::new("create-wallet")
                 .about("Creates a wallet")
             )   
+            .subcommand(Command::new("reindex-utxo")
+                .about("Rebuild the unspent output set and spent records from the blocks")
+            )
+            .subcommand(Command::new("validate-chain")
+                .about("Check every block of the active chain from genesis up against the consensus rules")
+            )
+            .subcommand(Command::new("rollback")
+                .about("Take blocks off the top of the chain, their transactions going back to the mempool")
+                .arg(arg!(<N>"'Number of blocks to take off'")
+                    .value_parser(clap::value_parser!(usize))
+                )
+            )
+            .subcommand(Command::new("invalidate-block")
+                .about("Mark a block and its descendants invalid, moving the chain to the best branch without them")
+                .arg(arg!(<HASH>"'Hash of the block'"))
+            )
+            .subcommand(Command::new("reconsider-block")
+                .about("Make a rolled back block and the branch built on it candidates again, moving the chain onto them if they have the most work")
+                .arg(arg!(<HASH>"'Hash of the block'"))
+            )
 
             //* All transaction operations of our command line */
             .subcommand(Command::new("send")
                 .about("Reward Someone!!")
                 .arg(arg!(<FROM>"'Source wallet address'"))
                 .arg(arg!(<TO>"'Destination wallet address'"))
-                .arg(arg!(<AMOUNT>"Amount to be transfered"))
+                .arg(arg!(<AMOUNT>"Amount of coins to be transfered, like 1.25")
+                    .value_parser(Amount::from_str)
+                )
+                .arg(arg!(--fee <COINS> "Fee paid to the miner [default: 0]")
+                    .value_parser(Amount::from_str)
+                    .conflicts_with("fee-rate")
+                )
+                .arg(arg!(--"fee-rate" <UNITS_PER_BYTE> "Fee in base units paid to the miner for every byte of the transaction")
+                    .value_parser(clap::value_parser!(u64))
+                )
+                .arg(arg!(--"coin-selection" <STRATEGY> "Strategy choosing the outputs of the sender to spend")
+                    .value_parser(Strategy::NAMES)
+                    .default_value("in-order")
+                )
+                .arg(arg!(--mine "Mine a block with the transaction right away instead of adding it to the mempool"))
+                .arg(arg!(--"spend-unconfirmed" "Also spend outputs of transactions still in the mempool")
+                    .conflicts_with("mine")
+                )
+                .arg(arg!(--"max-time" <SECONDS> "Give up mining the block after this many seconds")
+                    .value_parser(clap::value_parser!(u64))
+                    .requires("mine")
+                )
+                .arg(arg!(--"max-hashes" <N> "Give up mining the block after this many hashes")
+                    .value_parser(clap::value_parser!(u64))
+                    .requires("mine")
+                )
+            )
+            .subcommand(Command::new("bump-fee")
+                .about("Replace a pending transaction with one paying a higher fee from its change")
+                .arg(arg!(<TXID>"'Id of the pending transaction'"))
+                .arg(arg!(--fee <COINS> "Fee paid to the miner by the replacement, covering the original and its pending descendants")
+                    .value_parser(Amount::from_str)
+                    .conflicts_with("fee-rate")
+                    .required_unless_present("fee-rate")
+                )
+                .arg(arg!(--"fee-rate" <UNITS_PER_BYTE> "Fee in base units paid to the miner for every byte of the replacement")
+                    .value_parser(clap::value_parser!(u64))
+                )
+            )
+            .subcommand(Command::new("mempool")
+                .about("List the transactions waiting for a block, highest fee rate first")
+            )
+            .subcommand(Command::new("mine")
+                .about("Mine a block of transactions from the mempool, paying their fees to the --mine-to address")
+                .arg(arg!(--"max-size" <BYTES> "Bytes the transactions of the block may take [default: 1000000]")
+                    .value_parser(clap::value_parser!(usize))
+                )
+                .arg(arg!(--"max-time" <SECONDS> "Give up mining the block after this many seconds")
+                    .value_parser(clap::value_parser!(u64))
+                )
+                .arg(arg!(--"max-hashes" <N> "Give up mining the block after this many hashes")
+                    .value_parser(clap::value_parser!(u64))
+                )
+            )
+            .subcommand(Command::new("node")
+                .about("Run a node relaying blocks and transactions with its peers until Ctrl+C")
+                .arg(arg!(--listen <ADDR> "Address accepting peers, port 0 for any free port")
+                    .default_value(DEFAULT_LISTEN_ADDR)
+                )
+                .arg(arg!(--peer <ADDR> "Address of a peer to stay connected to, can be repeated")
+                    .action(ArgAction::Append)
+                )
+                .arg(arg!(--"max-peers" <N> "Number of peers dialing this node it serves at once")
+                    .value_parser(clap::value_parser!(usize))
+                )
+                .arg(arg!(--mine "Mine blocks of the mempool paying their rewards to the --mine-to address"))
+                .arg(arg!(--"rpc-bind" [ADDR] "Answer JSON-RPC calls on this address, authenticated by the .cookie file of the data directory")
+                    .default_missing_value(DEFAULT_RPC_ADDR)
+                )
             )
             .get_matches();
 
         //? All the matches related to the command line 
 
+        //selecting the chain to work on
+        if let Some(data_dir) = matches.get_one::<String>("datadir"){
+            self.data_dir = PathBuf::from(data_dir);
+        }
+        //number of threads used when mining
+        if let Some(threads) = matches.get_one::<usize>("threads"){
+            self.threads = *threads;
+        }
+        //address receiving the block rewards
+        if let Some(address) = matches.get_one::<String>("mine-to"){
+            self.mine_to = Some(address.clone());
+        }
+
         //* All the get matchings */
 
         //function to get the balance of an user
-        if let Some(ref matches) = matches.subcommand_matches("get-balance"){
+        if let Some(matches) = matches.subcommand_matches("get-balance"){
             if let Some(address) = matches.get_one::<String>("ADDRESS"){
                 let pub_key_hash = Address::decode(address).unwrap().body;
-                let bc = Blockchain::new()?;
+                let bc = Blockchain::new(&self.data_dir)?;
                 let utoxs = bc.find_utxo(pub_key_hash.clone());
-                let mut balance:i32 = 0;
-                for out in utoxs{
-                    balance+=out.value;
-                }
+                let balance = Amount::checked_sum(utoxs.iter().map(|out| out.value))
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "VALUE_OVERFLOW: the balance doesn't fit an amount"))?;
                 println!(
                     "
                     Account: {}
@@ -72,9 +222,51 @@ impl Cli {
             }
         }
 
+        //function to print a single block, looked up by height when the argument is a number
+        if let Some(matches) = matches.subcommand_matches("get-block"){
+            if let Some(block_id) = matches.get_one::<String>("BLOCK"){
+                let bc = Blockchain::new(&self.data_dir)?;
+                let block = match block_id.parse::<usize>(){
+                    Ok(height) => bc.get_block_by_height(height)?,
+                    Err(_) => bc.get_block_by_hash(block_id)?,
+                };
+                match block{
+                    Some(block) => println!("{:#?}",block),
+                    None => println!("Block not found: {}",block_id),
+                }
+            }
+        }
+
+        //function to prove a transaction is part of the active chain
+        if let Some(matches) = matches.subcommand_matches("prove-transaction"){
+            if let Some(tranc_id) = matches.get_one::<String>("TXID"){
+                let bc = Blockchain::new(&self.data_dir)?;
+                match bc.prove_transaction(tranc_id)?{
+                    Some((block_hash, proof)) => {
+                        let verified = bc.verify_transaction_proof(&block_hash, tranc_id, &proof)?;
+                        println!("Block: {}\nVerified: {}\n{:#?}", block_hash, verified, proof);
+                    }
+                    None => println!("Transaction not found in the chain: {}", tranc_id),
+                }
+            }
+        }
+
+        //function to list the branches of the chain
+        if let Some(_matches)=matches.subcommand_matches("chain-tips"){
+            let bc = Blockchain::new(&self.data_dir)?;
+            for tip in bc.tips()?{
+                let status = match (&tip.invalid, tip.active){
+                    (Some(reason), _) => format!("invalid ({})", reason),
+                    (None, true) => String::from("active"),
+                    (None, false) => String::from("valid fork"),
+                };
+                println!("{} height {} work {} {}", tip.hash, tip.height, tip.work, status);
+            }
+        }
+
         //function to get a list of all addresses of wallets present in database
-        if let Some(ref _matches)=matches.subcommand_matches("list-addresses"){
-            let wallets = Wallets::new()?;
+        if let Some(_matches)=matches.subcommand_matches("list-addresses"){
+            let wallets = Wallets::new(&self.data_dir)?;
             let addresses = wallets.get_all_addresses();
             println!("Addresses:");
             for address in addresses{
@@ -83,33 +275,102 @@ impl Cli {
         }
 
         //function to print our blockchain
-        if let Some(ref _matches)=matches.subcommand_matches("print-chain"){
-            self.print_chain();
+        if let Some(matches)=matches.subcommand_matches("print-chain"){
+            let bc = Blockchain::new(&self.data_dir)?;
+            Self::print_chain(&bc, matches.get_flag("headers"), &mut io::stdout().lock())?;
         }
 
         //* All the creation matches of our command line */
 
         //Function to create a new blockchain with a coinbase
-        if let Some(ref matches) = matches.subcommand_matches("create"){
+        if let Some(matches) = matches.subcommand_matches("create"){
             if let Some(address) = matches.get_one::<String>("ADDRESS"){
                 let address: String = String::from(address);
-                let _response = Blockchain::create_blockchain(address.clone());
+                let rewards = RewardSchedule::new(
+                    matches.get_one::<Amount>("subsidy").copied().unwrap_or(INITIAL_SUBSIDY),
+                    matches.get_one::<usize>("halving-interval").copied().unwrap_or(HALVING_INTERVAL),
+                    matches.get_one::<usize>("coinbase-maturity").copied().unwrap_or(COINBASE_MATURITY),
+                )?;
+                let _response = Blockchain::create_blockchain_with(address.clone(), &self.data_dir, rewards)?;
                 println!("Created Blockchain");
             }
+            if let Some(other) = matches.get_one::<String>("genesis-from"){
+                let genesis = {
+                    let other = Blockchain::new(&PathBuf::from(other))?;
+                    other.get_block_by_height(0)?.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "MISSING_GENESIS: the chain has no block at height 0"))?
+                };
+                let bc = Blockchain::create_from_genesis(genesis, &self.data_dir)?;
+                println!("Created Blockchain from genesis block {}", bc.tip_hash());
+            }
         }
 
         //Function to create a new wallet in the blockchain
-        if let Some(ref _matches) = matches.subcommand_matches("create-wallet"){
-            let mut wallets = Wallets::new()?;
+        if let Some(_matches) = matches.subcommand_matches("create-wallet"){
+            let mut wallets = Wallets::new(&self.data_dir)?;
             let address = wallets.create_wallet();
             wallets.save_all()?;
             println!("Success: {}",address);
         }
 
+        //Function to rebuild the UTXO set from the stored blocks
+        if let Some(_matches) = matches.subcommand_matches("reindex-utxo"){
+            let mut bc = Blockchain::new(&self.data_dir)?;
+            bc.validate_chain()?;//refusing to rebuild from a corrupted chain
+            bc.reindex_utxo()?;
+            bc.rebuild_spent_records()?;
+            println!("Reindexed UTXO set and spent records");
+        }
+
+        //Function to check the whole chain, opening it only checks what it rebuilds
+        if let Some(_matches) = matches.subcommand_matches("validate-chain"){
+            let bc = Blockchain::new(&self.data_dir)?;
+            bc.validate_chain()?;
+            println!("Validated {} blocks up to {}", bc.get_best_height()? + 1, bc.tip_hash());
+        }
+
+        //function to take blocks off the top of the chain
+        if let Some(matches) = matches.subcommand_matches("rollback"){
+            let blocks = matches.get_one::<usize>("N").copied().unwrap_or(0);
+            let mut bc = Blockchain::new(&self.data_dir)?;
+            let events = bc.rollback(blocks)?;
+            Mempool::open(&bc)?.apply_chain_events(&events, &bc)?;
+            println!("Rolled back {} blocks, the tip is at height {}", events.len(), bc.get_best_height()?);
+        }
+
+        //function to refuse a block and the branch built on it
+        if let Some(matches) = matches.subcommand_matches("invalidate-block"){
+            let hash = if let Some(hash) = matches.get_one::<String>("HASH"){
+                hash
+            }else{
+                println!("Block hash not supply! usage");
+                exit(1)
+            };
+            let mut bc = Blockchain::new(&self.data_dir)?;
+            let events = bc.invalidate_block(hash)?;
+            Mempool::open(&bc)?.apply_chain_events(&events, &bc)?;
+            Self::print_chain_events(&events);
+            println!("Invalidated {}, the tip is at height {}", hash, bc.get_best_height()?);
+        }
+
+        //function to bring back a rolled back branch
+        if let Some(matches) = matches.subcommand_matches("reconsider-block"){
+            let hash = if let Some(hash) = matches.get_one::<String>("HASH"){
+                hash
+            }else{
+                println!("Block hash not supply! usage");
+                exit(1)
+            };
+            let mut bc = Blockchain::new(&self.data_dir)?;
+            let events = bc.reconsider_block(hash)?;
+            Mempool::open(&bc)?.apply_chain_events(&events, &bc)?;
+            Self::print_chain_events(&events);
+            println!("Reconsidered {}, the tip is at height {}", hash, bc.get_best_height()?);
+        }
+
         //* All the transaction matches of our command line */
 
         //Function to send currency from and to particular address, a partcular amount
-        if let Some(ref matches) = matches.subcommand_matches("send"){
+        if let Some(matches) = matches.subcommand_matches("send"){
             let from: &String = if let Some(address) = matches.get_one::<String>("FROM"){
                 address
             }else{
@@ -124,32 +385,209 @@ impl Cli {
                 exit(1)
             };
 
-            let amount: i32 = if let Some(amount) = matches.get_one::<String>("AMOUNT"){
-                amount.parse().expect("Parsing error!")
+            let amount: Amount = if let Some(amount) = matches.get_one::<Amount>("AMOUNT"){
+                *amount
             }else{
                 println!("From not supply! usage");
                 exit(1)
             };
 
-            let mut bc = Blockchain::new()?;
-            let tx = Transaction::new_utxo(from, to, amount, &bc).unwrap_or_else(|err|{
+            let mut bc = Blockchain::new(&self.data_dir)?;
+            let fee = match (matches.get_one::<Amount>("fee"), matches.get_one::<u64>("fee-rate")){
+                (_, Some(rate)) => Fee::PerByte(Amount::from_units(*rate)),
+                (fee, None) => Fee::Absolute(fee.copied().unwrap_or(Amount::ZERO)),
+            };
+            let strategy: Strategy = matches.get_one::<String>("coin-selection").map_or(Ok(Strategy::default()), |name| name.parse())?;
+            let mut mempool = Mempool::open(&bc)?;
+            let wallets = Wallets::new(&self.data_dir)?;
+            let selector = strategy.selector();
+            let options = SpendOptions{ fee, selector: selector.as_ref(), pending: &mempool, unconfirmed: matches.get_flag("spend-unconfirmed") };
+            let tx = Transaction::new_utxo(&wallets, from, to, amount, &bc, &options).unwrap_or_else(|err|{
                 todo!("Can't create transaction: {}",err);
             });
-            bc.add_block(vec![tx])?;
+            if !matches.get_flag("mine"){
+                let tranc_id = tx.tranc_id.clone();
+                let fee = mempool.add(tx, &bc)?;
+                println!("Added {} to the mempool, paying a fee of {}", tranc_id, fee);
+                return Ok(());
+            }
+            let cancel = self.set_miner(&mut bc, matches)?;
+            let mine_to = self.mine_to.clone().unwrap_or_else(|| from.to_string());//the sender mines by default
+            match bc.mine_block(&mine_to, vec![tx.clone()]){
+                Ok(stats) => println!("Mined block: {}", stats),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {
+                    println!("Mining stopped, the transaction was not sent ({})", e);
+                    exit(if cancel.is_cancelled() { 130 } else { 1 })
+                }
+                Err(e) => return Err(e),
+            }
+            mempool.remove_confirmed(&[tx])?;
             println!("Success");
         }   
 
+        //Function to replace a pending transaction with one paying a higher fee
+        if let Some(matches) = matches.subcommand_matches("bump-fee"){
+            let tranc_id = if let Some(tranc_id) = matches.get_one::<String>("TXID"){
+                tranc_id
+            }else{
+                println!("Transaction id not supply! usage");
+                exit(1)
+            };
+            let bc = Blockchain::new(&self.data_dir)?;
+            let fee = match (matches.get_one::<Amount>("fee"), matches.get_one::<u64>("fee-rate")){
+                (_, Some(rate)) => Fee::PerByte(Amount::from_units(*rate)),
+                (fee, None) => Fee::Absolute(fee.copied().unwrap_or(Amount::ZERO)),
+            };
+            let mut mempool = Mempool::open(&bc)?;
+            let wallets = Wallets::new(&self.data_dir)?;
+            let tx = Transaction::bump_fee(&wallets, tranc_id, fee, &bc, &mempool).unwrap_or_else(|err|{
+                println!("Can't bump the fee of {}: {}", tranc_id, err);
+                exit(1)
+            });
+            let replacement_id = tx.tranc_id.clone();
+            let fee = mempool.add(tx, &bc)?;
+            println!("Replaced {} with {}, paying a fee of {}", tranc_id, replacement_id, fee);
+        }
+
+        //Function to list the pending transactions
+        if let Some(_matches) = matches.subcommand_matches("mempool"){
+            let bc = Blockchain::new(&self.data_dir)?;
+            let mempool = Mempool::open(&bc)?;
+            if mempool.is_empty(){
+                println!("No pending transactions");
+                return Ok(());
+            }
+            for entry in mempool.entries(){
+                println!("{} fee {} size {} bytes ({:.2} units/byte)", entry.transaction.tranc_id, entry.fee, entry.size, entry.fee_rate());
+            }
+            println!("{} pending transactions", mempool.len());
+        }
+
+        //Function to mine the pending transactions paying the most per byte
+        if let Some(matches) = matches.subcommand_matches("mine"){
+            let mine_to = match &self.mine_to{
+                Some(address) => address.clone(),
+                None => {
+                    println!("No address to reward, pass --mine-to or set {}", MINE_TO_ENV);
+                    exit(1)
+                }
+            };
+            let mut bc = Blockchain::new(&self.data_dir)?;
+            let mut mempool = Mempool::open(&bc)?;
+            let cancel = self.set_miner(&mut bc, matches)?;
+            let max_size = matches.get_one::<usize>("max-size").copied().unwrap_or(MAX_BLOCK_SIZE);
+            match mempool.mine_block(&mut bc, &mine_to, max_size){
+                Ok((stats, transactions)) => println!("Mined block with {} transactions: {}", transactions.len(), stats),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {
+                    println!("Mining stopped, the transactions stay in the mempool ({})", e);
+                    exit(if cancel.is_cancelled() { 130 } else { 1 })
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        //Function to run a node until Ctrl+C, the chain staying locked by it meanwhile
+        if let Some(matches) = matches.subcommand_matches("node"){
+            let mine_to = match (matches.get_flag("mine"), &self.mine_to){
+                (false, _) => None,
+                (true, Some(address)) => Some(address.clone()),
+                (true, None) => {
+                    println!("No address to reward, pass --mine-to or set {}", MINE_TO_ENV);
+                    exit(1)
+                }
+            };
+            env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+            let bc = Blockchain::new(&self.data_dir)?;
+            let mempool = Mempool::open(&bc)?;
+            let stop = Self::cancel_on_ctrl_c()?;
+            let config = NodeConfig{
+                listen: matches.get_one::<String>("listen").cloned().unwrap_or_else(|| DEFAULT_LISTEN_ADDR.to_string()),
+                peers: matches.get_many::<String>("peer").map(|peers| peers.cloned().collect()).unwrap_or_default(),
+                max_inbound: matches.get_one::<usize>("max-peers").copied().unwrap_or(DEFAULT_MAX_INBOUND),
+                mine_to,
+                threads: self.threads,
+            };
+            let node = Node::start(bc, mempool, config)?;
+            let rpc = match matches.get_one::<String>("rpc-bind"){
+                Some(bind) => match RpcServer::start(node.handle(), bind){
+                    Ok(rpc) => Some(rpc),
+                    Err(e) => {
+                        node.shutdown()?;
+                        return Err(e);
+                    }
+                },
+                None => None,
+            };
+            println!("Node listening on {}, Ctrl+C to stop", node.local_addr());
+            if let Some(rpc) = &rpc{
+                println!("Answering RPC calls on {}, credentials in {}", rpc.local_addr(), rpc.cookie_file().display());
+            }
+            while !stop.is_cancelled(){
+                thread::sleep(Duration::from_millis(200));
+            }
+            if let Some(rpc) = rpc{
+                rpc.shutdown()?;
+            }
+            let (hash, height) = node.shutdown()?;
+            println!("Stopped at height {} ({})", height, hash);
+        }
+
         Ok(())
     }
 
     //? Functions
 
+    //Function giving the chain a miner bounded by the --max-time and --max-hashes options and stopped by Ctrl+C
+    fn set_miner(&self, bc: &mut Blockchain, matches: &ArgMatches) -> Result<CancelToken,io::Error>{
+        let cancel = Self::cancel_on_ctrl_c()?;
+        let mut miner = Miner::new(self.threads).with_cancel(cancel.clone());
+        if let Some(seconds) = matches.get_one::<u64>("max-time"){
+            miner = miner.with_deadline(Instant::now() + Duration::from_secs(*seconds));
+        }
+        if let Some(max_hashes) = matches.get_one::<u64>("max-hashes"){
+            miner = miner.with_max_hashes(*max_hashes);
+        }
+        bc.set_miner(miner);
+        Ok(cancel)
+    }
+
+    //Function giving a token cancelled by the first Ctrl+C, a second one exiting right away
+    fn cancel_on_ctrl_c() -> Result<CancelToken,io::Error>{
+        let token = CancelToken::new();
+        let handler_token = token.clone();
+        ctrlc::set_handler(move || {
+            if handler_token.is_cancelled(){
+                exit(130);
+            }
+            println!("Stopping...");
+            handler_token.cancel();
+        }).map_err(io::Error::other)?;
+        Ok(token)
+    }
+
+    //Function printing how the active chain changed
+    fn print_chain_events(events: &[ChainEvent]){
+        for event in events{
+            match event{
+                ChainEvent::Connected(block) => println!("Connected {} at height {}", block.get_hash(), block.get_height()),
+                ChainEvent::Disconnected(block) => println!("Disconnected {} at height {}", block.get_hash(), block.get_height()),
+                ChainEvent::Rejected(invalid) => println!("Rejected {}", invalid),
+            }
+        }
+    }
+
     //Print function to print our blockchain using blockchain iterator
-    fn print_chain(&self){
-        let b = Blockchain::new().unwrap();
+    fn print_chain(b: &Blockchain, headers_only: bool, out: &mut dyn Write) -> Result<(),io::Error>{
+        if headers_only{
+            for (hash, header) in b.iter_headers(){
+                writeln!(out, "{}\n{:#?}", hash, header)?;
+            }
+            return Ok(());
+        }
         for block in b.iter(){
-            println!("{:#?}",block);
+            writeln!(out, "{:#?}",block)?;
         }
+        Ok(())
     }
 }
 
@@ -157,17 +595,42 @@ impl Cli {
 #[cfg(test)]
 mod tests{
     use super::*;
+    use crate::test_utils::{test_rewards, TempDir};
     #[test]
     fn test_decode(){
         let address = "3HWd4D3Li8bJbonVuNDZnxcRZygozMTriz";
         let pub_key_hash = Address::decode(address).unwrap().body;
         println!("{:?}",pub_key_hash);
     }
+    #[test]
+    fn test_print_chain_headers() -> Result<(),io::Error>{
+        let address = "3HWd4D3Li8bJbonVuNDZnxcRZygozMTriz";
+        let dir = TempDir::new();
+        let mut chain = Blockchain::create_blockchain_with(address.to_string(), dir.path(), test_rewards())?;
+        chain.mine_block(address, Vec::new())?;
+        let hashes: Vec<String> = chain.iter().map(|block| block.get_hash()).collect();
+
+        //headers only: every block, tip first, without its transactions
+        let mut out = Vec::new();
+        Cli::print_chain(&chain, true, &mut out)?;
+        let printed = String::from_utf8(out).unwrap();
+        assert_eq!(printed.lines().filter(|line| hashes.contains(&line.to_string())).collect::<Vec<_>>(), hashes);
+        assert_eq!(printed.matches("merkle_root").count(), 2);
+        assert!(!printed.contains("transactions"));
+
+        //the full chain has them
+        let mut out = Vec::new();
+        Cli::print_chain(&chain, false, &mut out)?;
+        assert_eq!(String::from_utf8(out).unwrap().matches("transactions").count(), 2);
+        Ok(())
+    }
+
     #[test]
     fn test_find_utxos() -> Result<(),io::Error>{
         let address = "3HWd4D3Li8bJbonVuNDZnxcRZygozMTriz";
         let pub_key_hash = Address::decode(address).unwrap().body;
-        let chain = Blockchain::new()?;
+        let dir = TempDir::new();
+        let chain = Blockchain::create_blockchain_with(address.to_string(), dir.path(), test_rewards())?;
         let utoxs = chain.find_utxo(pub_key_hash.clone());
         println!("{:?}",utoxs);
         Ok(())
@@ -1,15 +1,30 @@
 mod miner{
     pub mod chain;
-    mod mining;
+    pub mod difficulty;
+    pub mod mempool;
+    pub mod merkle;
+    pub mod mining;
+    pub mod pow;
+    pub mod reward;
 }
 mod wallet{
+    pub mod amount;
+    pub mod selection;
     pub mod transaction;
     pub mod tx;
+    #[allow(clippy::module_inception)]
     pub mod wallet;
 }
+mod network{
+    pub mod message;
+    pub mod node;
+    pub mod rpc;
+}
 mod command_line{
     pub mod cli;
 }
+#[cfg(test)]
+mod test_utils;
 //? use of CLI in out module
 use std::io;
 use command_line::cli::Cli;
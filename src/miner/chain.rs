@@ -1,235 +1,1894 @@
 //? used modules for the blockchain
 #[allow(unused_imports)]
-use std::{ collections::HashMap, hash::Hash, io, vec}; //for creating hash maps
-use crate::{miner::mining::Block, wallet::tx::TrancOutput, wallet::transaction::Transaction};//including creates
-use failure::format_err;//handling error
+use std::{ collections::{HashMap, HashSet}, fmt, fs, hash::Hash, io, path::{Path, PathBuf}, time::SystemTime, vec}; //for creating hash maps
+use crate::{miner::mining::{Block, BlockHeader, MinedBlock}, wallet::amount::Amount, wallet::tx::TrancOutput, wallet::selection::Coin, wallet::transaction::Transaction};//including creates
+use crate::miner::difficulty::{block_work, median_time_past, required_difficulty, MAX_FUTURE_DRIFT, MEDIAN_TIME_SPAN, RETARGET_INTERVAL};//proof of work and timestamp rules
+use crate::miner::merkle::InclusionProof;//proofs of transactions in blocks
+use crate::miner::pow::{Miner, MiningStats};//proof of work search used for new blocks
+use crate::miner::reward::RewardSchedule;//coinbase rules
 use log::info; 
 //for displaying message
 use sled;//for database
+use sled::transaction::{TransactionError as StorageTransactionError, Transactional};//writing several trees at once
 use bincode::serialize; //for serialization
 use serde_json;
+use serde::{Deserialize, Serialize};//for the undo records
 #[allow(unused_imports)]
 use bitcoincash_addr::Address;//for testing purpose
 
-//?Difficulty level of the chain POW
-static DIFFICULTY:usize = 1;
+//?Name of the sled tree holding the unspent transaction outputs
+const UTXO_TREE: &str = "utxo";
+//?Name of the sled tree indexing the UTXO set by owner, keys are "<pub key hash>:<tranc_id>:<vout index>"
+const ADDRESS_TREE: &str = "utxo_by_address";
+//?Key in the block tree recording up to which block the UTXO set is indexed
+const UTXO_TIP: &str = "UTXO_TIP";
+//?Name of the sled tree holding the spent output indexes of each transaction
+const SPENT_TREE: &str = "spent_records";
+//?Key in the block tree recording up to which block the spent records are written
+const SPENT_TIP: &str = "SPENT_TIP";
+//?Name of the sled tree holding the block hash and height of every transaction of the active chain keyed by tranc_id
+const TX_TREE: &str = "transactions";
+//?Key in the block tree recording up to which block the transaction index is written
+const TX_TIP: &str = "TX_TIP";
+//?Name of the sled tree mapping block heights (big endian) to block hashes
+const HEIGHT_TREE: &str = "heights";
+//?Name of the sled tree holding the header of every block keyed by block hash
+const HEADER_TREE: &str = "headers";
+//?Key in the block tree holding the storage format of the chain
+const FORMAT_KEY: &str = "FORMAT";
+//?Storage format written by this version, 1 since amounts are u64 base units instead of i32 coins
+const FORMAT_VERSION: u32 = 1;
+//?Key in the block tree where older versions kept the reward schedule, it now comes from the genesis block
+const REWARDS_KEY: &str = "REWARDS";
+//?Name of the sled tree holding the cumulative proof of work of every stored block keyed by block hash
+const WORK_TREE: &str = "work";
+//?Name of the sled tree ranking the stored blocks not known to be invalid, keys are the work (big endian) followed by the block hash
+const RANK_TREE: &str = "work_rank";
+//?Name of the sled tree holding why blocks were found invalid keyed by block hash
+const INVALID_TREE: &str = "invalid";
+//?Name of the sled tree holding the outputs each connected block spent keyed by block hash
+const UNDO_TREE: &str = "undo";
+//?Name of the sled tree linking every stored block to its children, keys are "<parent hash>:<block hash>"
+const CHILD_TREE: &str = "children";
+//?Name of the sled tree holding checked headers of blocks not downloaded yet keyed by block hash
+const SYNC_HEADER_TREE: &str = "sync_headers";
+//?Name of the sled tree holding downloaded blocks waiting for their parent keyed by "<parent hash>:<block hash>"
+const SYNC_BLOCK_TREE: &str = "sync_blocks";
+//?Name of the sled tree ranking the headers of blocks to download like the work ranking of the stored blocks
+const SYNC_RANK_TREE: &str = "sync_rank";
+//?Name of the sled tree mapping heights (big endian) to the blocks of the best header chain not stored yet
+const SYNC_PATH_TREE: &str = "sync_path";
 
 //?Blockchain struct to store the last has of the chain and the database address
-#[allow(dead_code)]
 //Blockchain struct storing a vector of blocks
 #[derive(Debug,Clone)]
 pub struct Blockchain{
     inst_hash:String,//hash of the last block in the chain
     db: sled::Db,//database
+    utxo: sled::Tree,//unspent outputs keyed by "<tranc_id>:<vout index>"
+    addresses: sled::Tree,//outpoints of the UTXO set grouped by the pub key hash owning them
+    spent: sled::Tree,//spent output indexes keyed by tranc_id
+    heights: sled::Tree,//block hashes keyed by height
+    transactions: sled::Tree,//block hash and height of the confirmed transactions, keyed by tranc_id
+    headers: sled::Tree,//block headers keyed by block hash
+    children: sled::Tree,//stored blocks keyed by parent and block hash, the branches without loading headers
+    work: sled::Tree,//cumulative proof of work of the branch ending at each block, keyed by block hash
+    invalid: sled::Tree,//blocks refused while connecting them and their descendants, keyed by block hash
+    ranked: sled::Tree,//valid blocks ordered by the work of their branch, the last one is the best tip
+    undo: sled::Tree,//outputs spent by each connected block, keyed by block hash
+    sync_headers: sled::Tree,//headers of blocks to download along with the work of their branch, keyed by block hash
+    sync_blocks: sled::Tree,//downloaded blocks whose parent isn't stored yet, keyed by parent and block hash
+    sync_ranked: sled::Tree,//headers of blocks to download ordered by the work of their branch, the last one is the best header
+    sync_path: sled::Tree,//blocks to download on the way to the best header, keyed by height
+    data_dir: PathBuf,//directory holding the databases of this chain
+    miner: Miner,//proof of work search used by add_block
+    rewards: RewardSchedule,//subsidy, halving and maturity rules of this chain
 }
 
+//? Details of the first block that failed the chain validation
+#[derive(Debug,Clone)]
+pub struct InvalidBlock{
+    pub height: Option<usize>,//height of the block counted from genesis, None when the chain can't be walked that far
+    pub hash: String,//hash under which the block is stored
+    pub reason: String,//why the block was rejected
+}
+
+impl fmt::Display for InvalidBlock{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result{
+        match self.height{
+            Some(height) => write!(f, "INVALID_BLOCK at height {} ({}): {}", height, self.hash, self.reason),
+            None => write!(f, "INVALID_BLOCK ({}): {}", self.hash, self.reason),
+        }
+    }
+}
+
+impl std::error::Error for InvalidBlock {}
+
+impl From<InvalidBlock> for io::Error{
+    fn from(e: InvalidBlock) -> Self{
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    }
+}
+
+//? Change of the active chain, in the order it happened
+#[derive(Debug,Clone)]
+pub enum ChainEvent{
+    Connected(Block),//added on top of the active chain
+    Disconnected(Block),//taken off the top of the active chain
+    Rejected(InvalidBlock),//failed the consensus checks while being connected, it and its descendants are never connected again
+}
+
+//? Output in the UTXO set along with what the maturity rules need to know about the transaction creating it
+#[derive(Debug,Clone,Serialize,Deserialize)]
+pub struct UnspentOutput{
+    pub output: TrancOutput,
+    pub height: usize,//height of the block confirming the transaction
+    pub coinbase: bool,//created by a coinbase, only spendable once mature
+}
+
+//? Output spent by a block, kept to put it back in the UTXO set when the block is disconnected
+#[derive(Debug,Clone,Serialize,Deserialize)]
+pub struct SpentOutput{
+    pub tranc_id: String,//transaction that created the output
+    pub vout: i32,//index of the output in that transaction
+    pub output: TrancOutput,
+    pub height: usize,//height of the block confirming that transaction
+    pub coinbase: bool,//whether that transaction is a coinbase
+}
+
+//? Changes connecting or disconnecting a block makes to the indexes of the chain, written in one transaction
+#[derive(Default)]
+struct IndexUpdate{
+    markers: sled::Batch,//tip markers in the block tree
+    utxo: sled::Batch,
+    addresses: sled::Batch,
+    spent: sled::Batch,
+    heights: sled::Batch,
+    transactions: sled::Batch,
+    undo: sled::Batch,
+}
+
+//? Last block of a stored branch
+#[derive(Debug,Clone)]
+pub struct ChainTip{
+    pub hash: String,
+    pub height: usize,
+    pub work: u128,//cumulative proof of work of the branch
+    pub active: bool,//the tip of the active chain
+    pub invalid: Option<String>,//why the branch can't be connected
+}
+
+//? Header of a block to download, kept in the sync tree until the block is stored
+#[derive(Debug,Clone,Serialize,Deserialize)]
+struct SyncedHeader{
+    header: BlockHeader,
+    work: u128,//cumulative proof of work of the branch ending at the block
+}
+
+//? Checked contents of a block on top of the tip, waiting for a proof of work found without holding the chain
+#[derive(Debug,Clone)]
+pub struct BlockTemplate{
+    pub transactions: Vec<Transaction>,//coinbase first
+    pub prev_block_hash: String,
+    pub height: usize,
+    pub difficulty: usize,
+    pub min_timestamp: u128,//median time past of the tip
+}
+
+impl BlockTemplate{
+    //* function to search a proof of work for the template, the block being handed to `Blockchain::submit_block` then */
+    pub fn mine(self, miner: &Miner) -> Result<MinedBlock,io::Error>{
+        Block::mine(self.transactions, self.prev_block_hash, self.height, self.difficulty, self.min_timestamp, miner)
+    }
+}
+
+//? Why a transaction was refused by the consensus checks, naming the transaction and the offending input
+#[derive(Debug,Clone,PartialEq)]
+pub enum TransactionError{
+    IdMismatch{ tranc_id: String },//stored id doesn't match the content
+    InvalidSignature{ tranc_id: String },//an input isn't signed by the owner of the output it spends
+    VerificationFailed{ tranc_id: String, reason: String },//the transaction couldn't be checked at all
+    MissingOutput{ tranc_id: String, input: usize, outpoint: String },//spends an output that doesn't exist
+    DoubleSpend{ tranc_id: String, input: usize, outpoint: String },//spends an output already spent in the chain
+    ConflictingSpend{ tranc_id: String, input: usize, outpoint: String, spent_by: String },//spends an output already spent in the same block
+    InputsBelowOutputs{ tranc_id: String, inputs: Amount, outputs: Amount },//pays more than it spends
+    ZeroOutput{ tranc_id: String, output: usize },//creates an output holding nothing
+    ValueOverflow{ tranc_id: String },//its inputs or outputs add up to more than an amount can hold
+    MissingCoinbase{ tranc_id: String },//the first transaction of the block isn't a coinbase (empty id for an empty block)
+    MisplacedCoinbase{ tranc_id: String, position: usize },//a coinbase after the first transaction
+    CoinbaseNotAllowed{ tranc_id: String },//a coinbase submitted outside of a block
+    ExcessiveReward{ tranc_id: String, paid: Amount, allowed: Amount },//the coinbase pays more than subsidy plus fees
+    ImmatureCoinbase{ tranc_id: String, input: usize, outpoint: String, coinbase_height: usize },//spends a coinbase too young
+}
+
+impl TransactionError{
+    //* function to get the id of the refused transaction */
+    pub fn tranc_id(&self) -> &str{
+        match self{
+            TransactionError::IdMismatch{ tranc_id }
+            | TransactionError::InvalidSignature{ tranc_id }
+            | TransactionError::VerificationFailed{ tranc_id, .. }
+            | TransactionError::MissingOutput{ tranc_id, .. }
+            | TransactionError::DoubleSpend{ tranc_id, .. }
+            | TransactionError::ConflictingSpend{ tranc_id, .. }
+            | TransactionError::InputsBelowOutputs{ tranc_id, .. }
+            | TransactionError::ZeroOutput{ tranc_id, .. }
+            | TransactionError::ValueOverflow{ tranc_id }
+            | TransactionError::MissingCoinbase{ tranc_id }
+            | TransactionError::MisplacedCoinbase{ tranc_id, .. }
+            | TransactionError::CoinbaseNotAllowed{ tranc_id }
+            | TransactionError::ExcessiveReward{ tranc_id, .. }
+            | TransactionError::ImmatureCoinbase{ tranc_id, .. } => tranc_id,
+        }
+    }
+}
+
+impl fmt::Display for TransactionError{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result{
+        match self{
+            TransactionError::IdMismatch{ tranc_id } => write!(f, "TRANSACTION_ID_MISMATCH: {}", tranc_id),
+            TransactionError::InvalidSignature{ tranc_id } => write!(f, "INVALID_SIGNATURE: {}", tranc_id),
+            TransactionError::VerificationFailed{ tranc_id, reason } => write!(f, "VERIFICATION_ERROR: {}: {}", tranc_id, reason),
+            TransactionError::MissingOutput{ tranc_id, input, outpoint } =>
+                write!(f, "MISSING_OUTPUT: input {} of {} spends {}, which doesn't exist", input, tranc_id, outpoint),
+            TransactionError::DoubleSpend{ tranc_id, input, outpoint } =>
+                write!(f, "DOUBLE_SPEND: input {} of {} spends {}, which is already spent", input, tranc_id, outpoint),
+            TransactionError::ConflictingSpend{ tranc_id, input, outpoint, spent_by } =>
+                write!(f, "DOUBLE_SPEND: input {} of {} spends {}, also spent by {} in the same block", input, tranc_id, outpoint, spent_by),
+            TransactionError::InputsBelowOutputs{ tranc_id, inputs, outputs } =>
+                write!(f, "INPUTS_BELOW_OUTPUTS: {} spends {} but pays {}", tranc_id, inputs, outputs),
+            TransactionError::ZeroOutput{ tranc_id, output } => write!(f, "ZERO_OUTPUT: output {} of {} holds nothing", output, tranc_id),
+            TransactionError::ValueOverflow{ tranc_id } => write!(f, "VALUE_OVERFLOW: the values of {} don't fit an amount", tranc_id),
+            TransactionError::MissingCoinbase{ tranc_id } => write!(f, "MISSING_COINBASE: first transaction '{}' isn't a coinbase", tranc_id),
+            TransactionError::MisplacedCoinbase{ tranc_id, position } =>
+                write!(f, "MISPLACED_COINBASE: {} is a coinbase at position {}", tranc_id, position),
+            TransactionError::CoinbaseNotAllowed{ tranc_id } => write!(f, "COINBASE_NOT_ALLOWED: {} is a coinbase, only a block can create one", tranc_id),
+            TransactionError::ExcessiveReward{ tranc_id, paid, allowed } =>
+                write!(f, "EXCESSIVE_REWARD: coinbase {} pays {} but the block allows {}", tranc_id, paid, allowed),
+            TransactionError::ImmatureCoinbase{ tranc_id, input, outpoint, coinbase_height } =>
+                write!(f, "IMMATURE_COINBASE: input {} of {} spends {}, a coinbase from height {} that isn't spendable yet", input, tranc_id, outpoint, coinbase_height),
+        }
+    }
+}
+
+impl std::error::Error for TransactionError {}
+
+impl From<TransactionError> for io::Error{
+    fn from(e: TransactionError) -> Self{
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    }
+}
+
+//? State of an output confirmed before the block being checked
+enum OutputState{
+    Unspent(UnspentOutput),
+    Spent,
+    Missing,
+}
+
+
 //?Blockchain interator struct for interating the blockchain
 pub struct BlockchainIterator<'a>{
     inst_hash:String,//store the hash of the last interating blockchain
     blockchain: &'a Blockchain,//stores the blockchain with lifetime operator
 }
 
-#[allow(dead_code)]
+//?Iterator over the (hash, header) pairs of the chain from the tip down, without loading transactions
+pub struct HeaderIterator<'a>{
+    inst_hash:String,//hash of the next header to return
+    blockchain: &'a Blockchain,//stores the blockchain with lifetime operator
+}
+
 //? implementing the Blockchain struct
 impl Blockchain {
     //* function to create a new blockchain starting from a default block */
-    pub fn new() -> Result<Self,io::Error>{
-        info!("Opening blockchain...");//message
+    /// data_dir: directory holding the databases of the chain
+    pub fn new(data_dir: &Path) -> Result<Self,io::Error>{
+        info!("Opening blockchain in {}...", data_dir.display());//message
         //opening database
-        let db = sled::open("data/blocks")?;
+        Blockchain::open(sled::open(data_dir.join("blocks"))?, data_dir)
+    }
+
+    //* function to load a blockchain from its open block database, bringing its indexes up to date */
+    fn open(db: sled::Db, data_dir: &Path) -> Result<Self,io::Error>{
         //getting the LAST hash
-        let hash = db.get("LAST").expect("Must create a new block database first").unwrap_or_else(||{
-            todo!("LAST_HASH_ERR");
-        });
+        let hash = db.get("LAST")?.ok_or_else(|| io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("No blockchain found in {}, create one first", data_dir.display()),
+        ))?;
         info!("Found block database!");//message
+        Self::check_format(&db, data_dir)?;//refusing chains this version can't read
         let lasthash = String::from_utf8(hash.to_vec()).expect("Can't get last hash!");
-        Ok(Blockchain { inst_hash: lasthash.clone(), db })//created a new blockchain
+        let rewards = Blockchain::genesis_rewards(&db, &lasthash)?;//rules the chain was created with
+        let mut blockchain = Blockchain::from_db(db, lasthash, data_dir, rewards)?;//created a new blockchain
+        let mut rebuilt = false;//indexes rebuilt from the blocks, which are validated then
+        //databases written before headers had their own tree get it filled from the blocks
+        if !blockchain.headers.contains_key(&blockchain.inst_hash)?{
+            info!("Header tree is out of date, rebuilding...");//message
+            blockchain.reindex_headers()?;
+            rebuilt = true;
+        }
+        //databases written before the blocks were linked to their children get the links of every stored header
+        if blockchain.children.is_empty(){
+            info!("Child links are out of date, rebuilding...");//message
+            blockchain.reindex_children()?;
+        }
+        //databases written before forks were stored get the work of the active chain
+        if !blockchain.work.contains_key(&blockchain.inst_hash)?{
+            info!("Work index is out of date, rebuilding...");//message
+            blockchain.reindex_work()?;
+            rebuilt = true;
+        }
+        //databases written before the blocks were ranked by work get the ranking of every stored block
+        if blockchain.ranked.is_empty() && !blockchain.work.is_empty(){
+            info!("Work ranking is out of date, rebuilding...");//message
+            blockchain.rerank_blocks()?;
+        }
+        //the same for the headers of blocks to download
+        if blockchain.sync_ranked.is_empty() && !blockchain.sync_headers.is_empty(){
+            info!("Header download ranking is out of date, rebuilding...");//message
+            blockchain.rerank_synced_headers()?;
+        }
+        //databases written before the address index get it built along with the UTXO set
+        let unindexed = blockchain.addresses.is_empty() && !blockchain.utxo.is_empty();
+        let utxo_stale = unindexed || blockchain.db.get(UTXO_TIP)?.as_deref() != Some(blockchain.inst_hash.as_bytes());
+        let spent_stale = blockchain.db.get(SPENT_TIP)?.as_deref() != Some(blockchain.inst_hash.as_bytes());
+        let tx_stale = blockchain.db.get(TX_TIP)?.as_deref() != Some(blockchain.inst_hash.as_bytes());
+        //blocks are checked when connected, the whole chain only before indexes are rebuilt from it
+        if rebuilt || utxo_stale || spent_stale || tx_stale{
+            blockchain.validate_chain()?;//refusing to rebuild from a corrupted chain
+            info!("Blockchain validated!");//message
+        }
+
+        //rebuilding the UTXO set if it doesn't match the tip (older database or interrupted write)
+        if utxo_stale{
+            info!("UTXO set is out of date, reindexing...");//message
+            blockchain.reindex_utxo()?;
+        }
+        //rebuilding the spent records the same way (older databases kept them in data/spent_records)
+        if spent_stale{
+            info!("Spent records are out of date, rebuilding...");//message
+            blockchain.rebuild_spent_records()?;
+        }
+        //databases written before transactions were indexed get the index of the active chain
+        if tx_stale{
+            info!("Transaction index is out of date, rebuilding...");//message
+            blockchain.reindex_transactions()?;
+        }
+        //rebuilding the height index if the tip isn't in it
+        let tip_height = blockchain.get_best_height()?;
+        if blockchain.heights.get(Self::height_key(tip_height))?.as_deref() != Some(blockchain.inst_hash.as_bytes()){
+            info!("Height index is out of date, rebuilding...");//message
+            blockchain.reindex_heights()?;
+        }
+        Ok(blockchain)
     }
 
-    //* function the blockchain startingwith a default block*/
-    pub fn create_blockchain(address: String) -> Result<Self,io::Error>{
-        info!("Creating blockchain");//message
-        //opening database
-        let db = sled::open("data/blocks")?;
-        info!("Creating new block in database...");//message
+    //* function to open the index trees next to the block tree */
+    /// rewards: reward rules of the chain, read from its genesis block
+    fn from_db(db: sled::Db, inst_hash: String, data_dir: &Path, rewards: RewardSchedule) -> Result<Self,io::Error>{
+        let utxo = db.open_tree(UTXO_TREE)?;//opening the UTXO set
+        let addresses = db.open_tree(ADDRESS_TREE)?;//opening the owner index of the UTXO set
+        let spent = db.open_tree(SPENT_TREE)?;//opening the spent records
+        let heights = db.open_tree(HEIGHT_TREE)?;//opening the height index
+        let transactions = db.open_tree(TX_TREE)?;//opening the transaction index
+        let headers = db.open_tree(HEADER_TREE)?;//opening the header tree
+        let children = db.open_tree(CHILD_TREE)?;//opening the links from parents to children
+        let work = db.open_tree(WORK_TREE)?;//opening the cumulative work of the blocks
+        let invalid = db.open_tree(INVALID_TREE)?;//opening the refused blocks
+        let ranked = db.open_tree(RANK_TREE)?;//opening the blocks ordered by work
+        let undo = db.open_tree(UNDO_TREE)?;//opening the undo records
+        let sync_headers = db.open_tree(SYNC_HEADER_TREE)?;//opening the headers of blocks to download
+        let sync_blocks = db.open_tree(SYNC_BLOCK_TREE)?;//opening the downloaded blocks waiting for their parent
+        let sync_ranked = db.open_tree(SYNC_RANK_TREE)?;//opening the headers to download ordered by work
+        let sync_path = db.open_tree(SYNC_PATH_TREE)?;//opening the blocks to download next
+        Ok(Blockchain{ inst_hash, db, utxo, addresses, spent, heights, transactions, headers, children, work, invalid, ranked, undo, sync_headers, sync_blocks, sync_ranked, sync_path, data_dir: data_dir.to_path_buf(), miner: Miner::default(), rewards })
+    }
+
+    //* function to check that a block database was written in the storage format of this version */
+    /// Databases without a format key come from versions storing amounts as i32 coins. Converting their outputs
+    /// would change every transaction id, signature and block hash, so they can't be migrated.
+    fn check_format(db: &sled::Db, data_dir: &Path) -> Result<(),io::Error>{
+        let format = match db.get(FORMAT_KEY)?{
+            Some(data) => u32::from_be_bytes(data.as_ref().try_into().map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "INVALID_FORMAT: can't read the storage format"))?),
+            None => 0,
+        };
+        if format != FORMAT_VERSION{
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!(
+                "UNSUPPORTED_FORMAT: the chain in {} has storage format {} but this version reads format {}, create it again",
+                data_dir.display(), format, FORMAT_VERSION,
+            )));
+        }
+        Ok(())
+    }
 
+    //* function to read the reward rules of a chain from the genesis block below a tip */
+    fn genesis_rewards(db: &sled::Db, inst_hash: &str) -> Result<RewardSchedule,io::Error>{
+        let genesis = Self::find_genesis(db, inst_hash)?;
+        let coinbase = genesis.get_transaction().into_iter().next().filter(Transaction::is_coinbase).ok_or_else(|| io::Error::new(
+            io::ErrorKind::InvalidData, format!("MISSING_COINBASE: genesis block {} doesn't start with a coinbase", genesis.get_hash()),
+        ))?;
+        let rewards = RewardSchedule::from_genesis_data(&coinbase.vin[0].pub_key)?;
+        //chains of older versions kept the schedule under a key of their own, it has to agree with the genesis block
+        if let Some(data) = db.get(REWARDS_KEY)?{
+            let stored = bincode::deserialize::<RewardSchedule>(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+            if stored != rewards{
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!(
+                    "REWARDS_MISMATCH: the chain stores {:?} but its genesis block {} commits to {:?}, create it again", stored, genesis.get_hash(), rewards,
+                )));
+            }
+            db.remove(REWARDS_KEY)?;
+        }
+        Ok(rewards)
+    }
+
+    //* function to find the genesis block through the height index, or by walking back from the tip before it exists */
+    fn find_genesis(db: &sled::Db, inst_hash: &str) -> Result<Block,io::Error>{
+        let decode = |data: &[u8]| bincode::deserialize::<Block>(data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()));
+        let missing = |hash: &str| io::Error::new(io::ErrorKind::NotFound, format!("MISSING_BLOCK: block {} isn't in the database", hash));
+        if let Some(hash) = db.open_tree(HEIGHT_TREE)?.get(Self::height_key(0))?{
+            let hash = String::from_utf8_lossy(&hash).to_string();
+            return decode(&db.get(&hash)?.ok_or_else(|| missing(&hash))?);
+        }
+        let mut hash = inst_hash.to_string();
+        loop{
+            let block = decode(&db.get(&hash)?.ok_or_else(|| missing(&hash))?)?;
+            if block.get_previus_hash().is_empty(){
+                return Ok(block);
+            }
+            hash = block.get_previus_hash();
+        }
+    }
+
+    //* function the blockchain startingwith a default block and its own reward schedule */
+    /// rewards: subsidy, halving and maturity rules written into the genesis coinbase
+    pub fn create_blockchain_with(address: String, data_dir: &Path, rewards: RewardSchedule) -> Result<Self,io::Error>{
         //settin up a coinbase transaction
-        let coinbase = Transaction::new_coinbase(address, String::from("Default coinbase")).expect("Can't create coinbase while generating new blockchain");
+        let coinbase = Transaction::new_reward(address, rewards.genesis_data("Default coinbase"), rewards.subsidy(0))?;
         let default_block = Block::default(coinbase); //passing coinbase 
-        
+        Blockchain::create_from_genesis(default_block, data_dir)
+    }
+
+    //* function to start a blockchain from a genesis block taken from another chain, to join its network under the same rules */
+    pub fn create_from_genesis(default_block: Block, data_dir: &Path) -> Result<Self,io::Error>{
+        if default_block.get_height() != 0 || !default_block.get_previus_hash().is_empty(){
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("NOT_GENESIS: block {} has a parent", default_block.get_hash())));
+        }
+        info!("Creating blockchain in {}", data_dir.display());//message
+        //opening database
+        let db = sled::open(data_dir.join("blocks"))?;
+        info!("Creating new block in database...");//message
+        db.insert(FORMAT_KEY, &FORMAT_VERSION.to_be_bytes())?;//storage format of the chain
+
         //inserting block in database
         db.insert(default_block.get_hash(), bincode::serialize(&default_block).expect("Can't insert new blockchain to database"))?;
         db.insert("LAST", default_block.get_hash().as_bytes())?;//setting LAST hash
         //creating new blockcain struct
-        let blockchain = Blockchain{inst_hash:default_block.get_hash(),db:db};
+        let rewards = Blockchain::genesis_rewards(&db, &default_block.get_hash())?;//rules the genesis block commits to
+        let mut blockchain = Blockchain::from_db(db, default_block.get_hash(), data_dir, rewards)?;
+        blockchain.store_header(&default_block)?;//header of the genesis block
+        blockchain.reindex_work()?;//work of the genesis block
+        blockchain.reindex_utxo()?;//indexing the outputs of the genesis block
+        blockchain.rebuild_spent_records()?;//starting with empty spent records
+        blockchain.reindex_transactions()?;//the genesis coinbase
+        blockchain.reindex_heights()?;//genesis at height 0
         //flushing the database
         let _result = blockchain.db.flush();
         Ok(blockchain)
     }
 
-    //* function to add block into the blockchain */ 
-    pub fn add_block(&mut self, transaction:Vec<Transaction>)->Result<(),io::Error>{
+    //* function to choose the miner used for the next blocks */
+    pub fn set_miner(&mut self, miner: Miner){
+        self.miner = miner;
+    }
+
+    //* function to mine a block of transactions behind a coinbase paying the subsidy and their fees to an address */
+    /// to: address of the miner
+    pub fn mine_block(&mut self, to: &str, transactions: Vec<Transaction>) -> Result<MiningStats,io::Error>{
+        let block = self.with_coinbase(to, self.get_best_height()? + 1, transactions)?;
+        self.add_block(block)
+    }
+
+    //* function to prepare a block of transactions on top of the tip, behind a coinbase paying the subsidy and their fees to an address */
+    /// Fails like `add_block` when the transactions break the consensus rules.
+    pub fn block_template(&self, to: &str, transactions: Vec<Transaction>) -> Result<BlockTemplate,io::Error>{
+        let height = self.get_best_height()? + 1;
+        let transactions = self.with_coinbase(to, height, transactions)?;
+        self.check_transactions(height, &transactions, |tranc_id, vout| self.output_state(tranc_id, vout))?;
+        let recent = self.next_recent_headers()?;
+        Ok(BlockTemplate{ transactions, prev_block_hash: self.inst_hash.clone(), height, difficulty: required_difficulty(height, &recent), min_timestamp: median_time_past(&recent) })
+    }
+
+    //* function to put the coinbase of a block at a height in front of its transactions */
+    fn with_coinbase(&self, to: &str, height: usize, transactions: Vec<Transaction>) -> Result<Vec<Transaction>,io::Error>{
+        let fees = self.fees_of(&transactions)?;
+        let reward = self.rewards.subsidy(height).checked_add(fees)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("REWARD_OVERFLOW: fees of {}", fees)))?;
+        let coinbase = Transaction::new_reward(to.to_string(), format!("Reward to {} at height {}", to, height), reward)?;
+        let mut block = vec![coinbase];
+        block.extend(transactions);
+        Ok(block)
+    }
+
+    //* function to sum the fees of transactions meant for the next block, in block order */
+    /// Inputs are looked up in the UTXO set or among the outputs of the earlier transactions;
+    /// unknown inputs count as zero and transactions paying more than they spend as paying no fee,
+    /// both are refused later by `check_transactions`.
+    pub fn fees_of(&self, transactions: &[Transaction]) -> Result<Amount,io::Error>{
+        let overflow = || io::Error::new(io::ErrorKind::InvalidInput, "VALUE_OVERFLOW: fees don't fit an amount");
+        let mut created: HashMap<String,Amount> = HashMap::new();//outputs of the earlier transactions
+        let mut fees = Amount::ZERO;
+        for tx in transactions{
+            if !tx.is_coinbase(){
+                let mut inputs = Amount::ZERO;
+                for vin in &tx.vin{
+                    let outpoint = Self::utxo_key(&vin.from, vin.vout);
+                    let value = match created.get(&outpoint){
+                        Some(value) => *value,
+                        None => match self.output_state(&vin.from, vin.vout)?{
+                            OutputState::Unspent(unspent) => unspent.output.value,
+                            _ => Amount::ZERO,
+                        },
+                    };
+                    inputs = inputs.checked_add(value).ok_or_else(overflow)?;
+                }
+                let outputs = Amount::checked_sum(tx.vout.iter().map(|out| out.value)).ok_or_else(overflow)?;
+                fees = fees.checked_add(inputs.saturating_sub(outputs)).ok_or_else(overflow)?;
+            }
+            for (index, out) in tx.vout.iter().enumerate(){
+                created.insert(Self::utxo_key(&tx.tranc_id, index as i32), out.value);
+            }
+        }
+        Ok(fees)
+    }
+
+    //* function to add block into the blockchain, returning the statistics of its proof of work */
+    /// Fails with `ErrorKind::Interrupted` when the miner is stopped first, nothing being added then.
+    pub fn add_block(&mut self, transaction:Vec<Transaction>)->Result<MiningStats,io::Error>{
         let lasthash = self.db.get("LAST")?.unwrap(); //getting the last hash from the db
+        let height = self.get_best_height()? + 1; //the new block goes on top of the tip
+        //refusing blocks with transactions that break the consensus rules
+        self.check_transactions(height, &transaction, |tranc_id, vout| self.output_state(tranc_id, vout))?;
+        let recent = self.next_recent_headers()?;
+        let difficulty = required_difficulty(height, &recent); //difficulty the chain requires for the new block
 
         //creating new block using the given transactions
-        let new_block: Block= Block::new(transaction, String::from_utf8(lasthash.to_vec()).expect("VECTOR_ERROR"), DIFFICULTY).unwrap_or_else(|e|{
-            todo!("BLOCK_CREATION_ERROR:{}",e);//error handling in case block can'tbe created
-        });
-        self.db.insert(new_block.get_hash(), serialize(&new_block).unwrap_or_else(|e|
-            {
-                todo!("BEFORE_DATABASE_SERIALIZATION_ERROR:{}",e);//handling error during data serialization
+        let (new_block, stats) = match Block::mine(transaction, String::from_utf8(lasthash.to_vec()).expect("VECTOR_ERROR"), height, difficulty, median_time_past(&recent), &self.miner)?{
+            MinedBlock::Mined(block, stats) => (block, stats),
+            MinedBlock::Stopped(reason, stats) => return Err(io::Error::new(io::ErrorKind::Interrupted, format!("MINING_STOPPED: {} after {}", reason, stats))),
+        };
+        self.store_block(&new_block)?;//block, header and work
+        self.apply_block(&new_block)?;//indexes and tip
+        Ok(stats)
+    }
+
+    //* function to store a block, its header and the cumulative work of its branch, without connecting it */
+    fn store_block(&self, block: &Block) -> Result<(),io::Error>{
+        let parent_work: u128 = self.get_work(&block.get_previus_hash())?.unwrap_or_default();//nothing below genesis
+        self.db.insert(block.get_hash(), serialize(block).map_err(|e| io::Error::other(format!("BEFORE_DATABASE_SERIALIZATION_ERROR:{}", e)))?)?;
+        self.store_header(block)?;//header available without the transactions
+        let work = parent_work.saturating_add(block_work(block.get_difficulty()));
+        self.work.insert(block.get_hash().as_bytes(), &work.to_be_bytes())?;
+        self.ranked.insert(Self::rank_key(work, &block.get_hash()), &[])?;//a candidate tip until found invalid
+        self.forget_synced_header(&block.get_hash())?;//downloaded
+        Ok(())
+    }
+
+    //* function to update the indexes for a checked block on top of the tip and make it the tip */
+    fn apply_block(&mut self, block: &Block) -> Result<(),io::Error>{
+        let undo = self.spent_outputs(block, |tranc_id, vout| match self.output_state(tranc_id, vout)?{
+            OutputState::Unspent(unspent) => Ok(unspent),
+            _ => Err(io::Error::new(io::ErrorKind::NotFound, format!("MISSING_OUTPUT: {}", Self::utxo_key(tranc_id, vout)))),
+        })?;
+        let mut update = IndexUpdate::default();
+        update.undo.insert(block.get_hash().as_bytes(), serialize(&undo).map_err(|e| io::Error::other(e.to_string()))?);//outputs to restore on disconnect
+        self.utxo_changes(block, &mut update.utxo, &mut update.addresses)?;//spending inputs and adding outputs to the UTXO set
+        update.spent = self.spent_changes(block, true)?;//the only place spent records are written
+        update.heights.insert(&Self::height_key(block.get_height()), block.get_hash().as_bytes());//height index
+        Self::transaction_changes(block, &mut update.transactions, true)?;//transaction index
+        self.commit(update, &block.get_hash())
+    }
+
+    //* function to write the index changes of a block along with the markers of the new tip in one transaction, then move the tip */
+    /// A crash or an I/O error leaves the indexes either before or after the block, never in between.
+    fn commit(&mut self, mut update: IndexUpdate, tip: &str) -> Result<(),io::Error>{
+        for key in ["LAST", UTXO_TIP, SPENT_TIP, TX_TIP]{//the UTXO set, spent records and transaction index match the tip along with it
+            update.markers.insert(key, tip.as_bytes());
+        }
+        (&*self.db, &self.utxo, &self.addresses, &self.spent, &self.heights, &self.transactions, &self.undo)
+            .transaction(|(markers, utxo, addresses, spent, heights, transactions, undo)|{
+                markers.apply_batch(&update.markers)?;
+                utxo.apply_batch(&update.utxo)?;
+                addresses.apply_batch(&update.addresses)?;
+                spent.apply_batch(&update.spent)?;
+                heights.apply_batch(&update.heights)?;
+                transactions.apply_batch(&update.transactions)?;
+                undo.apply_batch(&update.undo)?;
+                Ok(())
+            })
+            .map_err(|e: StorageTransactionError<()>| match e{
+                StorageTransactionError::Abort(()) => io::Error::other("TRANSACTION_ABORTED"),
+                StorageTransactionError::Storage(e) => e.into(),
+            })?;
+        self.inst_hash = tip.to_string();//moving the tip of this instance
+        self.update_sync_path()//the tip may have passed the best header
+    }
+
+    //* function to add a block mined elsewhere on top of any stored block, returning how the active chain changed */
+    /// The header has to link to a stored parent and meet the difficulty of its branch; the block is stored then,
+    /// and the branch with the most cumulative work becomes the active chain, keeping the current one on ties.
+    /// Transactions are only checked when their block is connected; a block failing then is reported by a
+    /// `ChainEvent::Rejected` and the best remaining branch is connected instead.
+    /// A block whose header came through `submit_headers` is kept until its parent is stored instead.
+    /// Submitting a stored block again changes nothing, a rolled back branch only comes back through `reconsider_block`.
+    /// Fails with `ErrorKind::NotFound` when the parent isn't stored, and on blocks already known to be invalid.
+    pub fn submit_block(&mut self, block: Block) -> Result<Vec<ChainEvent>,io::Error>{
+        let hash = block.get_hash();
+        if let Some(reason) = self.invalid.get(&hash)?{
+            return Err(InvalidBlock{ height: Some(block.get_height()), hash, reason: format!("KNOWN_INVALID: {}", String::from_utf8_lossy(&reason)) }.into());
+        }
+        if self.headers.contains_key(&hash)?{
+            let events = self.activate_best_chain()?;//nothing unless a rollback left a heavier branch
+            self.db.flush()?;
+            return Ok(events);
+        }
+        if self.has_block(&hash)?{
+            return Ok(Vec::new());//waiting for its parent
+        }
+        let parent = match self.get_header_by_hash(&block.get_previus_hash())?{
+            Some(parent) => parent,
+            None => return self.hold_block(block),
+        };
+        self.check_block_header(&block, &parent)?;
+        self.store_block(&block)?;
+        if let Some(reason) = self.invalid.get(block.get_previus_hash())?{
+            let reason = format!("INVALID_PARENT: {}", String::from_utf8_lossy(&reason));
+            self.mark_invalid(&hash, &reason)?;
+            return Err(InvalidBlock{ height: Some(block.get_height()), hash, reason }.into());
+        }
+        self.store_held_blocks(&hash)?;
+        let events = self.activate_best_chain()?;
+        if events.iter().any(|event| matches!(event, ChainEvent::Connected(_))){
+            self.prune_synced_headers()?;
+        }
+        self.db.flush()?;
+        Ok(events)
+    }
+
+    //* function to check a block against its stored parent: link, height, difficulty of the branch, proof of work and merkle root */
+    fn check_block_header(&self, block: &Block, parent: &BlockHeader) -> Result<(),InvalidBlock>{
+        self.check_header(block.get_header(), &block.get_hash(), parent)?;
+        if !block.has_valid_merkle_root(){
+            return Err(InvalidBlock{ height: Some(block.get_height()), hash: block.get_hash(), reason: String::from("MERKLE_ROOT_MISMATCH") });
+        }
+        Ok(())
+    }
+
+    //* function to check a header against its parent, stored or synced: link, height, timestamp, difficulty of the branch and proof of work */
+    /// hash: hash the header is known by
+    fn check_header(&self, header: &BlockHeader, hash: &str, parent: &BlockHeader) -> Result<(),InvalidBlock>{
+        let fail = |reason: String| InvalidBlock{ height: Some(header.height), hash: hash.to_string(), reason };
+        if header.height != parent.height + 1{
+            return Err(fail(format!("HEIGHT_MISMATCH: parent at height {}, found {}", parent.height, header.height)));
+        }
+        let recent = self.branch_headers(&header.prev_block_hash, RETARGET_INTERVAL.max(MEDIAN_TIME_SPAN)).map_err(|e| fail(format!("DATABASE_ERROR: {}", e)))?;
+        let earliest = median_time_past(&recent);
+        if header.timestamp < earliest{
+            return Err(fail(format!("TIME_TOO_OLD: {} is before the median time past {}", header.timestamp, earliest)));
+        }
+        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).map_err(|e| fail(format!("CLOCK_ERROR: {}", e)))?.as_millis();
+        if header.timestamp > now.saturating_add(MAX_FUTURE_DRIFT){
+            return Err(fail(format!("TIME_TOO_NEW: {} is more than {}ms ahead of {}", header.timestamp, MAX_FUTURE_DRIFT, now)));
+        }
+        let required = required_difficulty(header.height, &recent);
+        if header.bits != required{
+            return Err(fail(format!("DIFFICULTY_MISMATCH: required {}, found {}", required, header.bits)));
+        }
+        match header.hash(){
+            Ok(computed) if computed != hash => return Err(fail(format!("HASH_MISMATCH: content hashes to {}", computed))),
+            Ok(_) => (),
+            Err(e) => return Err(fail(format!("HASHING_ERROR: {}", e))),
+        }
+        match header.meets_difficulty(){
+            Ok(true) => (),
+            Ok(false) => return Err(fail(String::from("PROOF_OF_WORK_NOT_MET"))),
+            Err(e) => return Err(fail(format!("HASHING_ERROR: {}", e))),
+        }
+        Ok(())
+    }
+
+    //* function to get up to `count` headers of the branch ending at a block, genesis first */
+    fn branch_headers(&self, hash: &str, count: usize) -> Result<Vec<BlockHeader>,io::Error>{
+        let mut headers = Vec::new();
+        let mut hash = hash.to_string();
+        while headers.len() < count && !hash.is_empty(){
+            let header = self.find_header(&hash)?.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("MISSING_HEADER: {}", hash)))?;
+            hash = header.prev_block_hash.clone();
+            headers.push(header);
+        }
+        headers.reverse();
+        Ok(headers)
+    }
+
+    //* function to get the cumulative proof of work of the branch ending at a stored block */
+    pub fn get_work(&self, hash: &str) -> Result<Option<u128>,io::Error>{
+        match self.work.get(hash)?{
+            Some(data) => <[u8; 16]>::try_from(data.as_ref())
+                .map(|bytes| Some(u128::from_be_bytes(bytes)))
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("Bad work record for {}", hash))),
+            None => Ok(None),
+        }
+    }
+
+    //* function to rebuild the work of the blocks of the active chain from their headers */
+    pub fn reindex_work(&self) -> Result<(),io::Error>{
+        let mut headers: Vec<(String,BlockHeader)> = self.iter_headers().collect();
+        headers.reverse();//genesis first
+        let mut work: u128 = 0;
+        for (hash, header) in headers{
+            work = work.saturating_add(block_work(header.bits));
+            self.work.insert(hash.as_bytes(), &work.to_be_bytes())?;
+            self.ranked.insert(Self::rank_key(work, &hash), &[])?;
+        }
+        self.work.flush()?;
+        Ok(())
+    }
+
+    //* function to rank every stored block not known to be invalid by the work of its branch */
+    fn rerank_blocks(&self) -> Result<(),io::Error>{
+        self.ranked.clear()?;
+        for entry in self.work.iter(){
+            let (key, _) = entry?;
+            let hash = String::from_utf8_lossy(&key).to_string();
+            if !self.invalid.contains_key(&key)?{
+                self.ranked.insert(Self::rank_key(self.get_work(&hash)?.unwrap_or(0), &hash), &[])?;
             }
-        ))?;
-        self.db.insert("LAST", new_block.get_hash().as_bytes())?;// setting the LAST key
+        }
+        self.ranked.flush()?;
+        Ok(())
+    }
+
+    //* function to build the key of a block in the work ranking */
+    fn rank_key(work: u128, hash: &str) -> Vec<u8>{
+        let mut key = work.to_be_bytes().to_vec();//big endian keeps the ranking ordered by work
+        key.extend_from_slice(hash.as_bytes());
+        key
+    }
+
+    //* function to take a block out of the work ranking */
+    fn unrank(&self, hash: &str) -> Result<(),io::Error>{
+        if let Some(work) = self.get_work(hash)?{
+            self.ranked.remove(Self::rank_key(work, hash))?;
+        }
+        Ok(())
+    }
+
+    //* function to find the stored block with the most cumulative work that isn't known to be invalid */
+    /// Ties keep the active tip, otherwise the lowest hash with the most work wins.
+    fn best_tip(&self) -> Result<String,io::Error>{
+        let Some((last, _)) = self.ranked.last()? else{
+            return Ok(String::new());//every block is invalid
+        };
+        let most = &last[..16];//work of the heaviest branches
+        if let Some(work) = self.get_work(&self.inst_hash)?{
+            if self.ranked.contains_key(Self::rank_key(work, &self.inst_hash))? && work.to_be_bytes().as_slice() >= most{
+                return Ok(self.inst_hash.clone());
+            }
+        }
+        let (first, _) = self.ranked.scan_prefix(most).next().expect("the last key has this prefix")?;
+        Ok(String::from_utf8_lossy(&first[16..]).to_string())
+    }
+
+    //* function to list the last block of every stored branch and the tip of the active chain, which comes first */
+    /// Walks the links from parents to children, loading only the headers of the tips.
+    pub fn tips(&self) -> Result<Vec<ChainTip>,io::Error>{
+        let mut tips = Vec::new();
+        for key in self.children.iter().keys(){
+            let key = key?;
+            let hash = String::from_utf8_lossy(&key).split_once(':').map(|(_, hash)| hash.to_string()).unwrap_or_default();
+            //the active tip even when refused blocks were built on it
+            if hash != self.inst_hash && self.children_of(&hash).next().is_some(){
+                continue;
+            }
+            let header = self.get_header_by_hash(&hash)?.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("MISSING_HEADER: {}", hash)))?;
+            tips.push(ChainTip{
+                active: hash == self.inst_hash,
+                height: header.height,
+                work: self.get_work(&hash)?.unwrap_or(0),
+                invalid: self.invalid.get(&hash)?.map(|reason| String::from_utf8_lossy(&reason).to_string()),
+                hash,
+            });
+        }
+        tips.sort_by(|a, b| b.active.cmp(&a.active).then(b.work.cmp(&a.work)).then(a.hash.cmp(&b.hash)));
+        Ok(tips)
+    }
+
+    //* function to record a block and every stored descendant as invalid */
+    fn mark_invalid(&self, hash: &str, reason: &str) -> Result<(),io::Error>{
+        for hash in [hash.to_string()].into_iter().chain(self.descendants(hash)?){
+            self.invalid.insert(hash.as_bytes(), reason.as_bytes())?;
+            self.unrank(&hash)?;//never a tip again
+            self.forget_synced_header(&hash)?;//nor downloaded
+        }
+        self.update_sync_path()
+
+    }
+
+    //* function to get the hashes of the stored blocks built on a block, on any branch */
+    fn descendants(&self, hash: &str) -> Result<Vec<String>,io::Error>{
+        let mut descendants = Vec::new();
+        let mut frontier = vec![hash.to_string()];
+        while let Some(hash) = frontier.pop(){
+            for child in self.children_of(&hash){
+                let child = child?;
+                frontier.push(child.clone());
+                descendants.push(child);
+            }
+        }
+        Ok(descendants)
+    }
+
+    //* function to check whether a block is part of the active chain */
+    fn is_active(&self, hash: &str, height: usize) -> Result<bool,io::Error>{
+        Ok(self.heights.get(Self::height_key(height))?.as_deref() == Some(hash.as_bytes()))
+    }
+
+    //* function to connect the branch with the most work, going down other branches while its blocks turn out invalid */
+    fn activate_best_chain(&mut self) -> Result<Vec<ChainEvent>,io::Error>{
+        let mut events = Vec::new();
+        loop{
+            let best = self.best_tip()?;
+            if best.is_empty() || best == self.inst_hash{
+                return Ok(events);
+            }
+            self.reorganize(&best, &mut events)?;
+        }
+    }
+
+    //* function to disconnect blocks down to the fork point with a branch and connect the branch up to a block */
+    /// Stops at the first block of the branch failing the consensus checks, which is marked invalid.
+    fn reorganize(&mut self, new_tip: &str, events: &mut Vec<ChainEvent>) -> Result<(),io::Error>{
+        //blocks of the branch above the fork point, its tip first
+        let mut branch: Vec<String> = Vec::new();
+        let mut hash = new_tip.to_string();
+        loop{
+            let header = self.get_header_by_hash(&hash)?.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("MISSING_HEADER: {}", hash)))?;
+            if self.is_active(&hash, header.height)?{
+                break;
+            }
+            branch.push(hash);
+            hash = header.prev_block_hash;
+            if hash.is_empty(){
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("FOREIGN_BRANCH: {} doesn't fork from this chain", new_tip)));
+            }
+        }
+        if hash != self.inst_hash{
+            info!("Reorganizing from {} to {} at fork point {}", self.inst_hash, new_tip, hash);
+        }
+        while self.inst_hash != hash{
+            let block = self.disconnect_tip()?;
+            info!("Disconnected block {} at height {}", block.get_hash(), block.get_height());
+            events.push(ChainEvent::Disconnected(block));
+        }
+        for hash in branch.iter().rev(){
+            let block = self.get_block_by_hash(hash)?.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("MISSING_BLOCK: {}", hash)))?;
+            if let Err(e) = self.check_transactions(block.get_height(), &block.get_transaction(), |tranc_id, vout| self.output_state(tranc_id, vout)){
+                info!("Rejected block {} at height {}: {}", hash, block.get_height(), e);
+                self.mark_invalid(hash, &e.to_string())?;
+                events.push(ChainEvent::Rejected(InvalidBlock{ height: Some(block.get_height()), hash: hash.clone(), reason: e.to_string() }));
+                return Ok(());
+            }
+            self.apply_block(&block)?;
+            info!("Connected block {} at height {}", hash, block.get_height());
+            events.push(ChainEvent::Connected(block));
+        }
+        Ok(())
+    }
+
+    //* function to take the tip block off the chain, restoring the outputs it spent, and return it */
+    fn disconnect_tip(&mut self) -> Result<Block,io::Error>{
+        let block = self.get_block_by_hash(&self.inst_hash)?.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("MISSING_BLOCK: {}", self.inst_hash)))?;
+        if block.get_previus_hash().is_empty(){
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "GENESIS_DISCONNECT: the genesis block can't be disconnected"));
+        }
+        let undo = self.get_undo(&block)?;
+        let mut update = IndexUpdate::default();
+        for tx in block.get_transaction(){
+            for (index, out) in tx.vout.iter().enumerate(){
+                update.utxo.remove(Self::utxo_key(&tx.tranc_id, index as i32).as_bytes());//outputs of the block are gone
+                update.addresses.remove(Self::address_key(&out.pub_key_hash, &tx.tranc_id, index as i32));
+            }
+        }
+        for spent in &undo{
+            let unspent = UnspentOutput{ output: spent.output.clone(), height: spent.height, coinbase: spent.coinbase };
+            update.utxo.insert(Self::utxo_key(&spent.tranc_id, spent.vout).as_bytes(), serialize(&unspent).map_err(|e| io::Error::other(e.to_string()))?);//spent outputs are back
+            update.addresses.insert(Self::address_key(&spent.output.pub_key_hash, &spent.tranc_id, spent.vout), &[]);
+        }
+        update.spent = self.spent_changes(&block, false)?;
+        update.heights.remove(&Self::height_key(block.get_height()));
+        Self::transaction_changes(&block, &mut update.transactions, false)?;
+        update.undo.remove(block.get_hash().as_bytes());
+        self.commit(update, &block.get_previus_hash())?;
+        Ok(block)
+    }
+
+    //* function to list the outputs from below a block its transactions spend, in block order */
+    /// lookup: the unspent output a transaction id and index point to
+    fn spent_outputs<F>(&self, block: &Block, lookup: F) -> Result<Vec<SpentOutput>,io::Error>
+    where F: Fn(&str, i32) -> Result<UnspentOutput,io::Error>{
+        let transactions = block.get_transaction();
+        let created: HashSet<&str> = transactions.iter().map(|tx| tx.tranc_id.as_str()).collect();
+        let mut spent = Vec::new();
+        for tx in transactions.iter().filter(|tx| !tx.is_coinbase()){
+            for vin in &tx.vin{
+                if !created.contains(vin.from.as_str()){//outputs created and spent in the block just go away
+                    let unspent = lookup(&vin.from, vin.vout)?;
+                    spent.push(SpentOutput{ tranc_id: vin.from.clone(), vout: vin.vout, output: unspent.output, height: unspent.height, coinbase: unspent.coinbase });
+                }
+            }
+        }
+        Ok(spent)
+    }
+
+    //* function to get the outputs a connected block spent */
+    /// Blocks connected before undo records were kept get them from the transactions of the chain below.
+    pub fn get_undo(&self, block: &Block) -> Result<Vec<SpentOutput>,io::Error>{
+        if let Some(data) = self.undo.get(block.get_hash())?{
+            return bincode::deserialize(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()));
+        }
+        self.spent_outputs(block, |tranc_id, vout|{
+            let (prev, height) = self.find_transaction_with_height(tranc_id)?.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("Transaction not found: {}", tranc_id)))?;
+            let output = usize::try_from(vout).ok().and_then(|index| prev.vout.get(index)).cloned()
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("MISSING_OUTPUT: {}", Self::utxo_key(tranc_id, vout))))?;
+            Ok(UnspentOutput{ output, height, coinbase: prev.is_coinbase() })
+        })
+    }
+
+    //* function to take blocks off the top of the chain, returning them as disconnected, the highest first */
+    /// The blocks stay stored as a side branch left out of the work ranking, so the chain doesn't move back onto it
+    /// whatever its work until `reconsider_block` ranks it again or a block built on it arrives.
+    pub fn rollback(&mut self, blocks: usize) -> Result<Vec<ChainEvent>,io::Error>{
+        let height = self.get_best_height()?;
+        if blocks > height{
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("GENESIS_DISCONNECT: the chain has only {} blocks above genesis", height)));
+        }
+        let mut events = Vec::new();
+        for _ in 0..blocks{
+            let block = self.disconnect_tip()?;
+            self.unrank(&block.get_hash())?;//not a candidate tip anymore
+            info!("Rolled back block {} at height {}", block.get_hash(), block.get_height());
+            events.push(ChainEvent::Disconnected(block));
+        }
+        self.db.flush()?;
+        Ok(events)
+    }
+
+    //* function to rank a rolled back block and the valid blocks built on it again, moving the chain onto them if they have the most work */
+    pub fn reconsider_block(&mut self, hash: &str) -> Result<Vec<ChainEvent>,io::Error>{
+        if !self.headers.contains_key(hash)?{
+            return Err(io::Error::new(io::ErrorKind::NotFound, format!("Block {} not found", hash)));
+        }
+        for hash in [hash.to_string()].into_iter().chain(self.descendants(hash)?){
+            if !self.invalid.contains_key(&hash)?{
+                self.ranked.insert(Self::rank_key(self.get_work(&hash)?.unwrap_or(0), &hash), &[])?;
+            }
+        }
+        let events = self.activate_best_chain()?;
+        self.db.flush()?;
+        Ok(events)
+    }
+
+    //* function to mark a block and its descendants invalid, moving the chain to the best branch without them */
+    pub fn invalidate_block(&mut self, hash: &str) -> Result<Vec<ChainEvent>,io::Error>{
+        let header = self.get_header_by_hash(hash)?.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("Block {} not found", hash)))?;
+        if header.prev_block_hash.is_empty(){
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "GENESIS_DISCONNECT: the genesis block can't be invalidated"));
+        }
+        self.mark_invalid(hash, "INVALIDATED: by the user")?;
+        let mut events = Vec::new();
+        if self.is_active(hash, header.height)?{
+            while self.get_best_height()? >= header.height{
+                let block = self.disconnect_tip()?;
+                info!("Disconnected block {} at height {}", block.get_hash(), block.get_height());
+                events.push(ChainEvent::Disconnected(block));
+            }
+        }
+        events.extend(self.activate_best_chain()?);
+        self.db.flush()?;
+        Ok(events)
+    }
+
+    //* function to get the difficulty the next block on top of the tip has to meet */
+    pub fn next_difficulty(&self) -> Result<usize,io::Error>{
+        let height = self.get_best_height()? + 1;
+        Ok(required_difficulty(height, &self.next_recent_headers()?))
+    }
+
+    //* function to get the headers the difficulty and timestamp rules look at for the block on top of the tip, genesis first */
+    fn next_recent_headers(&self) -> Result<Vec<BlockHeader>,io::Error>{
+        let height = self.get_best_height()? + 1;
+        let mut recent: Vec<BlockHeader> = Vec::new();//headers right below the new block
+        for h in height.saturating_sub(RETARGET_INTERVAL.max(MEDIAN_TIME_SPAN))..height{
+            recent.push(self.get_header_by_height(h)?.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("Block at height {} not indexed", h)))?);
+        }
+        Ok(recent)
+    }
+
+    //* function to build the key of a height in the height index */
+    fn height_key(height: usize) -> [u8; 8]{
+        (height as u64).to_be_bytes()//big endian keeps the index ordered by height
+    }
+
+    //* function to get a block by its hash */
+    pub fn get_block_by_hash(&self, hash: &str) -> Result<Option<Block>,io::Error>{
+        match self.db.get(hash)?{
+            Some(data) => bincode::deserialize::<Block>(&data)
+                .map(Some)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string())),
+            None => Ok(None),
+        }
+    }
+
+    //* function to get the block of the chain at a height */
+    pub fn get_block_by_height(&self, height: usize) -> Result<Option<Block>,io::Error>{
+        match self.heights.get(Self::height_key(height))?{
+            Some(hash) => self.get_block_by_hash(&String::from_utf8_lossy(&hash)),
+            None => Ok(None),
+        }
+    }
+
+    //* function to get the header of a block by its hash */
+    pub fn get_header_by_hash(&self, hash: &str) -> Result<Option<BlockHeader>,io::Error>{
+        match self.headers.get(hash)?{
+            Some(data) => bincode::deserialize::<BlockHeader>(&data)
+                .map(Some)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string())),
+            None => Ok(None),
+        }
+    }
+
+    //* function to get the header of the block of the chain at a height */
+    pub fn get_header_by_height(&self, height: usize) -> Result<Option<BlockHeader>,io::Error>{
+        match self.heights.get(Self::height_key(height))?{
+            Some(hash) => self.get_header_by_hash(&String::from_utf8_lossy(&hash)),
+            None => Ok(None),
+        }
+    }
+
+    //* function to get the height of the tip of the chain */
+    pub fn get_best_height(&self) -> Result<usize,io::Error>{
+        let tip = self.get_header_by_hash(&self.inst_hash)?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Tip header is missing"))?;
+        Ok(tip.height)
+    }
+
+    //* function to get the hash of the tip of the chain */
+    pub fn tip_hash(&self) -> String{
+        self.inst_hash.clone()
+    }
+
+    //* function to list hashes of the best known header chain from its last header down to genesis, further apart the deeper they are */
+    /// The ten most recent blocks come one by one, then the step doubles each time, so a peer finds the last
+    /// block both chains share in a few hashes, whatever their length. Headers of blocks still to download
+    /// count too, so a sync goes on from the last header received.
+    pub fn locator(&self) -> Result<Vec<String>,io::Error>{
+        let missing = |hash: &str| io::Error::new(io::ErrorKind::NotFound, format!("MISSING_HEADER: {}", hash));
+        let mut locator = Vec::new();
+        let mut hash = self.best_header()?;
+        let mut header = self.find_header(&hash)?.ok_or_else(|| missing(&hash))?;
+        let mut step = 1;
+        loop{
+            locator.push(hash.clone());
+            if locator.len() >= 10{
+                step *= 2;
+            }
+            if header.height == 0{
+                return Ok(locator);
+            }
+            let target = header.height.saturating_sub(step);
+            while header.height > target{
+                //through the height index once on the active chain
+                hash = match self.is_active(&hash, header.height)?{
+                    true => self.heights.get(Self::height_key(target))?
+                        .map(|hash| String::from_utf8_lossy(&hash).to_string())
+                        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("MISSING_HEIGHT: {}", target)))?,
+                    false => header.prev_block_hash.clone(),
+                };
+                header = self.find_header(&hash)?.ok_or_else(|| missing(&hash))?;
+            }
+        }
+    }
+
+    //* function to get up to `max` headers of the active chain following the first block of a locator it holds */
+    /// Gives nothing when no block of the locator is part of the active chain.
+    pub fn headers_after(&self, locator: &[String], max: usize) -> Result<Vec<BlockHeader>,io::Error>{
+        let mut fork = None;
+        for hash in locator{
+            if let Some(header) = self.get_header_by_hash(hash)?{
+                if self.is_active(hash, header.height)?{
+                    fork = Some(header.height);
+                    break;
+                }
+            }
+        }
+        let mut headers = Vec::new();
+        if let Some(fork) = fork{
+            for height in (fork + 1..=self.get_best_height()?).take(max){
+                match self.get_header_by_height(height)?{
+                    Some(header) => headers.push(header),
+                    None => break,
+                }
+            }
+        }
+        Ok(headers)
+    }
+
+    //* function to record the headers of blocks to download, checking they link up and carry their proof of work */
+    /// Headers come parents first; the ones already known are skipped. Their blocks can then arrive in any order,
+    /// each one waiting for its parent in the sync tree. Returns the number of new headers.
+    /// Fails with `ErrorKind::NotFound` on a header whose parent is unknown, the headers before it being kept.
+    pub fn submit_headers(&self, headers: &[BlockHeader]) -> Result<usize,io::Error>{
+        let mut added = 0;
+        for header in headers{
+            let hash = header.hash()?;
+            if let Some(reason) = self.invalid.get(&hash)?{
+                return Err(InvalidBlock{ height: Some(header.height), hash, reason: format!("KNOWN_INVALID: {}", String::from_utf8_lossy(&reason)) }.into());
+            }
+            if self.find_header(&hash)?.is_some(){
+                continue;
+            }
+            let parent = self.find_header(&header.prev_block_hash)?.ok_or_else(|| io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("ORPHAN_HEADER: parent '{}' of {} is unknown", header.prev_block_hash, hash),
+            ))?;
+            if let Some(reason) = self.invalid.get(&header.prev_block_hash)?{
+                return Err(InvalidBlock{ height: Some(header.height), hash, reason: format!("INVALID_PARENT: {}", String::from_utf8_lossy(&reason)) }.into());
+            }
+            self.check_header(header, &hash, &parent)?;
+            let parent_work = match self.get_work(&header.prev_block_hash)?{
+                Some(work) => work,
+                None => self.get_synced_header(&header.prev_block_hash)?.map(|synced| synced.work).unwrap_or_default(),
+            };
+            let synced = SyncedHeader{ header: header.clone(), work: parent_work.saturating_add(block_work(header.bits)) };
+            self.sync_headers.insert(hash.as_bytes(), serialize(&synced).map_err(|e| io::Error::other(e.to_string()))?)?;
+            self.sync_ranked.insert(Self::rank_key(synced.work, &hash), &[])?;
+            added += 1;
+        }
+        self.update_sync_path()?;
+        self.sync_headers.flush()?;
+        Ok(added)
+    }
+
+    //* function to list the blocks of the best known header chain not downloaded yet, lowest first */
+    /// Gives (hash, height) pairs, nothing once the active chain has the most work.
+    /// Reads the sync path from its lowest block, so the cost follows `max` and the blocks waiting for their parent,
+    /// not the length of the header chain.
+    pub fn missing_blocks(&self, max: usize) -> Result<Vec<(String,usize)>,io::Error>{
+        let mut missing = Vec::new();
+        for entry in self.sync_path.iter(){
+            if missing.len() >= max{
+                break;
+            }
+            let (_, hash) = entry?;
+            let hash = String::from_utf8_lossy(&hash).to_string();
+            if let Some(synced) = self.get_synced_header(&hash)?{
+                if !self.sync_blocks.contains_key(Self::child_key(&synced.header.prev_block_hash, &hash).as_bytes())?{
+                    missing.push((hash, synced.header.height));
+                }
+            }
+        }
+        Ok(missing)
+    }
+
+    //* function to bring the sync path in line with the best header, walking down only to where it already matches */
+    fn update_sync_path(&self) -> Result<(),io::Error>{
+        let mut hash = self.best_header()?;
+        let top = self.find_header(&hash)?.map_or(0, |header| header.height);
+        for entry in self.sync_path.range(Self::height_key(top + 1)..){
+            self.sync_path.remove(entry?.0)?;//above the best header
+        }
+        loop{
+            let Some(synced) = self.get_synced_header(&hash)? else{
+                //down to a stored block, the entries left below belong to another branch
+                if let Some(header) = self.get_header_by_hash(&hash)?{
+                    for entry in self.sync_path.range(..=Self::height_key(header.height)){
+                        self.sync_path.remove(entry?.0)?;
+                    }
+                }
+                return Ok(());
+            };
+            let key = Self::height_key(synced.header.height);
+            if self.sync_path.get(key)?.as_deref() == Some(hash.as_bytes()){
+                return Ok(());//the rest of the path leads there already
+            }
+            self.sync_path.insert(key, hash.as_bytes())?;
+            hash = synced.header.prev_block_hash;
+        }
+    }
+
+    //* function to drop the header of a block to download from the sync trees, along with its block if it was held */
+    fn forget_synced_header(&self, hash: &str) -> Result<(),io::Error>{
+        let Some(synced) = self.get_synced_header(hash)? else{
+            return Ok(());
+        };
+        self.sync_headers.remove(hash.as_bytes())?;
+        self.sync_ranked.remove(Self::rank_key(synced.work, hash))?;
+        self.sync_blocks.remove(Self::child_key(&synced.header.prev_block_hash, hash).as_bytes())?;
+        let key = Self::height_key(synced.header.height);
+        if self.sync_path.get(key)?.as_deref() == Some(hash.as_bytes()){
+            self.sync_path.remove(key)?;
+        }
+        Ok(())
+    }
+
+    //* function to drop the headers of blocks to download that can't lead past the active chain anymore */
+    /// Headers with no more work than the tip go unless they lead to the best header; a peer sends them again
+    /// if their branch grows.
+    fn prune_synced_headers(&self) -> Result<(),io::Error>{
+        let tip_work = self.get_work(&self.inst_hash)?.unwrap_or(0);
+        let mut stale = Vec::new();
+        for entry in self.sync_ranked.iter(){
+            let (key, _) = entry?;
+            if key[..16] > tip_work.to_be_bytes()[..]{
+                break;
+            }
+            let hash = String::from_utf8_lossy(&key[16..]).to_string();
+            let on_path = match self.get_synced_header(&hash)?{
+                Some(synced) => self.sync_path.get(Self::height_key(synced.header.height))?.as_deref() == Some(hash.as_bytes()),
+                None => false,
+            };
+            if !on_path{
+                stale.push(hash);
+            }
+        }
+        for hash in stale{
+            self.forget_synced_header(&hash)?;
+        }
+        Ok(())
+    }
+
+    //* function to rank every header of a block to download not known to be invalid by the work of its branch */
+    fn rerank_synced_headers(&self) -> Result<(),io::Error>{
+        self.sync_ranked.clear()?;
+        for entry in self.sync_headers.iter(){
+            let (key, value) = entry?;
+            let synced = bincode::deserialize::<SyncedHeader>(&value).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+            if !self.invalid.contains_key(&key)?{
+                self.sync_ranked.insert(Self::rank_key(synced.work, &String::from_utf8_lossy(&key)), &[])?;
+            }
+        }
+        self.update_sync_path()?;
+        self.sync_ranked.flush()?;
+        Ok(())
+    }
+
+    //* function to check whether a block is stored or downloaded and waiting for its parent */
+    pub fn has_block(&self, hash: &str) -> Result<bool,io::Error>{
+        if self.headers.contains_key(hash)?{
+            return Ok(true);
+        }
+        Ok(match self.get_synced_header(hash)?{
+            Some(synced) => self.sync_blocks.contains_key(Self::child_key(&synced.header.prev_block_hash, hash).as_bytes())?,
+            None => false,
+        })
+    }
+
+    //* function to get the hash of the known header with the most work, stored or waiting for its block, the tip on ties */
+    /// Equal headers go to the lowest hash, like in `best_tip`.
+    fn best_header(&self) -> Result<String,io::Error>{
+        let tip_work = self.get_work(&self.inst_hash)?.unwrap_or(0);
+        let Some((last, _)) = self.sync_ranked.last()? else{
+            return Ok(self.inst_hash.clone());
+        };
+        let most = &last[..16];//work of the heaviest headers
+        if most <= tip_work.to_be_bytes().as_slice(){
+            return Ok(self.inst_hash.clone());
+        }
+        let (first, _) = self.sync_ranked.scan_prefix(most).next().expect("the last key has this prefix")?;
+        Ok(String::from_utf8_lossy(&first[16..]).to_string())
+    }
+
+    //* function to keep a block with a synced header until its parent is stored */
+    fn hold_block(&self, block: Block) -> Result<Vec<ChainEvent>,io::Error>{
+        let hash = block.get_hash();
+        if self.get_synced_header(&hash)?.is_none(){
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("ORPHAN_BLOCK: parent '{}' of {} is unknown", block.get_previus_hash(), hash),
+            ));
+        }
+        //the header was checked already, the block has to match it
+        let fail = |reason: String| InvalidBlock{ height: Some(block.get_height()), hash: hash.clone(), reason };
+        if block.get_header().hash()? != hash{
+            return Err(fail(String::from("HASH_MISMATCH: the content doesn't hash to the synced header")).into());
+        }
+        if !block.has_valid_merkle_root(){
+            return Err(fail(String::from("MERKLE_ROOT_MISMATCH")).into());
+        }
+        let data = serialize(&block).map_err(|e| io::Error::other(e.to_string()))?;
+        self.sync_blocks.insert(Self::child_key(&block.get_previus_hash(), &hash).as_bytes(), data)?;
+        Ok(Vec::new())
+    }
+
+    //* function to store the blocks waiting for a block just stored, then the ones waiting for them */
+    fn store_held_blocks(&self, hash: &str) -> Result<(),io::Error>{
+        let mut parents = vec![hash.to_string()];
+        while let Some(parent_hash) = parents.pop(){
+            let parent = self.get_header_by_hash(&parent_hash)?.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("MISSING_HEADER: {}", parent_hash)))?;
+            let mut children = Vec::new();
+            for entry in self.sync_blocks.scan_prefix(format!("{}:", parent_hash).as_bytes()){
+                let (_, value) = entry?;
+                children.push(bincode::deserialize::<Block>(&value).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?);
+            }
+            for child in children{
+                match self.check_block_header(&child, &parent){
+                    Ok(()) => {
+                        self.store_block(&child)?;
+                        parents.push(child.get_hash());
+                    }
+                    Err(invalid) => {
+                        self.sync_blocks.remove(Self::child_key(&parent_hash, &child.get_hash()).as_bytes())?;
+                        self.mark_invalid(&child.get_hash(), &invalid.reason)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    //* function to get a header of a stored block or of a block to download */
+    fn find_header(&self, hash: &str) -> Result<Option<BlockHeader>,io::Error>{
+        match self.get_header_by_hash(hash)?{
+            Some(header) => Ok(Some(header)),
+            None => Ok(self.get_synced_header(hash)?.map(|synced| synced.header)),
+        }
+    }
+
+    //* function to get the synced header of a block to download */
+    fn get_synced_header(&self, hash: &str) -> Result<Option<SyncedHeader>,io::Error>{
+        match self.sync_headers.get(hash)?{
+            Some(data) => bincode::deserialize::<SyncedHeader>(&data)
+                .map(Some)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string())),
+            None => Ok(None),
+        }
+    }
+
+    //* function to build the key of a block in the child and sync trees, grouping the blocks by parent */
+    fn child_key(parent: &str, hash: &str) -> String{
+        format!("{}:{}", parent, hash)
+    }
+
+    //* function to store the header of a block in the header tree and link it to its parent */
+    fn store_header(&self, block: &Block) -> Result<(),io::Error>{
+        let data = serialize(block.get_header()).map_err(|e| io::Error::other(e.to_string()))?;
+        self.headers.insert(block.get_hash().as_bytes(), data)?;
+        self.children.insert(Self::child_key(&block.get_previus_hash(), &block.get_hash()).as_bytes(), &[])?;
+        Ok(())
+    }
+
+    //* function to rebuild the header tree from the blocks */
+    pub fn reindex_headers(&self) -> Result<(),io::Error>{
+        self.headers.clear()?;
+        self.children.clear()?;
+        for block in self.iter(){
+            self.store_header(&block)?;
+        }
+        self.headers.flush()?;
+        Ok(())
+    }
+
+    //* function to rebuild the links from parents to children from the header tree */
+    fn reindex_children(&self) -> Result<(),io::Error>{
+        self.children.clear()?;
+        for entry in self.headers.iter(){
+            let (key, value) = entry?;
+            let header = bincode::deserialize::<BlockHeader>(&value).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+            self.children.insert(Self::child_key(&header.prev_block_hash, &String::from_utf8_lossy(&key)).as_bytes(), &[])?;
+        }
+        self.children.flush()?;
+        Ok(())
+    }
+
+    //* function to get the hashes of the stored blocks built right on a block */
+    fn children_of(&self, hash: &str) -> impl Iterator<Item = Result<String,io::Error>>{
+        let prefix = Self::child_key(hash, "");
+        self.children.scan_prefix(prefix.as_bytes()).keys().map(move |key| Ok(String::from_utf8_lossy(&key?[prefix.len()..]).to_string()))
+    }
+
+    //* function to rebuild the height index from the blocks */
+    pub fn reindex_heights(&self) -> Result<(),io::Error>{
+        self.heights.clear()?;
+        for (hash, header) in self.iter_headers(){
+            self.heights.insert(Self::height_key(header.height), hash.as_bytes())?;
+        }
+        self.heights.flush()?;
         Ok(())
     }
 
+    //* function to collect the changes to the transaction index when a block is connected or disconnected */
+    fn transaction_changes(block: &Block, batch: &mut sled::Batch, connect: bool) -> Result<(),io::Error>{
+        let location = serialize(&(block.get_hash(), block.get_height())).map_err(|e| io::Error::other(e.to_string()))?;
+        for tx in block.get_transaction(){
+            if connect{
+                batch.insert(tx.tranc_id.as_bytes(), location.clone());
+            }else{
+                batch.remove(tx.tranc_id.as_bytes());
+            }
+        }
+        Ok(())
+    }
+
+    //* function to rebuild the transaction index from the blocks of the active chain */
+    pub fn reindex_transactions(&self) -> Result<(),io::Error>{
+        self.transactions.clear()?;
+        for block in self.iter(){
+            let mut batch = sled::Batch::default();
+            Self::transaction_changes(&block, &mut batch, true)?;
+            self.transactions.apply_batch(batch)?;
+        }
+        self.db.insert(TX_TIP, self.inst_hash.as_bytes())?;
+        self.db.flush()?;
+        Ok(())
+    }
+
+    //* function to build the key of an output in the UTXO set */
+    pub(crate) fn utxo_key(tranc_id: &str, vout: i32) -> String{
+        format!("{}:{}", tranc_id, vout)
+    }
+
+    //* function to build the key of an output in the address index, its owner first so outputs of an address share a prefix */
+    fn address_key(pub_key_hash: &[u8], tranc_id: &str, vout: i32) -> Vec<u8>{
+        let mut key = Self::address_prefix(pub_key_hash);
+        key.extend_from_slice(Self::utxo_key(tranc_id, vout).as_bytes());
+        key
+    }
+
+    //* function to build the prefix shared by the outputs of an address in the address index */
+    fn address_prefix(pub_key_hash: &[u8]) -> Vec<u8>{
+        let mut prefix = pub_key_hash.to_vec();
+        prefix.push(b':');
+        prefix
+    }
+
+    //* function to apply the transactions of a block to the UTXO set and its address index */
+    fn update_utxo(&self, block: &Block) -> Result<(),io::Error>{
+        let (mut batch, mut owners) = (sled::Batch::default(), sled::Batch::default());
+        self.utxo_changes(block, &mut batch, &mut owners)?;
+        self.utxo.apply_batch(batch)?;
+        self.addresses.apply_batch(owners)?;
+        Ok(())
+    }
+
+    //* function to collect the changes the transactions of a block make to the UTXO set and its address index */
+    fn utxo_changes(&self, block: &Block, batch: &mut sled::Batch, owners: &mut sled::Batch) -> Result<(),io::Error>{
+        let mut created: HashMap<String,Vec<u8>> = HashMap::new();//owners of the outputs created by the block so far
+        for tx in block.get_transaction(){
+            if !tx.is_coinbase(){
+                for vin in &tx.vin{
+                    let key = Self::utxo_key(&vin.from, vin.vout);
+                    let owner = match created.remove(&key){
+                        Some(owner) => Some(owner),
+                        None => match self.utxo.get(key.as_bytes())?{
+                            Some(data) => Some(bincode::deserialize::<UnspentOutput>(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?.output.pub_key_hash),
+                            None => None,
+                        },
+                    };
+                    if let Some(owner) = owner{
+                        owners.remove(Self::address_key(&owner, &vin.from, vin.vout));
+                    }
+                    batch.remove(key.as_bytes());//output is now spent
+                }
+            }
+            for (index, out) in tx.vout.iter().enumerate(){
+                let unspent = UnspentOutput{ output: out.clone(), height: block.get_height(), coinbase: tx.is_coinbase() };
+                let value = serialize(&unspent).map_err(|e| io::Error::other(e.to_string()))?;
+                let key = Self::utxo_key(&tx.tranc_id, index as i32);
+                owners.insert(Self::address_key(&out.pub_key_hash, &tx.tranc_id, index as i32), &[]);
+                batch.insert(key.as_bytes(), value);//new unspent output
+                created.insert(key, out.pub_key_hash.clone());
+            }
+        }
+        Ok(())
+    }
+
+    //* function to rebuild the UTXO set by replaying every block from genesis */
+    pub fn reindex_utxo(&self) -> Result<(),io::Error>{
+        self.utxo.clear()?;
+        self.addresses.clear()?;
+        let mut blocks: Vec<Block> = self.iter().collect();
+        blocks.reverse();//genesis first so spends come after the outputs they remove
+        for block in &blocks{
+            self.update_utxo(block)?;
+        }
+        self.db.insert(UTXO_TIP, self.inst_hash.as_bytes())?;
+        self.db.flush()?;
+        Ok(())
+    }
+
+    //* function to list the unspent outputs owned by a pub key hash as (tranc_id, vout index, output) through the address index */
+    fn unspent_outputs_of<'a>(&'a self, pub_key_hash: &[u8]) -> impl Iterator<Item = (String, i32, TrancOutput)> + 'a{
+        let prefix = Self::address_prefix(pub_key_hash);
+        self.addresses.scan_prefix(&prefix).keys().flatten().filter_map(move |key|{
+            let key = String::from_utf8(key[prefix.len()..].to_vec()).ok()?;
+            let (tranc_id, vout) = key.split_once(':')?;
+            let unspent = bincode::deserialize::<UnspentOutput>(&self.utxo.get(key.as_bytes()).ok()??).ok()?;
+            Some((tranc_id.to_string(), vout.parse().ok()?, unspent.output))
+        })
+    }
+
+    //* To get the directory holding the databases of this chain */
+    pub fn data_dir(&self) -> &Path{
+        &self.data_dir
+    }
+
     //* Function for creating the first iterator */
-    pub fn iter(&self) -> BlockchainIterator{
+    pub fn iter(&self) -> BlockchainIterator<'_>{
         BlockchainIterator{
             inst_hash: self.inst_hash.clone(), //setting blockchain iterator
-            blockchain: &self, //blochchain
+            blockchain: self, //blochchain
         }
     }
 
-    //* function to load spent transactions */
-    fn load_spent_transactions(&self)->HashMap<String,Vec<i32>>{
-        let db = sled::open("data/spent_records").unwrap();
-        let mut spent_transactions = HashMap::new();
-        for item in db.into_iter(){
-            if let Ok((key,value)) = item{
-                let tx_id = String::from_utf8(key.to_vec()).unwrap_or_default();
-                let outputs: Vec<i32> = serde_json::from_slice(&value).unwrap_or_default();
-                spent_transactions.insert(tx_id,outputs);
+    //* Function for iterating the headers of the chain from the tip down */
+    pub fn iter_headers(&self) -> HeaderIterator<'_>{
+        HeaderIterator{
+            inst_hash: self.inst_hash.clone(),
+            blockchain: self,
+        }
+    }
+
+    //* function to load every header from the tip down to genesis, failing on missing or undecodable headers */
+    fn load_header_chain(&self) -> Result<Vec<(String,BlockHeader)>,InvalidBlock>{
+        let mut headers: Vec<(String,BlockHeader)> = Vec::new();
+        let mut hash = self.inst_hash.clone();
+        while !hash.is_empty(){
+            let fail = |reason: String| InvalidBlock{ height: None, hash: hash.clone(), reason: format!("{} ({} blocks below the tip)", reason, headers.len()) };
+            let header = match self.get_header_by_hash(&hash){
+                Ok(Some(header)) => header,
+                Ok(None) => return Err(fail(String::from("MISSING_HEADER"))),
+                Err(e) => return Err(fail(format!("DATABASE_ERROR: {}", e))),
+            };
+            let prev_hash = header.prev_block_hash.clone();
+            headers.push((hash, header));
+            hash = prev_hash;
+        }
+        headers.reverse();//genesis first
+        Ok(headers)
+    }
+
+    //* function to validate the header chain, returning the (hash, header) pairs from genesis up */
+    fn check_header_chain(&self) -> Result<Vec<(String,BlockHeader)>,InvalidBlock>{
+        let headers = self.load_header_chain()?;
+        let mut prev_hash = String::new();//genesis has no previous block
+        for (height, (hash, header)) in headers.iter().enumerate(){
+            let fail = |reason: String| InvalidBlock{ height: Some(height), hash: hash.clone(), reason };
+
+            //checking the link to the previous block
+            if header.prev_block_hash != prev_hash{
+                return Err(fail(format!("PREV_HASH_MISMATCH: expected '{}', found '{}'", prev_hash, header.prev_block_hash)));
+            }
+            //checking the height stored in the block
+            if header.height != height{
+                return Err(fail(format!("HEIGHT_MISMATCH: found {}", header.height)));
+            }
+            //checking the timestamp doesn't go below the median of the blocks before it
+            let recent: Vec<BlockHeader> = headers[height.saturating_sub(RETARGET_INTERVAL.max(MEDIAN_TIME_SPAN))..height].iter().map(|(_, header)| header.clone()).collect();
+            let earliest = median_time_past(&recent);
+            if header.timestamp < earliest{
+                return Err(fail(format!("TIME_TOO_OLD: {} is before the median time past {}", header.timestamp, earliest)));
+            }
+            //checking the block was mined with the difficulty the chain requires at its height
+            let required = required_difficulty(height, &recent);
+            if header.bits != required{
+                return Err(fail(format!("DIFFICULTY_MISMATCH: required {}, found {}", required, header.bits)));
+            }
+            //checking the proof of work and the stored hash
+            match header.meets_difficulty(){
+                Ok(true) => (),
+                Ok(false) => return Err(fail(String::from("PROOF_OF_WORK_NOT_MET"))),
+                Err(e) => return Err(fail(format!("HASHING_ERROR: {}", e))),
+            }
+            prev_hash = match header.hash(){
+                Ok(computed) => computed,//the next block has to link to the real hash of this one
+                Err(e) => return Err(fail(format!("HASHING_ERROR: {}", e))),
+            };
+        }
+        //the links checked every header is stored under its own hash but the tip, the one the chain is loaded from
+        if let Some((hash, _)) = headers.last().filter(|(hash, _)| *hash != prev_hash){
+            return Err(InvalidBlock{ height: Some(headers.len() - 1), hash: hash.clone(), reason: format!("HASH_MISMATCH: content hashes to {}", prev_hash) });
+        }
+        Ok(headers)
+    }
+
+    //* function to validate the whole chain from genesis up to the tip */
+    /// Checks the header chain first, then that every block matches its header,
+    /// the merkle roots and the consensus rules of every transaction (see `check_transactions`).
+    /// Returns the first block found to be invalid.
+    pub fn validate_chain(&self) -> Result<(),InvalidBlock>{
+        let headers = self.check_header_chain()?;
+        let mut unspent: HashMap<String,UnspentOutput> = HashMap::new();//outputs of the blocks checked so far
+        let mut spent: HashSet<String> = HashSet::new();//outputs spent by the blocks checked so far
+        for (height, (hash, header)) in headers.iter().enumerate(){
+            let fail = |reason: String| InvalidBlock{ height: Some(height), hash: hash.clone(), reason };
+
+            //loading the body and checking it belongs to the header
+            let block = match self.get_block_by_hash(hash){
+                Ok(Some(block)) => block,
+                Ok(None) => return Err(fail(String::from("MISSING_BLOCK"))),
+                Err(e) => return Err(fail(format!("DESERIALIZATION_ERROR: {}", e))),
+            };
+            if block.get_header() != header || &block.get_hash() != hash{
+                return Err(fail(String::from("HEADER_MISMATCH: stored block differs from its header")));
+            }
+            //checking the header commits to the transactions of the block
+            if !block.has_valid_merkle_root(){
+                return Err(fail(String::from("MERKLE_ROOT_MISMATCH")));
+            }
+
+            //checking every transaction of the block against the outputs of the blocks below it
+            let transactions = block.get_transaction();
+            let state = |tranc_id: &str, vout: i32| -> Result<OutputState,io::Error>{
+                let outpoint = Self::utxo_key(tranc_id, vout);
+                Ok(match unspent.get(&outpoint){
+                    Some(out) => OutputState::Unspent(out.clone()),
+                    None if spent.contains(&outpoint) => OutputState::Spent,
+                    None => OutputState::Missing,
+                })
+            };
+            if let Err(e) = self.check_transactions(height, &transactions, state){
+                return Err(fail(e.to_string()));
+            }
+            for tx in &transactions{
+                if !tx.is_coinbase(){
+                    for vin in &tx.vin{
+                        let outpoint = Self::utxo_key(&vin.from, vin.vout);
+                        unspent.remove(&outpoint);
+                        spent.insert(outpoint);
+                    }
+                }
+                for (index, out) in tx.vout.iter().enumerate(){
+                    unspent.insert(Self::utxo_key(&tx.tranc_id, index as i32), UnspentOutput{ output: out.clone(), height, coinbase: tx.is_coinbase() });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    //* function to record the outputs spent by the transactions of a block */
+    fn record_spent_outputs(&self, block: &Block) -> Result<(),io::Error>{
+        self.spent.apply_batch(self.spent_changes(block, true)?)?;
+        Ok(())
+    }
+
+    //* function to collect the changes to the spent records when the transactions of a block are connected or disconnected */
+    fn spent_changes(&self, block: &Block, connect: bool) -> Result<sled::Batch,io::Error>{
+        let mut records: HashMap<String,Vec<i32>> = HashMap::new();//spent outputs of the transactions the block touches
+        for tx in block.get_transaction(){
+            if tx.is_coinbase(){
+                continue;//coinbase spends nothing
+            }
+            for vin in &tx.vin{
+                if !records.contains_key(&vin.from){
+                    let outputs: Vec<i32> = match self.spent.get(&vin.from)?{
+                        Some(value) => serde_json::from_slice(&value)?,
+                        None => Vec::new(),
+                    };
+                    records.insert(vin.from.clone(), outputs);
+                }
+                let outputs = records.get_mut(&vin.from).expect("loaded above");
+                if !connect{
+                    outputs.retain(|vout| *vout != vin.vout);
+                }else if !outputs.contains(&vin.vout){//recording each output only once
+                    outputs.push(vin.vout);
+                }
+            }
+        }
+        let mut batch = sled::Batch::default();
+        for (tranc_id, outputs) in records{
+            if outputs.is_empty(){
+                batch.remove(tranc_id.as_bytes());
+            }else{
+                batch.insert(tranc_id.as_bytes(), serde_json::to_vec(&outputs)?);
+            }
+        }
+        Ok(batch)
+    }
+
+    //* function to rebuild the spent records by replaying every block from genesis */
+    /// Whatever the records held before is dropped, including the standalone data/spent_records store of older versions.
+    pub fn rebuild_spent_records(&mut self) -> Result<(),io::Error>{
+        let legacy = self.data_dir.join(SPENT_TREE);
+        if legacy.exists(){
+            info!("Removing the old spent records in {}...", legacy.display());//message
+            fs::remove_dir_all(&legacy)?;
+        }
+        self.db.drop_tree(SPENT_TREE)?;//stale or duplicated records of older runs
+        self.spent = self.db.open_tree(SPENT_TREE)?;
+        for block in self.iter(){
+            self.record_spent_outputs(&block)?;
+        }
+        self.db.insert(SPENT_TIP, self.inst_hash.as_bytes())?;
+        self.db.flush()?;
+        Ok(())
+    }
+
+    //* funcion to find and return all unsent transaction outputs */
+    pub fn find_utxo(&self, address: Vec<u8>) -> Vec<TrancOutput>{
+        self.unspent_outputs_of(&address)
+            .map(|(_, _, out)| out)
+            .collect()
+    }
+
+    //* function to get the unspent outputs of an address as coins to select from */
+    /// Confirmed outputs only, `SpendOptions::find_spendable_outputs` accounts for the pending transactions.
+    pub fn spendable_coins(&self, address: &[u8]) -> Vec<Coin>{
+        self.unspent_outputs_of(address)
+            .map(|(tranc_id, vout, out)| Coin{tranc_id, vout, value: out.value})
+            .collect()
+    }
+
+    //* function to find a confirmed transaction along with the height of its block, through the transaction index */
+    pub fn find_transaction_with_height(&self, id: &str) -> Result<Option<(Transaction,usize)>,io::Error>{
+        let Some(block) = self.find_transaction_block(id)? else{
+            return Ok(None);
+        };
+        Ok(block.get_transaction().into_iter().find(|tx| tx.tranc_id == id).map(|tx| (tx, block.get_height())))
+    }
+
+    //* function to get the block of the active chain confirming a transaction */
+    fn find_transaction_block(&self, id: &str) -> Result<Option<Block>,io::Error>{
+        match self.transactions.get(id)?{
+            Some(data) => {
+                let (hash, _height) = bincode::deserialize::<(String,usize)>(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+                self.get_block_by_hash(&hash)
             }
+            None => Ok(None),
         }
-        spent_transactions
     }
 
-    //* function to save pent transactions */
-    fn save_spent_transactions(&self, tx_id: &str, outputs:Vec<i32>) -> Result<(),io::Error>{
-        let db = sled::open("data/spent_records")?;
-        let value = serde_json::to_vec(&outputs)?;
-        db.insert(tx_id, value)?;
-        db.flush()?;
-        println!("Transaction saved");
-        Ok(())
+    //* function to build a merkle proof for a confirmed transaction, returning the hash of its block with it */
+    pub fn prove_transaction(&self, tranc_id: &str) -> Result<Option<(String, InclusionProof)>,io::Error>{
+        Ok(self.find_transaction_block(tranc_id)?.and_then(|block| block.prove_transaction(tranc_id).map(|proof| (block.get_hash(), proof))))
     }
 
-    //* function to send the list of unsent transactions */
-    fn find_unspent_transactions(&self, address: Vec<u8>)->Vec<Transaction>{
-        let mut spent_tx: HashMap<String,Vec<i32>> = self.load_spent_transactions();//hash map to store spent
-        let mut unspent_tx: Vec<Transaction> = Vec::new();//hash to store unspent transactions
+    //* function to check a merkle proof against the header of a block of this chain */
+    pub fn verify_transaction_proof(&self, block_hash: &str, tranc_id: &str, proof: &InclusionProof) -> Result<bool,io::Error>{
+        match self.get_block_by_hash(block_hash)?{
+            Some(block) => Ok(proof.verify(&block.get_header().merkle_root, tranc_id)),
+            None => Ok(false),
+        }
+    }
 
-        for block in self.iter(){ //iterating Clockchain
-            for tx in block.get_transaction(){ //iterating through transactions of each block
-                for index in 0..tx.vout.len(){//interating through vout
-                    if let Some(ids) = spent_tx.get(&tx.tranc_id){ //checking if transaction is in spent id
-                        if ids.contains(&(index as i32)){
-                            continue;//checking if transaction is already spent
-                        }
-                    }
+    //* function to run the consensus checks on the transactions of a block, in block order */
+    /// The block has to start with its only coinbase, paying at most the subsidy of its height plus the fees of the block.
+    /// Every transaction needs a valid id. Every other than the coinbase may only spend outputs that exist and
+    /// are unspent, either confirmed before the block or created earlier in it, each at most once in the block,
+    /// must be signed by the owners of those outputs, can't create empty outputs and can't pay more than it spends.
+    /// No sum of values may overflow.
+    /// Coinbase outputs are only spendable once mature.
+    /// height: height of the block
+    /// output_state: state of a confirmed output given its transaction id and index
+    fn check_transactions<F>(&self, height: usize, transactions: &[Transaction], output_state: F) -> Result<(),TransactionError>
+    where F: Fn(&str, i32) -> Result<OutputState,io::Error>{
+        //the coinbase comes first and only once
+        let coinbase = match transactions.first(){
+            Some(tx) if tx.is_coinbase() => tx,
+            first => return Err(TransactionError::MissingCoinbase{ tranc_id: first.map(|tx| tx.tranc_id.clone()).unwrap_or_default() }),
+        };
+        if let Some((position, tx)) = transactions.iter().enumerate().skip(1).find(|(_, tx)| tx.is_coinbase()){
+            return Err(TransactionError::MisplacedCoinbase{ tranc_id: tx.tranc_id.clone(), position });
+        }
 
-                    if tx.vout[index].can_be_unlock_with(address.clone()){//checking public auth
-                        unspent_tx.push(tx.to_owned()); //getting unsent transactions output
-                    }
-                }
-                if !tx.is_coinbase(){ // checking if the transaction is coinbase or not
-                    for i in &tx.vin{ // getting transactions in vin
-                        if i.can_unlock_output_with(address.clone()){ // cheking auth with public address
-                            match spent_tx.get_mut(&i.from) {
-                                Some(v) => {
-                                    v.push(i.vout);
-                                    self.save_spent_transactions(&i.from,v.to_vec()).unwrap();
-                                }
-                                None => {
-                                    spent_tx.insert(i.from.clone(),vec![i.vout]);
-                                    self.save_spent_transactions(&i.from,vec![i.vout]).unwrap();
-                                }                                
-                            }
-                        }
-                    }
-                }
+        let mut block_txs: HashMap<String,&Transaction> = HashMap::new();//earlier transactions of the block
+        let mut spent_by: HashMap<String,String> = HashMap::new();//outputs spent in the block and the transaction spending them
+        let mut fees = Amount::ZERO;//collected by the coinbase
+        for tx in transactions{
+            let tranc_id = tx.tranc_id.clone();
+            Self::check_id(tx)?;
+            if !tx.is_coinbase(){
+                let fee = self.check_spend(height, tx, &block_txs, &mut spent_by, &output_state)?;
+                fees = fees.checked_add(fee).ok_or_else(|| TransactionError::ValueOverflow{ tranc_id: tranc_id.clone() })?;
             }
+            block_txs.insert(tx.tranc_id.clone(), tx);
         }
-        unspent_tx
+
+        //the miner gets at most the subsidy and the fees
+        let overflow = || TransactionError::ValueOverflow{ tranc_id: coinbase.tranc_id.clone() };
+        let paid = Amount::checked_sum(coinbase.vout.iter().map(|out| out.value)).ok_or_else(overflow)?;
+        let allowed = self.rewards.subsidy(height).checked_add(fees).ok_or_else(overflow)?;
+        if paid > allowed{
+            return Err(TransactionError::ExcessiveReward{ tranc_id: coinbase.tranc_id.clone(), paid, allowed });
+        }
+        Ok(())
     }
 
-    //* funcion to find and return all unsent transaction outputs */
-    pub fn find_utxo(&self, address: Vec<u8>) -> Vec<TrancOutput>{
-        let mut utxos = Vec::<TrancOutput>::new(); //vec to store UTXOs
-        let unspend_txs = self.find_unspent_transactions(address.clone()); //getting unspend transactions
-        for tx in unspend_txs{ // iterating unspent transactions
-            for out in &tx.vout{ 
-                if out.can_be_unlock_with(address.clone()){
-                    utxos.push(out.clone());
-                }
-            } 
-        }
-        utxos
-    }
-
-    //* function to return list of transactions containing unspent outputs */
-    pub fn find_spendable_outputs(&self, address:Vec<u8> , amount: i32) -> (i32,HashMap<String,Vec<i32>>){
-        let mut unspent_outputs: HashMap<String,Vec<i32>> = HashMap::new(); //getting unspent transactions
-        let mut accumulated: i32 = 0; //accumanted amount from utxos
-        let unspent_txs: Vec<Transaction> = self.find_unspent_transactions(address.clone());
-        
-        for tx in unspent_txs{
-            for index in 0..tx.vout.len(){
-                if tx.vout[index].can_be_unlock_with(address.clone()) && accumulated< amount{
-                    match unspent_outputs.get_mut(&tx.tranc_id){
-                        Some(v)=>{
-                            v.push(index as i32);
-                        },
-                        None => {
-                            unspent_outputs.insert(tx.tranc_id.clone(), vec![index as i32]);
-                        }
-                    }
-                    accumulated+=tx.vout[index].value;
-                    if accumulated>=amount{
-                        return (accumulated, unspent_outputs);
-                    }
-                }
-            }
+    //* function to check that the stored id of a transaction matches its content */
+    fn check_id(tx: &Transaction) -> Result<(),TransactionError>{
+        match tx.has_valid_id(){
+            Ok(true) => Ok(()),
+            Ok(false) => Err(TransactionError::IdMismatch{ tranc_id: tx.tranc_id.clone() }),
+            Err(e) => Err(TransactionError::VerificationFailed{ tranc_id: tx.tranc_id.clone(), reason: e.to_string() }),
         }
-        (accumulated,unspent_outputs)
     }
 
-    //* function to find a transaction with a particular id */
-    pub fn find_transaction(&self, id: &str) -> Result<Transaction,failure::Error>{
-        for block in self.iter(){
-            for transaction in block.get_transaction(){
-                if transaction.tranc_id == id{
-                    return Ok(transaction.clone())
-                }
+    //* function to run the consensus checks on a transaction other than a coinbase, returning its fee */
+    /// block_txs: earlier transactions of the block, whose outputs can be spent
+    /// spent_by: outputs spent earlier in the block and the transaction spending them, the inputs of this one are added
+    fn check_spend<F>(&self, height: usize, tx: &Transaction, block_txs: &HashMap<String,&Transaction>, spent_by: &mut HashMap<String,String>, output_state: &F) -> Result<Amount,TransactionError>
+    where F: Fn(&str, i32) -> Result<OutputState,io::Error>{
+        let tranc_id = tx.tranc_id.clone();
+        let verification = |e: String| TransactionError::VerificationFailed{ tranc_id: tranc_id.clone(), reason: e };
+        let mut spent: Vec<TrancOutput> = Vec::new();//outputs spent by the inputs, in input order
+        let mut inputs = Amount::ZERO;
+        for (input, vin) in tx.vin.iter().enumerate(){
+            let outpoint = Self::utxo_key(&vin.from, vin.vout);
+            if let Some(first) = spent_by.insert(outpoint.clone(), tranc_id.clone()){
+                return Err(TransactionError::ConflictingSpend{ tranc_id, input, outpoint, spent_by: first });
             }
+            //the output comes from an earlier transaction of the block or from the chain, along with the height of a coinbase creating it
+            let (output, coinbase_height) = match block_txs.get(&vin.from){
+                Some(parent) => (usize::try_from(vin.vout).ok().and_then(|index| parent.vout.get(index)).cloned(), parent.is_coinbase().then_some(height)),
+                None => match output_state(&vin.from, vin.vout).map_err(|e| verification(e.to_string()))?{
+                    OutputState::Unspent(unspent) => (Some(unspent.output), unspent.coinbase.then_some(unspent.height)),
+                    OutputState::Spent => return Err(TransactionError::DoubleSpend{ tranc_id, input, outpoint }),
+                    OutputState::Missing => (None, None),
+                },
+            };
+            let output = output.ok_or_else(|| TransactionError::MissingOutput{ tranc_id: tranc_id.clone(), input, outpoint: outpoint.clone() })?;
+            inputs = inputs.checked_add(output.value).ok_or_else(|| TransactionError::ValueOverflow{ tranc_id: tranc_id.clone() })?;
+            if let Some(coinbase_height) = coinbase_height.filter(|coinbase_height| !self.rewards.is_mature(*coinbase_height, height)){
+                return Err(TransactionError::ImmatureCoinbase{ tranc_id, input, outpoint, coinbase_height });
+            }
+            spent.push(output);
+        }
+        match tx.verify_outputs(&spent){
+            Ok(true) => (),
+            Ok(false) => return Err(TransactionError::InvalidSignature{ tranc_id }),
+            Err(e) => return Err(verification(e.to_string())),
         }
-        Err(format_err!("Transaction not found"))
+        if let Some(output) = tx.vout.iter().position(|out| out.value.is_zero()){
+            return Err(TransactionError::ZeroOutput{ tranc_id, output });
+        }
+        let outputs = Amount::checked_sum(tx.vout.iter().map(|out| out.value)).ok_or_else(|| TransactionError::ValueOverflow{ tranc_id: tranc_id.clone() })?;
+        inputs.checked_sub(outputs).ok_or(TransactionError::InputsBelowOutputs{ tranc_id, inputs, outputs })
     }
 
-    //* function to find and give previous transactions */
-    fn get_previus_txs(&self, tx:&Transaction) -> Result<HashMap<String, Transaction>,io::Error>{
-        let mut prev_txs = HashMap::new();
-        for vin in &tx.vin{
-            let prev_tx = self.find_transaction(&vin.from).unwrap();
-            prev_txs.insert(prev_tx.tranc_id.clone(), prev_tx);
+    //* function to run the consensus checks on a transaction waiting for the next block, returning its fee */
+    /// It may spend confirmed outputs and outputs of other pending transactions, as if those came before it in the block.
+    /// pending: other transactions waiting for the next block by id
+    pub fn check_pending(&self, tx: &Transaction, pending: &HashMap<String,&Transaction>) -> Result<Amount,TransactionError>{
+        Self::check_id(tx)?;
+        if tx.is_coinbase(){
+            return Err(TransactionError::CoinbaseNotAllowed{ tranc_id: tx.tranc_id.clone() });
         }
-        Ok(prev_txs)
+        let height = self.get_best_height()
+            .map_err(|e| TransactionError::VerificationFailed{ tranc_id: tx.tranc_id.clone(), reason: e.to_string() })? + 1;
+        self.check_spend(height, tx, pending, &mut HashMap::new(), &|tranc_id, vout| self.output_state(tranc_id, vout))
     }
 
-    //* function to sign a transaction for auth */
-    pub fn sign_transaction(&self, tx: &mut Transaction, private_key: &[u8]) -> Result<(),io::Error>{
-        let prev_txs = self.get_previus_txs(tx)?;
-        tx.sign(private_key, prev_txs).unwrap();
-        Ok(())
+    //* function to get an output of the UTXO set */
+    pub fn unspent_output(&self, tranc_id: &str, vout: i32) -> Result<Option<UnspentOutput>,io::Error>{
+        match self.utxo.get(Self::utxo_key(tranc_id, vout))?{
+            Some(data) => bincode::deserialize::<UnspentOutput>(&data)
+                .map(Some)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string())),
+            None => Ok(None),
+        }
     }
 
-    //* function to verify a signed transaction with the public key */
-    pub fn verify_transaction(&self, tx: &mut Transaction) -> Result<bool,failure::Error>{
-        let prev_txs = self.get_previus_txs(tx)?;
-        tx.verify(prev_txs)
+    //* function to get the state of a confirmed output from the UTXO set and the spent records */
+    fn output_state(&self, tranc_id: &str, vout: i32) -> Result<OutputState,io::Error>{
+        if let Some(unspent) = self.unspent_output(tranc_id, vout)?{
+            return Ok(OutputState::Unspent(unspent));
+        }
+        let spent: Vec<i32> = match self.spent.get(tranc_id)?{
+            Some(value) => serde_json::from_slice(&value)?,
+            None => Vec::new(),
+        };
+        Ok(if spent.contains(&vout) { OutputState::Spent } else { OutputState::Missing })
     }
+
 }
 
 //?interator implementation
@@ -237,37 +1896,1065 @@ impl<'a> Iterator for BlockchainIterator<'a>{
     type Item = Block;
 
     fn next(&mut self) -> Option<Self::Item>{
-        if let Ok(encoded_block) = self.blockchain.db.get(&self.inst_hash){
-            match encoded_block{
-                Some(data)=>{
-                    if let Ok(block) = bincode::deserialize::<Block>(&data){
-                        if block.get_previus_hash()==""{
-                            self.inst_hash.clear();
-                            return Some(block);
-                        }
-                        self.inst_hash = block.get_previus_hash();
-                        return Some(block);
-                    }
+        if let Ok(Some(data)) = self.blockchain.db.get(&self.inst_hash){
+            if let Ok(block) = bincode::deserialize::<Block>(&data){
+                if block.get_previus_hash().is_empty(){
+                    self.inst_hash.clear();
+                    return Some(block);
                 }
-                None => ()
+                self.inst_hash = block.get_previus_hash();
+                return Some(block);
             }
         }
         None 
     }
 }
 
+//?header interator implementation
+impl Iterator for HeaderIterator<'_>{
+    type Item = (String, BlockHeader);
+
+    fn next(&mut self) -> Option<Self::Item>{
+        if self.inst_hash.is_empty(){
+            return None;
+        }
+        let header = self.blockchain.get_header_by_hash(&self.inst_hash).ok()??;
+        let hash = std::mem::replace(&mut self.inst_hash, header.prev_block_hash.clone());
+        Some((hash, header))
+    }
+}
+
 // testing code
 
 #[cfg(test)]
 mod tests{
     use super::*;
+    use crate::miner::difficulty::INITIAL_DIFFICULTY;
+    use crate::miner::pow::CancelToken;
+    use crate::miner::reward::INITIAL_SUBSIDY;
+    use crate::test_utils::{coins, reward, spend, spend_to, test_keys, test_rewards, units, TempDir};
+    use crate::miner::mempool::Mempool;
+    use crate::wallet::selection::{CoinSelector, InOrder, LargestFirst, SmallestFirst};
+    use crate::wallet::transaction::{Fee, SpendOptions};
+
+    //* helper to open an empty blockchain in a temporary directory */
+    fn temp_chain(dir: &TempDir) -> Blockchain{
+        let db = sled::open(dir.path().join("blocks")).unwrap();
+        Blockchain::from_db(db, String::new(), dir.path(), test_rewards()).unwrap()
+    }
+
+    //* helper to drop a chain and open it again from the same database handle, as `new` does after a restart */
+    //reopening the directory right away could fail: sled releases its file lock from background threads
+    fn reopen(chain: Blockchain) -> Result<Blockchain,io::Error>{
+        let (db, data_dir) = (chain.db.clone(), chain.data_dir.clone());
+        drop(chain);
+        Blockchain::open(db, &data_dir)
+    }
+
+    //* helper to store a block under a key and make it the tip, without any checks */
+    fn push_block(chain: &mut Blockchain, key: &str, block: &Block){
+        chain.db.insert(key, serialize(block).unwrap()).unwrap();
+        chain.heights.insert(Blockchain::height_key(block.get_height()), key.as_bytes()).unwrap();
+        chain.headers.insert(key, serialize(block.get_header()).unwrap()).unwrap();
+        chain.update_utxo(block).unwrap();
+        chain.db.insert("LAST", key.as_bytes()).unwrap();
+        chain.inst_hash = key.to_string();
+    }
+
+    //* helper to get the consensus error out of a refused block */
+    fn rejection(e: io::Error) -> TransactionError{
+        *e.into_inner().unwrap().downcast::<TransactionError>().unwrap()
+    }
+
+    fn balance(chain: &Blockchain, pub_key_hash: &[u8]) -> Amount{
+        Amount::checked_sum(chain.find_utxo(pub_key_hash.to_vec()).iter().map(|out| out.value)).unwrap()
+    }
+
+    #[test]
+    fn test_validate_chain_accepts_signed_spend(){
+        let (secret, public, address) = test_keys(1);
+        let (_, _, receiver) = test_keys(2);
+        let dir = TempDir::new();
+        let mut chain = temp_chain(&dir);
+        let coinbase = Transaction::new_reward(address, String::new(), INITIAL_SUBSIDY).unwrap();
+        let genesis = Block::default(coinbase.clone());
+        push_block(&mut chain, &genesis.get_hash(), &genesis);
+        let tx = spend(&coinbase, 0, &secret, &public, &receiver, 100);
+        let block = Block::new(vec![reward(1), tx], genesis.get_hash(), 1, INITIAL_DIFFICULTY).unwrap();
+        push_block(&mut chain, &block.get_hash(), &block);
+        assert!(chain.validate_chain().is_ok());
+    }
+
+    #[test]
+    fn test_validate_chain_rejects_forged_signature(){
+        let (_, public, address) = test_keys(1);
+        let (forger, _, receiver) = test_keys(2);
+        let dir = TempDir::new();
+        let mut chain = temp_chain(&dir);
+        let coinbase = Transaction::new_reward(address, String::new(), INITIAL_SUBSIDY).unwrap();
+        let genesis = Block::default(coinbase.clone());
+        push_block(&mut chain, &genesis.get_hash(), &genesis);
+        let tx = spend(&coinbase, 0, &forger, &public, &receiver, 100);
+        let block = Block::new(vec![reward(1), tx], genesis.get_hash(), 1, INITIAL_DIFFICULTY).unwrap();
+        push_block(&mut chain, &block.get_hash(), &block);
+        let err = chain.validate_chain().unwrap_err();
+        assert_eq!(err.height, Some(1));
+        assert!(err.reason.starts_with("INVALID_SIGNATURE"));
+    }
+
+    #[test]
+    fn test_validate_chain_rejects_tampered_transaction_id(){
+        let (_, _, address) = test_keys(1);
+        let dir = TempDir::new();
+        let mut chain = temp_chain(&dir);
+        let mut coinbase = Transaction::new_reward(address, String::new(), INITIAL_SUBSIDY).unwrap();
+        coinbase.tranc_id = "00".repeat(32);
+        let genesis = Block::default(coinbase);
+        push_block(&mut chain, &genesis.get_hash(), &genesis);
+        let err = chain.validate_chain().unwrap_err();
+        assert_eq!(err.height, Some(0));
+        assert!(err.reason.starts_with("TRANSACTION_ID_MISMATCH"));
+    }
+
+    #[test]
+    fn test_validate_chain_rejects_broken_link(){
+        let (_, _, address) = test_keys(1);
+        let dir = TempDir::new();
+        let mut chain = temp_chain(&dir);
+        let genesis = Block::default(Transaction::new_reward(address.clone(), String::new(), INITIAL_SUBSIDY).unwrap());
+        push_block(&mut chain, "not-the-genesis-hash", &genesis);
+        let block = Block::new(vec![Transaction::new_reward(address, String::from("second"), INITIAL_SUBSIDY).unwrap()], String::from("not-the-genesis-hash"), 1, INITIAL_DIFFICULTY).unwrap();
+        push_block(&mut chain, &block.get_hash(), &block);
+        let err = chain.validate_chain().unwrap_err();
+        assert_eq!(err.height, Some(1));
+        assert!(err.reason.starts_with("PREV_HASH_MISMATCH"));
+    }
+
+    #[test]
+    fn test_validate_chain_reports_missing_block(){
+        let (_, _, address) = test_keys(1);
+        let dir = TempDir::new();
+        let mut chain = temp_chain(&dir);
+        let block = Block::new(vec![Transaction::new_reward(address, String::new(), INITIAL_SUBSIDY).unwrap()], String::from("missing"), 1, INITIAL_DIFFICULTY).unwrap();
+        push_block(&mut chain, &block.get_hash(), &block);
+        let err = chain.validate_chain().unwrap_err();
+        assert_eq!(err.height, None);
+        assert_eq!(err.hash, "missing");
+    }
+
+    #[test]
+    fn test_open_validates_only_what_it_rebuilds(){
+        let (_, _, address) = test_keys(1);
+        let dir = TempDir::new();
+        let mut chain = Blockchain::create_blockchain_with(address.clone(), dir.path(), test_rewards()).unwrap();
+        chain.add_block(vec![reward(1)]).unwrap();
+        //a tip whose body no longer matches its header
+        let tampered = child_block(&chain.get_block_by_height(0).unwrap().unwrap(), Vec::new());
+        chain.db.insert(chain.inst_hash.as_bytes(), serialize(&tampered).unwrap()).unwrap();
+        let chain = reopen(chain).unwrap();
+        assert!(chain.validate_chain().unwrap_err().reason.starts_with("HEADER_MISMATCH"));
+
+        //but the indexes aren't rebuilt from it
+        chain.db.remove(UTXO_TIP).unwrap();
+        let Err(err) = reopen(chain) else { panic!("indexes were rebuilt from a corrupted chain") };
+        assert!(err.to_string().contains("HEADER_MISMATCH"), "{}", err);
+    }
+
+    #[test]
+    fn test_difficulty_retargets_and_is_enforced(){
+        let (_, _, address) = test_keys(1);
+        let dir = TempDir::new();
+        let mut chain = Blockchain::create_blockchain_with(address.clone(), dir.path(), test_rewards()).unwrap();
+        for i in 1..=RETARGET_INTERVAL{
+            let coinbase = Transaction::new_reward(address.clone(), format!("block {}", i), INITIAL_SUBSIDY).unwrap();
+            chain.add_block(vec![coinbase]).unwrap();
+        }
+        //blocks came much faster than the target time, so the work went up by the maximum step
+        let tip = chain.iter().next().unwrap();
+        assert_eq!(tip.get_difficulty(), INITIAL_DIFFICULTY + 2);
+        assert_eq!(chain.next_difficulty().unwrap(), INITIAL_DIFFICULTY + 2);
+        assert!(chain.validate_chain().is_ok());
+
+        //a block that picks its own easier difficulty is rejected
+        let coinbase = Transaction::new_reward(address, String::from("too easy"), INITIAL_SUBSIDY).unwrap();
+        let block = Block::new(vec![coinbase], tip.get_hash(), tip.get_height() + 1, 1).unwrap();
+        push_block(&mut chain, &block.get_hash(), &block);
+        let err = chain.validate_chain().unwrap_err();
+        assert_eq!(err.height, Some(RETARGET_INTERVAL + 1));
+        assert!(err.reason.starts_with("DIFFICULTY_MISMATCH"));
+    }
+
+    //* helper to mine a block on top of a parent with a timestamp of at least `timestamp` */
+    fn timed_block(parent: &Block, timestamp: u128) -> Block{
+        let height = parent.get_height() + 1;
+        match Block::mine(vec![reward(height)], parent.get_hash(), height, INITIAL_DIFFICULTY, timestamp, &Miner::default()).unwrap(){
+            MinedBlock::Mined(block, _) => block,
+            MinedBlock::Stopped(reason, _) => panic!("mining stopped: {}", reason),
+        }
+    }
+
+    #[test]
+    fn test_block_timestamps_are_bounded(){
+        let (_, _, address) = test_keys(1);
+        let dir = TempDir::new();
+        let mut chain = Blockchain::create_blockchain_with(address, dir.path(), test_rewards()).unwrap();
+        let genesis = chain.get_block_by_height(0).unwrap().unwrap();
+        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_millis();
+
+        //a block too far ahead of the clock is refused without being marked invalid, it may be fine later
+        let ahead = timed_block(&genesis, now + MAX_FUTURE_DRIFT + 60_000);
+        let err = chain.submit_block(ahead.clone()).unwrap_err();
+        assert!(err.to_string().contains("TIME_TOO_NEW"), "{}", err);
+        assert!(!chain.invalid.contains_key(ahead.get_hash()).unwrap());
+        assert!(chain.submit_headers(&[ahead.get_header().clone()]).unwrap_err().to_string().contains("TIME_TOO_NEW"));
+
+        //blocks within the drift move the median time past forward
+        let first = timed_block(&genesis, now + 60_000);
+        let second = timed_block(&first, now + 60_000);
+        chain.submit_block(first).unwrap();
+        chain.submit_block(second.clone()).unwrap();
+        let template = chain.block_template(&test_keys(2).2, Vec::new()).unwrap();
+        assert!(template.min_timestamp >= now + 60_000);
+
+        //a block going back below the median is refused
+        let late = timed_block(&second, 0);
+        let err = chain.submit_block(late.clone()).unwrap_err();
+        assert!(err.to_string().contains("TIME_TOO_OLD"), "{}", err);
+
+        //and so is a stored chain holding one
+        push_block(&mut chain, &late.get_hash(), &late);
+        let err = chain.validate_chain().unwrap_err();
+        assert_eq!(err.height, Some(3));
+        assert!(err.reason.starts_with("TIME_TOO_OLD"), "{}", err.reason);
+    }
+
+    #[test]
+    fn test_blocks_are_found_by_height_and_hash(){
+        let (_, _, address) = test_keys(1);
+        let dir = TempDir::new();
+        let mut chain = Blockchain::create_blockchain_with(address.clone(), dir.path(), test_rewards()).unwrap();
+        for i in 1..=3{
+            chain.add_block(vec![Transaction::new_reward(address.clone(), format!("block {}", i), INITIAL_SUBSIDY).unwrap()]).unwrap();
+        }
+        assert_eq!(chain.get_best_height().unwrap(), 3);
+        for height in 0..=3{
+            let block = chain.get_block_by_height(height).unwrap().unwrap();
+            assert_eq!(block.get_height(), height);
+            assert_eq!(chain.get_block_by_hash(&block.get_hash()).unwrap().unwrap().get_hash(), block.get_hash());
+        }
+        assert!(chain.get_block_by_height(4).unwrap().is_none());
+        assert!(chain.get_block_by_hash("unknown").unwrap().is_none());
+
+        //the index is rebuilt from the blocks when it's lost
+        chain.heights.clear().unwrap();
+        chain.reindex_heights().unwrap();
+        assert_eq!(chain.get_block_by_height(3).unwrap().unwrap().get_hash(), chain.inst_hash);
+    }
+
+    #[test]
+    fn test_header_chain_needs_no_transactions(){
+        let (_, _, address) = test_keys(1);
+        let dir = TempDir::new();
+        let mut chain = Blockchain::create_blockchain_with(address.clone(), dir.path(), test_rewards()).unwrap();
+        for i in 1..=3{
+            chain.add_block(vec![Transaction::new_reward(address.clone(), format!("block {}", i), INITIAL_SUBSIDY).unwrap()]).unwrap();
+        }
+        let tip = chain.get_block_by_hash(&chain.inst_hash).unwrap().unwrap();
+        assert_eq!(&chain.get_header_by_height(3).unwrap().unwrap(), tip.get_header());
+        let hashes: Vec<String> = chain.iter_headers().map(|(hash, _)| hash).collect();
+        assert_eq!(hashes, chain.iter().map(|block| block.get_hash()).collect::<Vec<String>>());
+
+        //dropping every body leaves the header chain walkable and valid
+        let bodies: Vec<String> = chain.iter().map(|block| block.get_hash()).collect();
+        for hash in &bodies{
+            chain.db.remove(hash).unwrap();
+        }
+        assert_eq!(chain.iter_headers().count(), 4);
+        assert_eq!(chain.get_best_height().unwrap(), 3);
+        assert!(chain.check_header_chain().is_ok());
+        assert!(chain.validate_chain().unwrap_err().reason.starts_with("MISSING_BLOCK"));
+    }
+
+    #[test]
+    fn test_header_chain_detects_tampering(){
+        let (_, _, address) = test_keys(1);
+        let dir = TempDir::new();
+        let mut chain = Blockchain::create_blockchain_with(address.clone(), dir.path(), test_rewards()).unwrap();
+        chain.add_block(vec![Transaction::new_reward(address, String::from("second"), INITIAL_SUBSIDY).unwrap()]).unwrap();
+        let mut header = chain.get_header_by_height(1).unwrap().unwrap();
+        header.merkle_root = String::from("00");
+        chain.headers.insert(chain.inst_hash.as_bytes(), serialize(&header).unwrap()).unwrap();
+        let err = chain.check_header_chain().unwrap_err();
+        assert_eq!(err.height, Some(1));
+
+        //the header tree is rebuilt from the blocks
+        chain.reindex_headers().unwrap();
+        assert!(chain.validate_chain().is_ok());
+    }
+
+    #[test]
+    fn test_stopped_mining_adds_nothing(){
+        let (_, _, address) = test_keys(1);
+        let dir = TempDir::new();
+        let mut chain = Blockchain::create_blockchain_with(address.clone(), dir.path(), test_rewards()).unwrap();
+        let tip = chain.inst_hash.clone();
+        let cancel = CancelToken::new();
+        cancel.cancel();
+        chain.set_miner(Miner::new(1).with_cancel(cancel));
+        let err = chain.add_block(vec![Transaction::new_reward(address.clone(), String::from("second"), INITIAL_SUBSIDY).unwrap()]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Interrupted);
+        assert_eq!(chain.inst_hash, tip);
+        assert_eq!(chain.get_best_height().unwrap(), 0);
+
+        //a fresh miner mines the block as usual
+        chain.set_miner(Miner::new(1));
+        assert!(chain.add_block(vec![Transaction::new_reward(address, String::from("second"), INITIAL_SUBSIDY).unwrap()]).unwrap().hashes > 0);
+        assert_eq!(chain.get_best_height().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_add_block_refuses_invalid_transactions(){
+        let (secret, public, address) = test_keys(1);
+        let (thief_secret, thief_public, thief) = test_keys(3);
+        let dir = TempDir::new();
+        let mut chain = temp_chain(&dir);
+        let coinbase = Transaction::new_reward(address.clone(), String::new(), INITIAL_SUBSIDY).unwrap();
+        let genesis = Block::default(coinbase.clone());
+        push_block(&mut chain, &genesis.get_hash(), &genesis);
+
+        //the thief signs with its own key
+        let stolen = spend(&coinbase, 0, &thief_secret, &thief_public, &thief, 100);
+        let err = chain.add_block(vec![reward(1), stolen.clone()]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(rejection(err), TransactionError::InvalidSignature{ tranc_id: stolen.tranc_id });
+        //a tampered output after signing
+        let mut tampered = spend(&coinbase, 0, &secret, &public, &address, 100);
+        tampered.vout[0].pub_key_hash = TrancOutput::new(coins(100), thief.clone()).unwrap().pub_key_hash;
+        assert!(matches!(rejection(chain.add_block(vec![reward(1), tampered.clone()]).unwrap_err()), TransactionError::IdMismatch{ .. }));
+        tampered.tranc_id = tampered.hash().unwrap();
+        assert!(matches!(rejection(chain.add_block(vec![reward(1), tampered]).unwrap_err()), TransactionError::InvalidSignature{ .. }));
+        assert_eq!(chain.get_best_height().unwrap(), 0);
+
+        //the owner's spend goes through
+        chain.add_block(vec![reward(1), spend(&coinbase, 0, &secret, &public, &thief, 100)]).unwrap();
+        assert_eq!(chain.get_best_height().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_add_block_refuses_double_spends(){
+        let (secret, public, address) = test_keys(1);
+        let (_, _, receiver) = test_keys(2);
+        let dir = TempDir::new();
+        let mut chain = temp_chain(&dir);
+        let coinbase = Transaction::new_reward(address.clone(), String::new(), INITIAL_SUBSIDY).unwrap();
+        let genesis = Block::default(coinbase.clone());
+        push_block(&mut chain, &genesis.get_hash(), &genesis);
+        let outpoint = Blockchain::utxo_key(&coinbase.tranc_id, 0);
+
+        //the same output spent twice in one block
+        let first = spend(&coinbase, 0, &secret, &public, &receiver, 100);
+        let second = spend(&coinbase, 0, &secret, &public, &address, 100);
+        assert_eq!(rejection(chain.add_block(vec![reward(1), first.clone(), second.clone()]).unwrap_err()), TransactionError::ConflictingSpend{
+            tranc_id: second.tranc_id.clone(), input: 0, outpoint: outpoint.clone(), spent_by: first.tranc_id.clone(),
+        });
+        //spent in a block, then again in the next one
+        chain.add_block(vec![reward(1), first]).unwrap();
+        assert_eq!(rejection(chain.add_block(vec![reward(2), second.clone()]).unwrap_err()), TransactionError::DoubleSpend{
+            tranc_id: second.tranc_id, input: 0, outpoint,
+        });
+        assert_eq!(chain.get_best_height().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_add_block_refuses_missing_outputs_and_overspending(){
+        let (secret, public, address) = test_keys(1);
+        let (_, _, receiver) = test_keys(2);
+        let dir = TempDir::new();
+        let mut chain = temp_chain(&dir);
+        let coinbase = Transaction::new_reward(address.clone(), String::new(), INITIAL_SUBSIDY).unwrap();
+        let genesis = Block::default(coinbase.clone());
+        push_block(&mut chain, &genesis.get_hash(), &genesis);
+
+        //an output index the transaction doesn't have
+        let mut bad_index = spend(&coinbase, 0, &secret, &public, &receiver, 100);
+        bad_index.vin[0].vout = 1;
+        bad_index.tranc_id = bad_index.hash().unwrap();
+        assert_eq!(rejection(chain.add_block(vec![reward(1), bad_index.clone()]).unwrap_err()), TransactionError::MissingOutput{
+            tranc_id: bad_index.tranc_id, input: 0, outpoint: Blockchain::utxo_key(&coinbase.tranc_id, 1),
+        });
+        //an output of a transaction that isn't in the chain
+        let unknown = spend(&Transaction::new_reward(address.clone(), String::from("unknown"), INITIAL_SUBSIDY).unwrap(), 0, &secret, &public, &receiver, 100);
+        assert!(matches!(rejection(chain.add_block(vec![reward(1), unknown]).unwrap_err()), TransactionError::MissingOutput{ input: 0, .. }));
+        //paying more than the spent output holds
+        let overspend = spend(&coinbase, 0, &secret, &public, &receiver, 101);
+        assert_eq!(rejection(chain.add_block(vec![reward(1), overspend.clone()]).unwrap_err()), TransactionError::InputsBelowOutputs{
+            tranc_id: overspend.tranc_id, inputs: coins(100), outputs: coins(101),
+        });
+        //empty outputs and values adding up past any amount
+        let empty = spend(&coinbase, 0, &secret, &public, &receiver, 0);
+        assert_eq!(rejection(chain.add_block(vec![reward(1), empty.clone()]).unwrap_err()), TransactionError::ZeroOutput{ tranc_id: empty.tranc_id, output: 0 });
+        let overflow = spend_to(&coinbase, 0, &secret, &public, vec![
+            TrancOutput::new(Amount::MAX, receiver.clone()).unwrap(), TrancOutput::new(coins(1), receiver.clone()).unwrap(),
+        ]);
+        assert_eq!(rejection(chain.add_block(vec![reward(1), overflow.clone()]).unwrap_err()), TransactionError::ValueOverflow{ tranc_id: overflow.tranc_id });
+        assert_eq!(chain.get_best_height().unwrap(), 0);
+
+        //a child spending its parent in the same block is fine, in that order only
+        let parent = spend(&coinbase, 0, &secret, &public, &address, 100);
+        let child = spend(&parent, 0, &secret, &public, &receiver, 100);
+        assert!(matches!(rejection(chain.add_block(vec![reward(1), child.clone(), parent.clone()]).unwrap_err()), TransactionError::MissingOutput{ .. }));
+        chain.add_block(vec![reward(1), parent, child]).unwrap();
+        assert!(chain.validate_chain().is_ok());
+    }
+
+    #[test]
+    fn test_blocks_need_exactly_one_leading_coinbase(){
+        let (secret, public, address) = test_keys(1);
+        let (_, _, receiver) = test_keys(2);
+        let dir = TempDir::new();
+        let mut chain = Blockchain::create_blockchain_with(address, dir.path(), test_rewards()).unwrap();
+        let coinbase = chain.iter().next().unwrap().get_transaction()[0].clone();
+        let tx = spend(&coinbase, 0, &secret, &public, &receiver, 100);
+
+        assert_eq!(rejection(chain.add_block(vec![tx.clone()]).unwrap_err()), TransactionError::MissingCoinbase{ tranc_id: tx.tranc_id.clone() });
+        assert_eq!(rejection(chain.add_block(Vec::new()).unwrap_err()), TransactionError::MissingCoinbase{ tranc_id: String::new() });
+        assert_eq!(rejection(chain.add_block(vec![tx.clone(), reward(1)]).unwrap_err()), TransactionError::MissingCoinbase{ tranc_id: tx.tranc_id.clone() });
+        let second = reward(2);
+        assert_eq!(rejection(chain.add_block(vec![reward(1), tx.clone(), second.clone()]).unwrap_err()), TransactionError::MisplacedCoinbase{ tranc_id: second.tranc_id, position: 2 });
+        assert_eq!(chain.get_best_height().unwrap(), 0);
+        chain.add_block(vec![reward(1), tx]).unwrap();
+    }
+
+    #[test]
+    fn test_coinbase_pays_at_most_subsidy_and_fees(){
+        let (secret, public, address) = test_keys(1);
+        let (_, _, receiver) = test_keys(2);
+        let (_, _, miner) = test_keys(9);
+        let dir = TempDir::new();
+        let schedule = RewardSchedule::new(coins(40), 2, 1).unwrap();
+        let mut chain = Blockchain::create_blockchain_with(address.clone(), dir.path(), schedule).unwrap();
+        let genesis_coinbase = chain.iter().next().unwrap().get_transaction()[0].clone();
+        assert_eq!(genesis_coinbase.vout[0].value, coins(40));
+
+        //height 1: subsidy 40, the spend leaves a fee of 15
+        let tx = spend(&genesis_coinbase, 0, &secret, &public, &receiver, 25);
+        let greedy = Transaction::new_reward(miner.clone(), String::from("greedy"), coins(56)).unwrap();
+        assert_eq!(rejection(chain.add_block(vec![greedy.clone(), tx.clone()]).unwrap_err()), TransactionError::ExcessiveReward{
+            tranc_id: greedy.tranc_id, paid: coins(56), allowed: coins(55),
+        });
+        chain.add_block(vec![Transaction::new_reward(miner.clone(), String::from("fair"), coins(55)).unwrap(), tx]).unwrap();
+        //height 2: the subsidy halved
+        let early = Transaction::new_reward(miner.clone(), String::from("early"), coins(40)).unwrap();
+        assert!(matches!(rejection(chain.add_block(vec![early]).unwrap_err()), TransactionError::ExcessiveReward{ allowed, .. } if allowed == coins(20)));
+        chain.add_block(vec![Transaction::new_reward(miner, String::from("halved"), coins(20)).unwrap()]).unwrap();
+        assert!(chain.validate_chain().is_ok());
+
+        //the schedule is read back from the genesis block
+        assert_eq!(reopen(chain).unwrap().rewards, schedule);
+    }
+
+    #[test]
+    fn test_older_storage_formats_are_refused(){
+        let (_, _, address) = test_keys(1);
+        let dir = TempDir::new();
+        let chain = Blockchain::create_blockchain_with(address, dir.path(), test_rewards()).unwrap();
+        let db = chain.db.clone();
+        let chain = reopen(chain).unwrap();
+
+        //chains written before the format key stored amounts as i32 coins
+        db.remove(FORMAT_KEY).unwrap();
+        let Err(err) = reopen(chain) else { panic!("a chain of format 0 was opened") };
+        assert!(err.to_string().starts_with("UNSUPPORTED_FORMAT"), "{}", err);
+        //and later formats can't be read either
+        db.insert(FORMAT_KEY, &(FORMAT_VERSION + 1).to_be_bytes()).unwrap();
+        assert!(Blockchain::open(db.clone(), dir.path()).is_err());
+        db.insert(FORMAT_KEY, &FORMAT_VERSION.to_be_bytes()).unwrap();
+        assert!(Blockchain::open(db, dir.path()).is_ok());
+    }
+
+    #[test]
+    fn test_reward_schedule_comes_from_genesis(){
+        let (_, _, address) = test_keys(1);
+        let (dir, joined_dir) = (TempDir::new(), TempDir::new());
+        let schedule = RewardSchedule::new(coins(40), 2, 3).unwrap();
+        let chain = Blockchain::create_blockchain_with(address, dir.path(), schedule).unwrap();
+        //a chain joining from the same genesis block follows the same rules
+        let joined = Blockchain::create_from_genesis(chain.get_block_by_height(0).unwrap().unwrap(), joined_dir.path()).unwrap();
+        assert_eq!(joined.rewards, schedule);
+
+        //a schedule stored by an older version has to agree with the genesis block
+        chain.db.insert(REWARDS_KEY, serialize(&RewardSchedule::default()).unwrap()).unwrap();
+        let Err(err) = reopen(chain) else { panic!("conflicting rules were accepted") };
+        assert!(err.to_string().starts_with("REWARDS_MISMATCH"), "{}", err);
+        let legacy = joined.db.clone();
+        legacy.insert(REWARDS_KEY, serialize(&schedule).unwrap()).unwrap();
+        let joined = reopen(joined).unwrap();
+        assert_eq!(joined.rewards, schedule);
+        assert!(!legacy.contains_key(REWARDS_KEY).unwrap());
+    }
+
+    #[test]
+    fn test_mined_blocks_pay_subsidy_and_fees(){
+        let (secret, public, address) = test_keys(1);
+        let (_, _, receiver) = test_keys(2);
+        let (_, _, miner) = test_keys(9);
+        let miner_hash = Address::decode(&miner).unwrap().body;
+        let dir = TempDir::new();
+        let mut chain = Blockchain::create_blockchain_with(address.clone(), dir.path(), test_rewards()).unwrap();
+        let coinbase = chain.iter().next().unwrap().get_transaction()[0].clone();
+
+        //a parent and its child, each leaving a fee
+        let parent = spend(&coinbase, 0, &secret, &public, &address, 90);
+        let child = spend(&parent, 0, &secret, &public, &receiver, 85);
+        assert_eq!(chain.fees_of(&[parent.clone(), child.clone()]).unwrap(), coins(15));
+        chain.mine_block(&miner, vec![parent, child]).unwrap();
+        let block = chain.iter().next().unwrap();
+        assert!(block.get_transaction()[0].is_coinbase());
+        assert_eq!(balance(&chain, &miner_hash), coins(115));
+
+        //an empty block pays the subsidy alone
+        chain.mine_block(&miner, Vec::new()).unwrap();
+        assert_eq!(balance(&chain, &miner_hash), coins(215));
+        assert!(chain.mine_block("not-an-address", Vec::new()).is_err());
+        assert!(chain.validate_chain().is_ok());
+    }
+
+    #[test]
+    fn test_coinbase_outputs_mature(){
+        let (secret, public, address) = test_keys(1);
+        let (_, _, receiver) = test_keys(2);
+        let dir = TempDir::new();
+        let mut chain = Blockchain::create_blockchain_with(address.clone(), dir.path(), RewardSchedule::new(coins(100), 1000, 3).unwrap()).unwrap();
+        let coinbase = chain.iter().next().unwrap().get_transaction()[0].clone();
+        let tx = spend(&coinbase, 0, &secret, &public, &receiver, 100);
+        for height in 1..3{
+            assert_eq!(rejection(chain.add_block(vec![reward(height), tx.clone()]).unwrap_err()), TransactionError::ImmatureCoinbase{
+                tranc_id: tx.tranc_id.clone(), input: 0, outpoint: Blockchain::utxo_key(&coinbase.tranc_id, 0), coinbase_height: 0,
+            });
+            chain.add_block(vec![reward(height)]).unwrap();
+        }
+        chain.add_block(vec![reward(3), tx]).unwrap();
+
+        //a coinbase can't be spent in its own block either
+        let own = reward(4);
+        let (miner_secret, miner_public, _) = test_keys(9);
+        let spend_own = spend(&own, 0, &miner_secret, &miner_public, &receiver, 100);
+        assert!(matches!(rejection(chain.add_block(vec![own, spend_own]).unwrap_err()), TransactionError::ImmatureCoinbase{ coinbase_height: 4, .. }));
+    }
+
+    #[test]
+    fn test_validate_chain_rejects_double_spend(){
+        let (secret, public, address) = test_keys(1);
+        let (_, _, receiver) = test_keys(2);
+        let dir = TempDir::new();
+        let mut chain = temp_chain(&dir);
+        let coinbase = Transaction::new_reward(address.clone(), String::new(), INITIAL_SUBSIDY).unwrap();
+        let genesis = Block::default(coinbase.clone());
+        push_block(&mut chain, &genesis.get_hash(), &genesis);
+        let first = Block::new(vec![reward(1), spend(&coinbase, 0, &secret, &public, &receiver, 100)], genesis.get_hash(), 1, INITIAL_DIFFICULTY).unwrap();
+        push_block(&mut chain, &first.get_hash(), &first);
+        let second = Block::new(vec![reward(2), spend(&coinbase, 0, &secret, &public, &address, 100)], first.get_hash(), 2, INITIAL_DIFFICULTY).unwrap();
+        push_block(&mut chain, &second.get_hash(), &second);
+        let err = chain.validate_chain().unwrap_err();
+        assert_eq!(err.height, Some(2));
+        assert!(err.reason.starts_with("DOUBLE_SPEND"));
+    }
+
+    #[test]
+    fn test_transaction_inclusion_proofs(){
+        let (secret, public, address) = test_keys(1);
+        let (_, _, receiver) = test_keys(2);
+        let dir = TempDir::new();
+        let mut chain = temp_chain(&dir);
+        let coinbase = Transaction::new_reward(address.clone(), String::new(), INITIAL_SUBSIDY).unwrap();
+        let genesis = Block::default(coinbase.clone());
+        push_block(&mut chain, &genesis.get_hash(), &genesis);
+        let reward = Transaction::new_reward(address, String::from("second"), INITIAL_SUBSIDY).unwrap();
+        let tx = spend(&coinbase, 0, &secret, &public, &receiver, 100);
+        chain.add_block(vec![reward.clone(), tx.clone()]).unwrap();
+
+        let (block_hash, proof) = chain.prove_transaction(&tx.tranc_id).unwrap().unwrap();
+        assert_eq!(block_hash, chain.inst_hash);
+        assert!(chain.verify_transaction_proof(&block_hash, &tx.tranc_id, &proof).unwrap());
+        assert!(!chain.verify_transaction_proof(&block_hash, &reward.tranc_id, &proof).unwrap());
+        assert!(!chain.verify_transaction_proof(&genesis.get_hash(), &tx.tranc_id, &proof).unwrap());
+        assert!(chain.prove_transaction("unknown").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_validate_chain_rejects_wrong_height(){
+        let (_, _, address) = test_keys(1);
+        let dir = TempDir::new();
+        let mut chain = temp_chain(&dir);
+        let genesis = Block::default(Transaction::new_reward(address.clone(), String::new(), INITIAL_SUBSIDY).unwrap());
+        push_block(&mut chain, &genesis.get_hash(), &genesis);
+        let block = Block::new(vec![Transaction::new_reward(address, String::from("second"), INITIAL_SUBSIDY).unwrap()], genesis.get_hash(), 5, INITIAL_DIFFICULTY).unwrap();
+        push_block(&mut chain, &block.get_hash(), &block);
+        let err = chain.validate_chain().unwrap_err();
+        assert_eq!(err.height, Some(1));
+        assert!(err.reason.starts_with("HEIGHT_MISMATCH"));
+    }
+
+    #[test]
+    fn test_utxo_set_follows_added_blocks(){
+        let (secret, public, address) = test_keys(1);
+        let (_, _, receiver) = test_keys(2);
+        let sender_hash = Address::decode(&address).unwrap().body;
+        let receiver_hash = Address::decode(&receiver).unwrap().body;
+        let dir = TempDir::new();
+        let mut chain = temp_chain(&dir);
+        let coinbase = Transaction::new_reward(address.clone(), String::new(), INITIAL_SUBSIDY).unwrap();
+        let genesis = Block::default(coinbase.clone());
+        push_block(&mut chain, &genesis.get_hash(), &genesis);
+        chain.reindex_utxo().unwrap();
+        assert_eq!(chain.find_utxo(sender_hash.clone()).len(), 1);
+
+        let tx = spend_to(&coinbase, 0, &secret, &public, vec![TrancOutput::new(coins(30), receiver).unwrap(), TrancOutput::new(coins(70), address).unwrap()]);
+        chain.add_block(vec![reward(1), tx.clone()]).unwrap();
+
+        assert_eq!(balance(&chain, &sender_hash), coins(70));
+        assert_eq!(balance(&chain, &receiver_hash), coins(30));
+        let pool = Mempool::open(&chain).unwrap();
+        let options = SpendOptions{ fee: Fee::Absolute(Amount::ZERO), selector: &InOrder, pending: &pool, unconfirmed: false };
+        let (accumulated, outputs) = options.find_spendable_outputs(&chain, &sender_hash, coins(50));
+        assert_eq!(accumulated, coins(70));
+        assert_eq!(outputs.get(&tx.tranc_id), Some(&vec![1]));
+
+        //rebuilding from blocks gives the same set
+        let before: Vec<_> = chain.utxo.iter().chain(chain.addresses.iter()).flatten().collect();
+        chain.reindex_utxo().unwrap();
+        let after: Vec<_> = chain.utxo.iter().chain(chain.addresses.iter()).flatten().collect();
+        assert_eq!(before, after);
+        assert_eq!(chain.addresses.len(), chain.utxo.len());
+        assert_eq!(chain.db.get(UTXO_TIP).unwrap().as_deref(), Some(chain.inst_hash.as_bytes()));
+    }
+
+    #[test]
+    fn test_spendable_outputs_avoid_dust_change(){
+        let (_, _, address) = test_keys(1);
+        let address_hash = Address::decode(&address).unwrap().body;
+        let dir = TempDir::new();
+        let mut chain = Blockchain::create_blockchain_with(address.clone(), dir.path(), RewardSchedule::new(units(5000), 1000, 1).unwrap()).unwrap();
+        chain.add_block(vec![Transaction::new_reward(address.clone(), String::from("second"), units(4998)).unwrap()]).unwrap();
+        let first = chain.get_block_by_height(0).unwrap().unwrap().get_transaction()[0].tranc_id.clone();
+        let second = chain.get_block_by_height(1).unwrap().unwrap().get_transaction()[0].tranc_id.clone();
+        let both = HashMap::from([(first.clone(), vec![0]), (second.clone(), vec![0])]);
+        let pool = Mempool::open(&chain).unwrap();
+        let select = |amount: u64, selector: &dyn CoinSelector| SpendOptions{ fee: Fee::Absolute(Amount::ZERO), selector, pending: &pool, unconfirmed: false }
+            .find_spendable_outputs(&chain, &address_hash, units(amount));
+
+        //with outputs of 5000 and 4998 units either one alone leaves dust for a target of 4997, whatever their order
+        assert_eq!(select(4997, &InOrder), (units(9998), both.clone()));
+        //a large enough change stops at one output
+        assert_eq!(select(4000, &LargestFirst), (units(5000), HashMap::from([(first.clone(), vec![0])])));
+        //so does an exact match
+        assert_eq!(select(4998, &SmallestFirst), (units(4998), HashMap::from([(second, vec![0])])));
+        //a change of 2 is dust, the next output is added for a change of 5000
+        assert_eq!(select(4998, &LargestFirst), (units(9998), both.clone()));
+        //dust is only left when there's nothing else
+        assert_eq!(select(9996, &InOrder), (units(9998), both));
+    }
+
+    #[test]
+    fn test_balance_queries_do_not_write_spent_records(){
+        let (secret, public, address) = test_keys(1);
+        let (_, _, receiver) = test_keys(2);
+        let sender_hash = Address::decode(&address).unwrap().body;
+        let dir = TempDir::new();
+        let mut chain = temp_chain(&dir);
+        let coinbase = Transaction::new_reward(address, String::new(), INITIAL_SUBSIDY).unwrap();
+        let genesis = Block::default(coinbase.clone());
+        push_block(&mut chain, &genesis.get_hash(), &genesis);
+        chain.rebuild_spent_records().unwrap();
+        chain.add_block(vec![reward(1), spend(&coinbase, 0, &secret, &public, &receiver, 100)]).unwrap();
+
+        let records: Vec<_> = chain.spent.iter().flatten().collect();
+        let spent = chain.spent.get(coinbase.tranc_id.as_bytes()).unwrap().unwrap();
+        assert_eq!(serde_json::from_slice::<Vec<i32>>(&spent).unwrap(), vec![0]);
+        for _ in 0..3{
+            assert!(chain.find_utxo(sender_hash.clone()).is_empty());
+            assert!(chain.spendable_coins(&sender_hash).is_empty());
+        }
+        assert_eq!(chain.spent.iter().flatten().collect::<Vec<_>>(), records);
+
+        //the migration derives the same records from the blocks
+        chain.rebuild_spent_records().unwrap();
+        assert_eq!(chain.spent.iter().flatten().collect::<Vec<_>>(), records);
+    }
+
+    #[test]
+    fn test_rebuilt_spent_records_drop_stale_ones(){
+        let (secret, public, address) = test_keys(1);
+        let (_, _, receiver) = test_keys(2);
+        let dir = TempDir::new();
+        let mut chain = Blockchain::create_blockchain_with(address, dir.path(), test_rewards()).unwrap();
+        let coinbase = chain.get_block_by_height(0).unwrap().unwrap().get_transaction()[0].clone();
+        chain.add_block(vec![reward(1), spend(&coinbase, 0, &secret, &public, &receiver, 100)]).unwrap();
+        let records: Vec<_> = chain.spent.iter().flatten().collect();
+
+        //duplicates and unknown outputs as written by older runs, plus their standalone store
+        chain.spent.insert(coinbase.tranc_id.as_bytes(), serde_json::to_vec(&vec![0, 0, 3]).unwrap()).unwrap();
+        chain.spent.insert("stale", serde_json::to_vec(&vec![1]).unwrap()).unwrap();
+        chain.db.remove(SPENT_TIP).unwrap();
+        let legacy = sled::open(dir.path().join(SPENT_TREE)).unwrap();
+        legacy.insert(coinbase.tranc_id.as_bytes(), serde_json::to_vec(&vec![0, 0]).unwrap()).unwrap();
+        legacy.flush().unwrap();
+        drop(legacy);
+
+        let chain = reopen(chain).unwrap();
+        assert_eq!(chain.spent.iter().flatten().collect::<Vec<_>>(), records);
+        assert!(!dir.path().join(SPENT_TREE).exists());
+    }
+
+    #[test]
+    fn test_data_dirs_hold_independent_chains(){
+        let (_, _, first) = test_keys(1);
+        let (_, _, second) = test_keys(2);
+        let (dev, test) = (TempDir::new(), TempDir::new());
+        let dev_chain = Blockchain::create_blockchain_with(first.clone(), dev.path(), test_rewards()).unwrap();
+        let test_chain = Blockchain::create_blockchain_with(second.clone(), test.path(), test_rewards()).unwrap();
+        assert_ne!(dev_chain.inst_hash, test_chain.inst_hash);
+
+        let dev_genesis = dev_chain.inst_hash.clone();
+        let dev_chain = reopen(dev_chain).unwrap();
+        assert_eq!(dev_chain.inst_hash, dev_genesis);
+        assert_eq!(dev_chain.data_dir(), dev.path());
+        assert_eq!(dev_chain.find_utxo(Address::decode(&first).unwrap().body).len(), 1);
+        assert!(dev_chain.find_utxo(Address::decode(&second).unwrap().body).is_empty());
+        assert!(Blockchain::new(&dev.path().join("missing")).is_err());
+    }
+
+    #[test]
+    fn test_address_index_is_built_for_older_databases(){
+        let (_, _, address) = test_keys(1);
+        let address_hash = Address::decode(&address).unwrap().body;
+        let dir = TempDir::new();
+        let chain = Blockchain::create_blockchain_with(address, dir.path(), test_rewards()).unwrap();
+        assert_eq!(chain.addresses.len(), 1);
+        chain.addresses.clear().unwrap();//as left by a version without the index
+        assert!(chain.find_utxo(address_hash.clone()).is_empty());
+        let chain = reopen(chain).unwrap();
+        assert_eq!(chain.find_utxo(address_hash).len(), 1);
+    }
+
+    //* helper to mine a block on top of a stored one, as another node would */
+    fn child_block(parent: &Block, transactions: Vec<Transaction>) -> Block{
+        let height = parent.get_height() + 1;
+        let mut block = vec![reward(height)];
+        block.extend(transactions);
+        Block::new(block, parent.get_hash(), height, INITIAL_DIFFICULTY).unwrap()
+    }
+
+    fn hashes(events: &[ChainEvent]) -> Vec<String>{
+        events.iter().map(|event| match event{
+            ChainEvent::Connected(block) => format!("+{}", block.get_hash()),
+            ChainEvent::Disconnected(block) => format!("-{}", block.get_hash()),
+            ChainEvent::Rejected(invalid) => format!("!{}", invalid.hash),
+        }).collect()
+    }
+
+    #[test]
+    fn test_heavier_branch_reorganizes_the_chain(){
+        let (secret, public, address) = test_keys(1);
+        let (_, _, receiver) = test_keys(2);
+        let sender_hash = Address::decode(&address).unwrap().body;
+        let receiver_hash = Address::decode(&receiver).unwrap().body;
+        let dir = TempDir::new();
+        let mut chain = Blockchain::create_blockchain_with(address.clone(), dir.path(), test_rewards()).unwrap();
+        let genesis = chain.get_block_by_height(0).unwrap().unwrap();
+        let coinbase = genesis.get_transaction()[0].clone();
+        let tx = spend(&coinbase, 0, &secret, &public, &receiver, 60);
+        chain.mine_block(&receiver, vec![tx.clone()]).unwrap();
+        let a1 = chain.get_block_by_height(1).unwrap().unwrap();
+
+        //a branch with as much work is stored but doesn't replace the active one
+        let b1 = child_block(&genesis, Vec::new());
+        assert!(chain.submit_block(b1.clone()).unwrap().is_empty());
+        assert_eq!(chain.iter().next().unwrap().get_hash(), a1.get_hash());
+        assert_eq!(chain.tips().unwrap().iter().map(|tip| (tip.hash.clone(), tip.active)).collect::<Vec<_>>(), vec![(a1.get_hash(), true), (b1.get_hash(), false)]);
+        assert!(chain.submit_block(b1.clone()).unwrap().is_empty());//known already
+
+        //more work moves the chain over, the spend going back to the UTXO set
+        let b2 = child_block(&b1, Vec::new());
+        let events = chain.submit_block(b2.clone()).unwrap();
+        assert_eq!(hashes(&events), vec![format!("-{}", a1.get_hash()), format!("+{}", b1.get_hash()), format!("+{}", b2.get_hash())]);
+        assert_eq!(chain.get_best_height().unwrap(), 2);
+        assert_eq!(chain.get_block_by_height(1).unwrap().unwrap().get_hash(), b1.get_hash());
+        assert_eq!(chain.get_work(&b2.get_hash()).unwrap(), Some(3 * block_work(INITIAL_DIFFICULTY)));
+        assert_eq!(balance(&chain, &sender_hash), coins(100));
+        assert_eq!(balance(&chain, &receiver_hash), Amount::ZERO);
+        assert!(chain.check_pending(&tx, &HashMap::new()).is_ok());
+        assert!(chain.find_transaction_with_height(&tx.tranc_id).unwrap().is_none());
+        assert!(chain.validate_chain().is_ok());
+
+        //the indexes match the new tip when opened again, the work ranking and transaction index being rebuilt for older databases
+        chain.ranked.clear().unwrap();
+        chain.transactions.clear().unwrap();
+        chain.db.remove(TX_TIP).unwrap();
+        let mut chain = reopen(chain).unwrap();
+        assert_eq!(chain.iter().next().unwrap().get_hash(), b2.get_hash());
+        assert_eq!(chain.ranked.len(), 4);
+
+        //and the first branch comes back once it gets ahead
+        let a2 = child_block(&a1, Vec::new());
+        assert!(chain.submit_block(a2.clone()).unwrap().is_empty());
+        let a3 = child_block(&a2, Vec::new());
+        let events = chain.submit_block(a3.clone()).unwrap();
+        assert_eq!(hashes(&events), vec![
+            format!("-{}", b2.get_hash()), format!("-{}", b1.get_hash()),
+            format!("+{}", a1.get_hash()), format!("+{}", a2.get_hash()), format!("+{}", a3.get_hash()),
+        ]);
+        assert_eq!(balance(&chain, &sender_hash), Amount::ZERO);
+        assert_eq!(balance(&chain, &receiver_hash), coins(200));//the payment and the reward of a1 with its fee
+        assert!(matches!(chain.output_state(&coinbase.tranc_id, 0).unwrap(), OutputState::Spent));
+        assert_eq!(chain.find_transaction_with_height(&tx.tranc_id).unwrap().map(|(found, height)| (found.tranc_id, height)), Some((tx.tranc_id.clone(), 1)));
+        assert_eq!(chain.find_transaction_with_height(&coinbase.tranc_id).unwrap().map(|(_, height)| height), Some(0));
+        assert!(chain.validate_chain().is_ok());
+    }
+
+    #[test]
+    fn test_invalid_branches_are_rejected(){
+        let (secret, public, address) = test_keys(1);
+        let (forger, _, receiver) = test_keys(2);
+        let dir = TempDir::new();
+        let mut chain = Blockchain::create_blockchain_with(address.clone(), dir.path(), test_rewards()).unwrap();
+        let genesis = chain.get_block_by_height(0).unwrap().unwrap();
+        let coinbase = genesis.get_transaction()[0].clone();
+        chain.mine_block(&receiver, vec![spend(&coinbase, 0, &secret, &public, &receiver, 60)]).unwrap();
+        let a1 = chain.get_block_by_height(1).unwrap().unwrap();
+
+        //headers are checked before anything is stored
+        let orphan = Block::new(vec![reward(5)], "00".repeat(32), 5, INITIAL_DIFFICULTY).unwrap();
+        assert_eq!(chain.submit_block(orphan).unwrap_err().kind(), io::ErrorKind::NotFound);
+        let too_easy = Block::new(vec![reward(1)], genesis.get_hash(), 1, 1).unwrap();
+        assert!(chain.submit_block(too_easy).unwrap_err().to_string().contains("DIFFICULTY_MISMATCH"));
+        let misplaced = Block::new(vec![reward(3)], genesis.get_hash(), 3, INITIAL_DIFFICULTY).unwrap();
+        assert!(chain.submit_block(misplaced).unwrap_err().to_string().contains("HEIGHT_MISMATCH"));
+
+        //transactions only when the branch gets connected, going back to the best valid one
+        let b1 = child_block(&genesis, vec![spend(&coinbase, 0, &forger, &public, &receiver, 60)]);
+        assert!(chain.submit_block(b1.clone()).unwrap().is_empty());
+        let b2 = child_block(&b1, Vec::new());
+        let events = chain.submit_block(b2.clone()).unwrap();
+        assert_eq!(hashes(&events), vec![format!("-{}", a1.get_hash()), format!("!{}", b1.get_hash()), format!("+{}", a1.get_hash())]);
+        assert!(matches!(&events[1], ChainEvent::Rejected(invalid) if invalid.reason.starts_with("INVALID_SIGNATURE")));
+        assert_eq!(chain.iter().next().unwrap().get_hash(), a1.get_hash());
+        assert!(chain.validate_chain().is_ok());
+
+        //the branch stays refused
+        assert!(chain.submit_block(b2.clone()).unwrap_err().to_string().contains("KNOWN_INVALID"));
+        assert!(chain.submit_block(child_block(&b2, Vec::new())).unwrap_err().to_string().contains("INVALID_PARENT"));
+        let tips = chain.tips().unwrap();
+        assert_eq!(tips.len(), 2);
+        assert!(tips[0].active && tips[0].invalid.is_none());
+        assert!(tips[1].invalid.as_deref().is_some_and(|reason| reason.starts_with("INVALID_PARENT: INVALID_SIGNATURE")));
+
+        //databases written before the child links get them from the headers
+        let tips: Vec<String> = tips.into_iter().map(|tip| tip.hash).collect();
+        chain.children.clear().unwrap();
+        let chain = reopen(chain).unwrap();
+        assert_eq!(chain.tips().unwrap().into_iter().map(|tip| tip.hash).collect::<Vec<_>>(), tips);
+        assert_eq!(chain.descendants(&genesis.get_hash()).unwrap().len(), 4);
+    }
+
+    //* helper to capture the UTXO set, spent records, height and transaction indexes and tip markers */
+    fn chain_state(chain: &Blockchain) -> Vec<Vec<(sled::IVec, sled::IVec)>>{
+        let markers = ["LAST", UTXO_TIP, SPENT_TIP, TX_TIP].iter().map(|key| (sled::IVec::from(*key), chain.db.get(key).unwrap().unwrap())).collect();
+        vec![
+            chain.utxo.iter().flatten().collect(),
+            chain.addresses.iter().flatten().collect(),
+            chain.spent.iter().flatten().collect(),
+            chain.heights.iter().flatten().collect(),
+            chain.transactions.iter().flatten().collect(),
+            markers,
+        ]
+    }
+
+    #[test]
+    fn test_undo_data_round_trips_blocks(){
+        let (secret, public, address) = test_keys(1);
+        let (secret2, public2, receiver) = test_keys(2);
+        let (_, _, other) = test_keys(3);
+        let dir = TempDir::new();
+        let mut chain = Blockchain::create_blockchain_with(address.clone(), dir.path(), test_rewards()).unwrap();
+        let coinbase = chain.get_block_by_height(0).unwrap().unwrap().get_transaction()[0].clone();
+        let genesis_state = chain_state(&chain);
+
+        //a block spending an output it creates, then one spending an output from below
+        let first = spend_to(&coinbase, 0, &secret, &public, vec![TrancOutput::new(coins(30), receiver.clone()).unwrap(), TrancOutput::new(coins(70), address.clone()).unwrap()]);
+        let chained = spend(&first, 1, &secret, &public, &other, 70);
+        chain.add_block(vec![reward(1), first.clone(), chained]).unwrap();
+        let b1 = chain.get_block_by_height(1).unwrap().unwrap();
+        let b1_state = chain_state(&chain);
+        chain.add_block(vec![reward(2), spend(&first, 0, &secret2, &public2, &address, 30)]).unwrap();
+        let b2 = chain.get_block_by_height(2).unwrap().unwrap();
+        let b2_state = chain_state(&chain);
+
+        let undo = chain.get_undo(&b1).unwrap();
+        assert_eq!(undo.iter().map(|spent| (spent.tranc_id.clone(), spent.vout, spent.output.value, spent.height, spent.coinbase)).collect::<Vec<_>>(), vec![(coinbase.tranc_id.clone(), 0, coins(100), 0, true)]);
+        let undo = chain.get_undo(&b2).unwrap();
+        assert_eq!(undo.iter().map(|spent| (spent.tranc_id.clone(), spent.vout, spent.output.value, spent.height, spent.coinbase)).collect::<Vec<_>>(), vec![(first.tranc_id.clone(), 0, coins(30), 1, false)]);
+        //blocks connected without undo records get them from the chain
+        chain.undo.remove(b2.get_hash()).unwrap();
+        let rebuilt = chain.get_undo(&b2).unwrap();
+        assert_eq!((rebuilt.len(), rebuilt[0].height, rebuilt[0].coinbase), (1, 1, false));
+
+        //every rollback gives back the exact state below the block
+        assert_eq!(hashes(&chain.rollback(1).unwrap()), vec![format!("-{}", b2.get_hash())]);
+        assert_eq!(chain_state(&chain), b1_state);
+        assert_eq!(chain.rollback(1).unwrap().len(), 1);
+        assert_eq!(chain_state(&chain), genesis_state);
+        assert!(chain.undo.is_empty());
+        assert!(chain.rollback(1).unwrap_err().to_string().starts_with("GENESIS_DISCONNECT"));
+        assert!(chain.validate_chain().is_ok());
+
+        //the blocks stay stored as a side branch, even after a restart
+        let mut chain = reopen(chain).unwrap();
+        assert_eq!(chain.get_best_height().unwrap(), 0);
+        assert!(chain.get_block_by_hash(&b2.get_hash()).unwrap().is_some());
+        let genesis = chain.get_block_by_height(0).unwrap().unwrap().get_hash();
+        let tips = chain.tips().unwrap();
+        assert_eq!(tips.iter().map(|tip| (tip.hash.clone(), tip.active, tip.invalid.is_some())).collect::<Vec<_>>(), vec![
+            (genesis.clone(), true, false), (b2.get_hash(), false, false),
+        ]);
+
+        //submitting one of them again or mining on the lower tip doesn't bring the heavier branch back
+        assert!(chain.submit_block(b1.clone()).unwrap().is_empty());
+        assert_eq!(chain_state(&chain), genesis_state);
+        chain.add_block(vec![reward(1)]).unwrap();
+        let c1 = chain.tip_hash();
+        let chain = reopen(chain).unwrap();
+        assert_eq!(chain.tip_hash(), c1);
+
+        //until it's reconsidered, connecting it with the state above it
+        let mut chain = chain;
+        assert_eq!(hashes(&chain.reconsider_block(&b1.get_hash()).unwrap()), vec![format!("-{}", c1), format!("+{}", b1.get_hash()), format!("+{}", b2.get_hash())]);
+        assert_eq!(chain_state(&chain), b2_state);
+        assert_eq!(chain.reconsider_block("unknown").unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_invalidated_blocks_leave_the_chain(){
+        let (_, _, address) = test_keys(1);
+        let dir = TempDir::new();
+        let mut chain = Blockchain::create_blockchain_with(address, dir.path(), test_rewards()).unwrap();
+        let genesis = chain.get_block_by_height(0).unwrap().unwrap();
+        chain.add_block(vec![reward(1)]).unwrap();
+        chain.add_block(vec![reward(2)]).unwrap();
+        let (a1, a2) = (chain.get_block_by_height(1).unwrap().unwrap(), chain.get_block_by_height(2).unwrap().unwrap());
+        let b1 = child_block(&genesis, Vec::new());
+        assert!(chain.submit_block(b1.clone()).unwrap().is_empty());
+
+        //the best branch left takes over
+        let events = chain.invalidate_block(&a1.get_hash()).unwrap();
+        assert_eq!(hashes(&events), vec![format!("-{}", a2.get_hash()), format!("-{}", a1.get_hash()), format!("+{}", b1.get_hash())]);
+        assert_eq!(chain.iter().next().unwrap().get_hash(), b1.get_hash());
+        assert!(chain.submit_block(a2).unwrap_err().to_string().contains("KNOWN_INVALID"));
+        assert!(chain.validate_chain().is_ok());
+
+        //invalidating a block off the active chain changes nothing else
+        let b2 = child_block(&b1, Vec::new());
+        chain.submit_block(b2.clone()).unwrap();
+        let c1 = child_block(&genesis, Vec::new());
+        chain.submit_block(c1.clone()).unwrap();
+        assert!(chain.invalidate_block(&c1.get_hash()).unwrap().is_empty());
+        assert_eq!(chain.iter().next().unwrap().get_hash(), b2.get_hash());
+        let tips: Vec<(String, bool, bool)> = chain.tips().unwrap().into_iter().map(|tip| (tip.hash, tip.active, tip.invalid.is_some())).collect();
+        assert_eq!(tips.len(), 3);
+        assert_eq!(tips[0], (b2.get_hash(), true, false));
+        assert!(tips[1..].iter().all(|(_, active, invalid)| !active && *invalid));
+
+        assert!(chain.invalidate_block(&genesis.get_hash()).unwrap_err().to_string().starts_with("GENESIS_DISCONNECT"));
+        assert_eq!(chain.invalidate_block("unknown").unwrap_err().kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_locators_find_the_fork(){
+        let (_, _, address) = test_keys(1);
+        let dir = TempDir::new();
+        let mut chain = Blockchain::create_blockchain_with(address, dir.path(), test_rewards()).unwrap();
+        for height in 1..=25{
+            chain.add_block(vec![reward(height)]).unwrap();
+        }
+        let hash_at = |height: usize| chain.get_block_by_height(height).unwrap().unwrap().get_hash();
+
+        //one by one near the tip, then doubling steps down to genesis
+        let locator = chain.locator().unwrap();
+        let heights: Vec<usize> = locator.iter().map(|hash| chain.get_header_by_hash(hash).unwrap().unwrap().height).collect();
+        assert_eq!(heights, vec![25, 24, 23, 22, 21, 20, 19, 18, 17, 16, 14, 10, 2, 0]);
+        assert_eq!(locator[0], chain.tip_hash());
+
+        //a peer on a fork gets the headers after the last block they share
+        let fork = child_block(&chain.get_block_by_height(20).unwrap().unwrap(), Vec::new());
+        let peer_locator = vec![fork.get_hash(), hash_at(20), hash_at(19), hash_at(0)];
+        let hashes_after = |locator: &[String], max: usize| -> Vec<String> {
+            chain.headers_after(locator, max).unwrap().iter().map(|header| header.hash().unwrap()).collect()
+        };
+        assert_eq!(hashes_after(&peer_locator, 3), vec![hash_at(21), hash_at(22), hash_at(23)]);
+        assert_eq!(hashes_after(&peer_locator, 500).len(), 5);
+        assert!(hashes_after(&[fork.get_hash()], 500).is_empty());
+        assert!(hashes_after(&locator, 500).is_empty());
+    }
+
+    #[test]
+    fn test_blocks_download_after_their_headers_in_any_order(){
+        let (_, _, address) = test_keys(1);
+        let (dir, synced_dir) = (TempDir::new(), TempDir::new());
+        let mut chain = Blockchain::create_blockchain_with(address, dir.path(), test_rewards()).unwrap();
+        for height in 1..=25{
+            chain.add_block(vec![reward(height)]).unwrap();
+        }
+        let blocks: Vec<Block> = (0..=25).map(|height| chain.get_block_by_height(height).unwrap().unwrap()).collect();
+        let headers: Vec<BlockHeader> = blocks[1..].iter().map(|block| block.get_header().clone()).collect();
+        let mut synced = Blockchain::create_from_genesis(blocks[0].clone(), synced_dir.path()).unwrap();
+
+        //headers link up from a known block and carry their proof of work
+        assert_eq!(synced.submit_headers(&headers[..20]).unwrap(), 20);
+        assert_eq!(synced.submit_headers(&headers).unwrap(), 5);
+        assert_eq!(synced.submit_headers(&headers[24..]).unwrap(), 0);
+        let mut bad = headers[5].clone();
+        bad.bits += 1;
+        assert!(synced.submit_headers(&[bad]).unwrap_err().to_string().contains("DIFFICULTY_MISMATCH"));
+        let fork = child_block(&blocks[25], Vec::new());
+        let orphan = child_block(&fork, Vec::new());
+        assert_eq!(synced.submit_headers(&[orphan.get_header().clone()]).unwrap_err().kind(), io::ErrorKind::NotFound);
+        let side = child_block(&blocks[0], Vec::new());//a lighter branch, dropped once the chain gets past it
+        assert_eq!(synced.submit_headers(&[side.get_header().clone()]).unwrap(), 1);
+        assert_eq!(synced.locator().unwrap()[0], blocks[25].get_hash());
+        let missing: Vec<usize> = synced.missing_blocks(5).unwrap().into_iter().map(|(_, height)| height).collect();
+        assert_eq!(missing, vec![1, 2, 3, 4, 5]);
+
+        //blocks without their parent wait for it, across a restart
+        for block in blocks[13..].iter().rev(){
+            assert!(synced.submit_block(block.clone()).unwrap().is_empty());
+        }
+        assert!(synced.has_block(&blocks[20].get_hash()).unwrap());
+        assert_eq!(synced.get_best_height().unwrap(), 0);
+        synced.sync_ranked.clear().unwrap();//as written before the download ranking
+        synced.sync_path.clear().unwrap();
+        let mut synced = reopen(synced).unwrap();
+        let missing: Vec<usize> = synced.missing_blocks(100).unwrap().into_iter().map(|(_, height)| height).collect();
+        assert_eq!(missing, (1..=12).collect::<Vec<usize>>());
+        let mut events = Vec::new();
+        for block in blocks[1..13].iter().rev(){
+            events.extend(synced.submit_block(block.clone()).unwrap());
+        }
+        assert_eq!(hashes(&events), blocks[1..].iter().map(|block| format!("+{}", block.get_hash())).collect::<Vec<String>>());
+        assert_eq!(synced.tip_hash(), chain.tip_hash());
+        assert!(synced.missing_blocks(100).unwrap().is_empty());
+        assert!(synced.sync_headers.is_empty() && synced.sync_blocks.is_empty());
+        assert!(synced.sync_ranked.is_empty() && synced.sync_path.is_empty());
+        assert!(synced.validate_chain().is_ok());
+    }
+
     #[test]
     fn test_utxo()->Result<(),io::Error>{
         let address = "3HWd4D3Li8bJbonVuNDZnxcRZygozMTriz";
         let pub_key_hash = Address::decode(address).unwrap().body;
         // let mut utxos = Vec::<TrancOutput>::new(); 
-        let chain = Blockchain::new()?;
-        let _unspend_txs = chain.find_unspent_transactions(pub_key_hash.clone());
+        let dir = TempDir::new();
+        let chain = Blockchain::create_blockchain_with(address.to_string(), dir.path(), test_rewards())?;
+        let _unspend_txs = chain.find_utxo(pub_key_hash.clone());
         println!("Fetching done");
         Ok(())
     }
@@ -275,7 +2962,8 @@ mod tests{
     fn test_unspent_transactions()->Result<(),io::Error>{
         let address = "3HWd4D3Li8bJbonVuNDZnxcRZygozMTriz";
         let pub_key_hash = Address::decode(address).unwrap().body;
-        let chain = Blockchain::new()?;
+        let dir = TempDir::new();
+        let chain = Blockchain::create_blockchain_with(address.to_string(), dir.path(), test_rewards())?;
         let mut spent_tx: HashMap<String,Vec<i32>> = HashMap::new();//hash map to store spent
         let mut unspent_tx: Vec<Transaction> = Vec::new();//hash to store unspent transactions
 
@@ -312,7 +3000,10 @@ mod tests{
     }
     #[test]
     fn test_uto()->Result<(),io::Error>{
-        let db = sled::open("data/spent_records")?;
+        let address = "3HWd4D3Li8bJbonVuNDZnxcRZygozMTriz";
+        let dir = TempDir::new();
+        let chain = Blockchain::create_blockchain_with(address.to_string(), dir.path(), test_rewards())?;
+        let db = &chain.spent;
         println!("database loaded");
         println!("{}",db.iter().count());
         for item in db.iter(){
@@ -0,0 +1,152 @@
+//? Proof of work difficulty rules of the chain
+use super::mining::BlockHeader;
+
+//? difficulty of the genesis block and every block up to the first retarget (same work as one leading hex zero)
+pub const INITIAL_DIFFICULTY: usize = 4;
+//? number of blocks between two difficulty adjustments
+pub const RETARGET_INTERVAL: usize = 10;
+//? wanted time between two blocks in miliseconds
+pub const TARGET_BLOCK_TIME: u128 = 10_000;
+//? number of blocks below a new one whose median timestamp it can't go under
+pub const MEDIAN_TIME_SPAN: usize = 11;
+//? how far in miliseconds a block timestamp may be ahead of the clock of the node checking it (a dozen target block times)
+pub const MAX_FUTURE_DRIFT: u128 = 12 * TARGET_BLOCK_TIME;
+//? maximum change of the difficulty in a single adjustment (a factor of 4 in work)
+const MAX_ADJUSTMENT: i64 = 2;
+//? bounds of the difficulty (a SHA256 hash has 256 bits)
+const MIN_DIFFICULTY: i64 = 1;
+const MAX_DIFFICULTY: i64 = 255;
+
+//* To check whether the difficulty has to be recalculated for the block at a height */
+pub fn is_retarget_height(height: usize) -> bool{
+    height > 0 && height.is_multiple_of(RETARGET_INTERVAL)
+}
+
+//* To calculate the next difficulty from the time the last window of blocks took */
+/// last_difficulty: difficulty of the block before the retarget height
+/// actual_timespan: miliseconds between the first and the last block of the window
+pub fn retarget(last_difficulty: usize, actual_timespan: u128) -> usize{
+    let expected = TARGET_BLOCK_TIME * (RETARGET_INTERVAL as u128 - 1);//the window spans interval-1 gaps
+    let ratio = expected as f64 / actual_timespan.max(1) as f64;//above 1 when blocks came too fast
+    let adjustment = (ratio.log2().round() as i64).clamp(-MAX_ADJUSTMENT, MAX_ADJUSTMENT);//every bit doubles the work
+    (last_difficulty as i64 + adjustment).clamp(MIN_DIFFICULTY, MAX_DIFFICULTY) as usize
+}
+
+//* To get the difficulty the chain rules require for the block at a height */
+/// height: height of the block to check or mine
+/// recent: the headers of the blocks right below that height, genesis first, at least `RETARGET_INTERVAL` of them when the chain is that long
+pub fn required_difficulty(height: usize, recent: &[BlockHeader]) -> usize{
+    match recent.last(){
+        None => INITIAL_DIFFICULTY,//genesis
+        Some(last) if is_retarget_height(height) && recent.len() >= RETARGET_INTERVAL => {
+            let first = &recent[recent.len() - RETARGET_INTERVAL];//first block of the window
+            retarget(last.bits, last.timestamp.saturating_sub(first.timestamp))
+        }
+        Some(last) => last.bits,//unchanged between retargets
+    }
+}
+
+//* To get the median timestamp of the last `MEDIAN_TIME_SPAN` blocks, the earliest timestamp the next block may have */
+/// recent: the headers of the blocks right below the new block, genesis first
+/// Bounding timestamps from below this way and from above by `MAX_FUTURE_DRIFT` keeps miners from stretching the retarget window.
+pub fn median_time_past(recent: &[BlockHeader]) -> u128{
+    let mut times: Vec<u128> = recent.iter().rev().take(MEDIAN_TIME_SPAN).map(|header| header.timestamp).collect();
+    times.sort_unstable();
+    times.get(times.len() / 2).copied().unwrap_or(0)
+}
+
+//* To check whether a hex encoded hash has at least `difficulty` leading zero bits */
+pub fn meets_difficulty(hash: &str, difficulty: usize) -> bool{
+    let mut zero_bits = 0;
+    for c in hash.chars(){
+        match c.to_digit(16){
+            Some(0) => zero_bits += 4,//a whole zero nibble
+            Some(nibble) => {
+                zero_bits += nibble.leading_zeros() as usize - 28;//zeros inside the first non zero nibble
+                break;
+            }
+            None => return false,//not a hex hash
+        }
+        if zero_bits >= difficulty{
+            break;
+        }
+    }
+    zero_bits >= difficulty
+}
+
+//* To check whether a raw hash has at least `difficulty` leading zero bits, same rule as `meets_difficulty` */
+pub fn digest_meets_difficulty(digest: &[u8], difficulty: usize) -> bool{
+    let mut zero_bits = 0;
+    for byte in digest{
+        zero_bits += byte.leading_zeros() as usize;
+        if *byte != 0 || zero_bits >= difficulty{
+            break;
+        }
+    }
+    zero_bits >= difficulty
+}
+
+//* To get the work a block of a difficulty stands for, the number of hashes expected to find it */
+/// Branches are compared by the sum of the work of their blocks, saturating at `u128::MAX`.
+pub fn block_work(difficulty: usize) -> u128{
+    u32::try_from(difficulty).ok().and_then(|bits| 1u128.checked_shl(bits)).unwrap_or(u128::MAX)
+}
+
+#[cfg(test)]
+mod tests{
+    use super::*;
+
+    #[test]
+    fn test_meets_difficulty(){
+        assert!(meets_difficulty("0fff", 4));
+        assert!(!meets_difficulty("0fff", 5));
+        assert!(meets_difficulty("01ff", 7));
+        assert!(!meets_difficulty("01ff", 8));
+        assert!(meets_difficulty("ffff", 0));
+        assert!(!meets_difficulty("zz", 1));
+    }
+
+    #[test]
+    fn test_digest_meets_difficulty(){
+        assert!(digest_meets_difficulty(&[0x0f, 0xff], 4));
+        assert!(!digest_meets_difficulty(&[0x0f, 0xff], 5));
+        assert!(digest_meets_difficulty(&[0x00, 0x01], 15));
+        assert!(!digest_meets_difficulty(&[0x00, 0x01], 16));
+        assert!(digest_meets_difficulty(&[0xff], 0));
+        assert!(!digest_meets_difficulty(&[0x00, 0x00], 17));
+    }
+
+    #[test]
+    fn test_retarget(){
+        let expected = TARGET_BLOCK_TIME * (RETARGET_INTERVAL as u128 - 1);
+        assert_eq!(retarget(8, expected), 8);//on schedule
+        assert_eq!(retarget(8, expected / 2), 9);//twice as fast
+        assert_eq!(retarget(8, expected * 2), 7);//twice as slow
+        assert_eq!(retarget(8, 0), 10);//clamped
+        assert_eq!(retarget(8, expected * 1000), 6);//clamped
+        assert_eq!(retarget(1, expected * 4), 1);//never below the minimum
+    }
+
+    #[test]
+    fn test_median_time_past(){
+        let headers = |times: &[u128]| times.iter().map(|timestamp| BlockHeader{
+            version: 1, prev_block_hash: String::new(), merkle_root: String::new(), timestamp: *timestamp, height: 0, bits: 1, extra_nonce: 0, nonce: 0,
+        }).collect::<Vec<_>>();
+        assert_eq!(median_time_past(&[]), 0);//genesis has nothing below it
+        assert_eq!(median_time_past(&headers(&[5])), 5);
+        assert_eq!(median_time_past(&headers(&[1, 9, 3])), 3);//order doesn't matter
+        //only the last blocks count, an old outlier is ignored
+        let mut times = vec![1_000_000];
+        times.extend(1..=MEDIAN_TIME_SPAN as u128);
+        assert_eq!(median_time_past(&headers(&times)), 6);
+    }
+
+    #[test]
+    fn test_block_work(){
+        assert_eq!(block_work(0), 1);
+        assert_eq!(block_work(4), 16);
+        assert_eq!(block_work(5), 2 * block_work(4));//a bit more doubles the work
+        assert_eq!(block_work(127), 1 << 127);
+        assert_eq!(block_work(200), u128::MAX);
+    }
+}
@@ -0,0 +1,805 @@
+//? Pool of transactions waiting for a block
+use std::{cmp::Ordering, collections::{BinaryHeap, HashMap, HashSet}, fmt, io};
+use log::info;
+use crate::miner::chain::{Blockchain, ChainEvent, TransactionError};
+use crate::miner::pow::MiningStats;
+use crate::wallet::{amount::Amount, transaction::{PendingTransactions, Transaction}};
+
+//? name of the database holding the pool in the data directory
+pub const MEMPOOL_DB: &str = "mempool";
+//? size in bytes the transactions of a mined block may take besides its coinbase
+pub const MAX_BLOCK_SIZE: usize = 1_000_000;
+//? fee per byte a replacement pays on top of the fees of the transactions it evicts, so replacing can't go on for free
+pub const MIN_REPLACEMENT_RATE: Amount = Amount::from_units(1);
+
+//? A pending transaction along with what it pays for its size
+#[derive(Debug, Clone)]
+pub struct PoolEntry {
+    pub transaction: Transaction,
+    pub fee: Amount,//left to the miner
+    pub size: usize,//bytes of the serialized transaction
+    pub parents: Vec<String>,//pending transactions it spends outputs of
+}
+
+impl PoolEntry {
+    //* To compare the fees per byte of two entries without rounding */
+    pub fn cmp_fee_rate(&self, other: &PoolEntry) -> Ordering {
+        cmp_fee_rate((self.fee, self.size), (other.fee, other.size))
+    }
+
+    //* To get the fee per byte, for display */
+    pub fn fee_rate(&self) -> f64 {
+        self.fee.units() as f64 / self.size.max(1) as f64
+    }
+}
+
+//* To compare the fees per byte of two (fee, size) pairs without rounding */
+fn cmp_fee_rate((fee, size): (Amount, usize), (other_fee, other_size): (Amount, usize)) -> Ordering {
+    let rate = |fee: Amount, size: usize| u128::from(fee.units()) * size as u128;
+    rate(fee, other_size).cmp(&rate(other_fee, size))
+}
+
+//* To get the lowest fee a transaction of a given size pays to replace pending ones paying a fee together */
+fn min_replacement_fee(replaced_fee: Amount, size: usize) -> Amount {
+    replaced_fee.saturating_add(MIN_REPLACEMENT_RATE.saturating_mul(u64::try_from(size).unwrap_or(u64::MAX)))
+}
+
+//? A pending transaction with the pending ancestors it needs, in block order
+struct Package {
+    tranc_ids: Vec<String>,//ancestors first, the transaction last
+    fee: Amount,//paid by all of them not picked yet
+    size: usize,//bytes of all of them not picked yet
+}
+
+//? A package waiting to be picked, the highest fee rate first and the lowest id among equal ones
+#[derive(PartialEq, Eq)]
+struct Candidate {
+    fee: Amount,//of the package when it was queued
+    size: usize,
+    tranc_id: String,
+}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        cmp_fee_rate((self.fee, self.size), (other.fee, other.size)).then_with(|| other.tranc_id.cmp(&self.tranc_id))
+    }
+}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+//? Why a transaction was refused by the pool
+#[derive(Debug, Clone, PartialEq)]
+pub enum MempoolError {
+    AlreadyPending{ tranc_id: String },//the same transaction is in the pool
+    Conflict{ tranc_id: String, input: usize, outpoint: String, spent_by: String },//spends an output a pending transaction spends, paying too little to replace it
+    Rejected(TransactionError),//fails the consensus checks
+}
+
+impl fmt::Display for MempoolError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MempoolError::AlreadyPending{ tranc_id } => write!(f, "ALREADY_PENDING: {} is already in the mempool", tranc_id),
+            MempoolError::Conflict{ tranc_id, input, outpoint, spent_by } =>
+                write!(f, "MEMPOOL_CONFLICT: input {} of {} spends {}, already spent by pending {} and paying too little to replace it", input, tranc_id, outpoint, spent_by),
+            MempoolError::Rejected(e) => e.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for MempoolError {}
+
+impl From<TransactionError> for MempoolError {
+    fn from(e: TransactionError) -> Self {
+        MempoolError::Rejected(e)
+    }
+}
+
+impl From<MempoolError> for io::Error {
+    fn from(e: MempoolError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    }
+}
+
+//? Pending transactions of a chain
+pub struct Mempool {
+    db: sled::Db,//saved transactions by id
+    entries: HashMap<String, PoolEntry>,//pending transactions by id
+    spent_by: HashMap<String, String>,//outputs spent by pending transactions and the transaction spending them
+}
+
+impl Mempool {
+    //* To open the pool of a chain, checking the saved transactions against it again */
+    pub fn open(chain: &Blockchain) -> Result<Mempool, io::Error> {
+        Mempool::load(sled::open(chain.data_dir().join(MEMPOOL_DB))?, chain)
+    }
+
+    //* To load the saved transactions of an open pool database that are still valid on the chain */
+    fn load(db: sled::Db, chain: &Blockchain) -> Result<Mempool, io::Error> {
+        let mut pool = Mempool { db, entries: HashMap::new(), spent_by: HashMap::new() };
+        let mut saved: Vec<Transaction> = pool.db.iter().values()
+            .map(|value| bincode::deserialize(&value?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string())))
+            .collect::<Result<_, io::Error>>()?;
+        //children can only enter after their parents, so going over the refused ones again while some get in
+        loop {
+            let before = saved.len();
+            let mut refused = Vec::new();
+            for tx in saved {
+                match pool.accept(tx.clone(), chain) {
+                    Ok((_, replaced)) => for tranc_id in replaced {
+                        pool.db.remove(&tranc_id)?;
+                    },
+                    Err(e) => refused.push((tx, e)),
+                }
+            }
+            saved = refused.iter().map(|(tx, _)| tx.clone()).collect();
+            if saved.len() == before || saved.is_empty() {
+                for (tx, e) in refused {
+                    info!("Dropping {} from the mempool: {}", tx.tranc_id, e);
+                    pool.db.remove(&tx.tranc_id)?;
+                }
+                break;
+            }
+        }
+        pool.db.flush()?;
+        Ok(pool)
+    }
+
+    //* To add a transaction to the pool, returning the fee it pays */
+    /// The pending transactions it replaces are dropped.
+    pub fn add(&mut self, tx: Transaction, chain: &Blockchain) -> Result<Amount, io::Error> {
+        let value = bincode::serialize(&tx).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+        let tranc_id = tx.tranc_id.clone();
+        let (fee, replaced) = self.accept(tx, chain)?;
+        for replaced_id in replaced {
+            info!("{} replaces {} in the mempool", tranc_id, replaced_id);
+            self.db.remove(&replaced_id)?;
+        }
+        self.db.insert(&tranc_id, value)?;
+        self.db.flush()?;
+        Ok(fee)
+    }
+
+    //* To check a transaction and keep it in memory, returning its fee and the transactions it replaced */
+    /// A transaction spending outputs pending ones already spend replaces them along with their descendants when its
+    /// fee covers all of theirs plus `MIN_REPLACEMENT_RATE` for each of its own bytes.
+    fn accept(&mut self, tx: Transaction, chain: &Blockchain) -> Result<(Amount, Vec<String>), MempoolError> {
+        if self.entries.contains_key(&tx.tranc_id) {
+            return Err(MempoolError::AlreadyPending{ tranc_id: tx.tranc_id });
+        }
+        //the conflicting transactions go along with their descendants, which the replacement can't spend from
+        let mut conflict = None;
+        let mut replaced: Vec<String> = Vec::new();
+        for (input, vin) in tx.vin.iter().enumerate() {
+            let outpoint = Blockchain::utxo_key(&vin.from, vin.vout);
+            if let Some(spent_by) = self.spent_by.get(&outpoint) {
+                for id in [spent_by.clone()].into_iter().chain(self.descendants(spent_by)) {
+                    if !replaced.contains(&id) {
+                        replaced.push(id);
+                    }
+                }
+                conflict.get_or_insert(MempoolError::Conflict{ tranc_id: tx.tranc_id.clone(), input, outpoint, spent_by: spent_by.clone() });
+            }
+        }
+        let pending: HashMap<String, &Transaction> = self.entries.iter()
+            .filter(|(id, _)| !replaced.contains(id))
+            .map(|(id, entry)| (id.clone(), &entry.transaction))
+            .collect();
+        let fee = chain.check_pending(&tx, &pending)?;
+        let size = tx.size().map_err(|e| TransactionError::VerificationFailed{ tranc_id: tx.tranc_id.clone(), reason: e.to_string() })?;
+        if let Some(conflict) = conflict {
+            let replaced_fee = replaced.iter().fold(Amount::ZERO, |total, id| total.saturating_add(self.entries[id].fee));
+            if fee < min_replacement_fee(replaced_fee, size) {
+                return Err(conflict);
+            }
+        }
+        for id in &replaced {
+            self.unlink(id);
+        }
+
+        let mut parents: Vec<String> = Vec::new();
+        for vin in &tx.vin {
+            self.spent_by.insert(Blockchain::utxo_key(&vin.from, vin.vout), tx.tranc_id.clone());
+            if self.entries.contains_key(&vin.from) && !parents.contains(&vin.from) {
+                parents.push(vin.from.clone());
+            }
+        }
+        for child in self.entries.values_mut() {
+            if child.transaction.vin.iter().any(|vin| vin.from == tx.tranc_id) && !child.parents.contains(&tx.tranc_id) {
+                child.parents.push(tx.tranc_id.clone());//a parent coming back after its child
+            }
+        }
+        self.entries.insert(tx.tranc_id.clone(), PoolEntry { transaction: tx, fee, size, parents });
+        Ok((fee, replaced))
+    }
+
+    //* To drop a transaction from memory, leaving its descendants, returning whether it was pending */
+    fn unlink(&mut self, tranc_id: &str) -> bool {
+        let Some(entry) = self.entries.remove(tranc_id) else { return false };
+        for vin in &entry.transaction.vin {
+            self.spent_by.remove(&Blockchain::utxo_key(&vin.from, vin.vout));
+        }
+        for child in self.entries.values_mut() {
+            child.parents.retain(|parent| parent != tranc_id);
+        }
+        true
+    }
+
+    //* To drop a transaction from the pool, leaving its descendants */
+    fn remove(&mut self, tranc_id: &str) -> Result<(), io::Error> {
+        if self.unlink(tranc_id) {
+            self.db.remove(tranc_id)?;
+        }
+        Ok(())
+    }
+
+    //* To drop a transaction from the pool along with the descendants spending its outputs */
+    fn evict(&mut self, tranc_id: &str) -> Result<(), io::Error> {
+        for descendant in self.descendants(tranc_id).into_iter().chain([tranc_id.to_string()]) {
+            info!("Dropping {} from the mempool", descendant);
+            self.remove(&descendant)?;
+        }
+        Ok(())
+    }
+
+    //* To drop transactions a block confirmed along with the pending ones conflicting with them */
+    /// The descendants of a confirmed transaction stay, those of a conflicting one are dropped too.
+    pub fn remove_confirmed(&mut self, transactions: &[Transaction]) -> Result<(), io::Error> {
+        for tx in transactions {
+            self.remove(&tx.tranc_id)?;
+            for vin in &tx.vin {
+                if let Some(conflict) = self.spent_by.get(&Blockchain::utxo_key(&vin.from, vin.vout)).cloned() {
+                    info!("{} conflicts with confirmed {}", conflict, tx.tranc_id);
+                    self.evict(&conflict)?;
+                }
+            }
+        }
+        self.db.flush()?;
+        Ok(())
+    }
+
+    //* To follow the changes of the active chain, in the order they happened */
+    /// Transactions of connected blocks leave the pool like with `remove_confirmed`, those of disconnected blocks
+    /// that the chain doesn't confirm in the end are added back, parents first. After a disconnect the pending
+    /// transactions are checked again, since the outputs they spend may be gone or immature once more.
+    pub fn apply_chain_events(&mut self, events: &[ChainEvent], chain: &Blockchain) -> Result<(), io::Error> {
+        let disconnected = events.iter().any(|event| matches!(event, ChainEvent::Disconnected(_)));
+        let mut unconfirmed: Vec<Vec<Transaction>> = Vec::new();//transactions of disconnected blocks, the highest block first
+        for event in events {
+            match event {
+                ChainEvent::Connected(block) => {
+                    let transactions = block.get_transaction();
+                    for txs in unconfirmed.iter_mut() {
+                        txs.retain(|tx| !transactions.iter().any(|confirmed| confirmed.tranc_id == tx.tranc_id));
+                    }
+                    self.remove_confirmed(&transactions)?;
+                }
+                ChainEvent::Disconnected(block) => unconfirmed.push(block.get_transaction().into_iter().filter(|tx| !tx.is_coinbase()).collect()),
+                ChainEvent::Rejected(invalid) => info!("{}", invalid),
+            }
+        }
+        for tx in unconfirmed.into_iter().rev().flatten() {
+            let tranc_id = tx.tranc_id.clone();
+            if let Err(e) = self.add(tx, chain) {
+                info!("Dropping {} from a disconnected block: {}", tranc_id, e);
+            }
+        }
+        if disconnected {
+            self.revalidate(chain)?;
+        }
+        Ok(())
+    }
+
+    //* To drop the pending transactions the chain doesn't accept anymore along with their descendants */
+    fn revalidate(&mut self, chain: &Blockchain) -> Result<(), io::Error> {
+        let refused: Vec<(String, TransactionError)> = {
+            let pending: HashMap<String, &Transaction> = self.entries.iter().map(|(id, entry)| (id.clone(), &entry.transaction)).collect();
+            let mut refused: Vec<(String, TransactionError)> = pending.values()
+                .filter_map(|tx| chain.check_pending(tx, &pending).err().map(|e| (tx.tranc_id.clone(), e)))
+                .collect();
+            refused.sort_by(|a, b| a.0.cmp(&b.0));
+            refused
+        };
+        for (tranc_id, e) in refused {
+            if self.entries.contains_key(&tranc_id) {//not already gone with a refused ancestor
+                info!("{} is no longer valid on the chain: {}", tranc_id, e);
+                self.evict(&tranc_id)?;
+            }
+        }
+        self.db.flush()?;
+        Ok(())
+    }
+
+    //* To get the pending ancestors of a transaction, each after its own parents */
+    pub fn ancestors(&self, tranc_id: &str) -> Vec<String> {
+        let mut ancestors = Vec::new();
+        self.collect_ancestors(tranc_id, &mut ancestors, &mut HashSet::new());
+        ancestors.retain(|ancestor| ancestor != tranc_id);
+        ancestors
+    }
+
+    fn collect_ancestors(&self, tranc_id: &str, ancestors: &mut Vec<String>, seen: &mut HashSet<String>) {
+        if !seen.insert(tranc_id.to_string()) {
+            return;
+        }
+        if let Some(entry) = self.entries.get(tranc_id) {
+            for parent in &entry.parents {
+                self.collect_ancestors(parent, ancestors, seen);
+            }
+            ancestors.push(tranc_id.to_string());
+        }
+    }
+
+    //* To get the pending children of every pending transaction */
+    fn children(&self) -> HashMap<&str, Vec<&str>> {
+        let mut children: HashMap<&str, Vec<&str>> = HashMap::new();
+        for (id, entry) in &self.entries {
+            for parent in &entry.parents {
+                children.entry(parent.as_str()).or_default().push(id.as_str());
+            }
+        }
+        for ids in children.values_mut() {
+            ids.sort();
+        }
+        children
+    }
+
+    //* To get the pending descendants of a transaction, each after its own parents */
+    pub fn descendants(&self, tranc_id: &str) -> Vec<String> {
+        self.descendants_with(tranc_id, &self.children())
+    }
+
+    fn descendants_with(&self, tranc_id: &str, children: &HashMap<&str, Vec<&str>>) -> Vec<String> {
+        let mut descendants: Vec<String> = Vec::new();
+        let mut found: HashSet<&str> = HashSet::new();
+        let mut frontier = vec![tranc_id];
+        while let Some(parent) = frontier.pop() {
+            for child in children.get(parent).into_iter().flatten() {
+                if found.insert(child) {
+                    descendants.push(child.to_string());
+                    frontier.push(child);
+                }
+            }
+        }
+        //ordering them like the ancestors of the last one would be
+        let mut ordered = Vec::new();
+        let mut placed: HashSet<String> = HashSet::new();
+        for descendant in &descendants {
+            for id in self.ancestors(descendant).into_iter().chain([descendant.clone()]) {
+                if found.contains(id.as_str()) && placed.insert(id.clone()) {
+                    ordered.push(id);
+                }
+            }
+        }
+        ordered
+    }
+
+    //* To get a pending transaction */
+    pub fn get(&self, tranc_id: &str) -> Option<&PoolEntry> {
+        self.entries.get(tranc_id)
+    }
+
+    //* To get the pending transactions, highest fee rate first */
+    pub fn entries(&self) -> Vec<&PoolEntry> {
+        let mut entries: Vec<&PoolEntry> = self.entries.values().collect();
+        entries.sort_by(|a, b| b.cmp_fee_rate(a).then_with(|| a.transaction.tranc_id.cmp(&b.transaction.tranc_id)));
+        entries
+    }
+
+    //* To pick the transactions of the next block by the fee rate of their packages, parents before children */
+    /// A transaction paying a high fee brings its pending ancestors along, those that don't fit anymore are skipped.
+    /// The package of every transaction is computed once, picking one takes what it brought along out of the packages
+    /// of its descendants, which go back in the queue with their new fee rate.
+    /// max_size: bytes the picked transactions may take together
+    pub fn select(&self, max_size: usize) -> Vec<Transaction> {
+        let children = self.children();
+        let mut packages: HashMap<&str, Package> = HashMap::new();
+        let mut queue: BinaryHeap<Candidate> = BinaryHeap::new();
+        for id in self.entries.keys() {
+            let tranc_ids: Vec<String> = self.ancestors(id).into_iter().chain([id.clone()]).collect();
+            let (fee, size) = tranc_ids.iter().fold((Amount::ZERO, 0), |(fee, size), id| {
+                let entry = &self.entries[id];
+                (fee.saturating_add(entry.fee), size + entry.size)
+            });
+            queue.push(Candidate { fee, size, tranc_id: id.clone() });
+            packages.insert(id.as_str(), Package { tranc_ids, fee, size });
+        }
+
+        let mut size = 0;
+        let mut picked: HashSet<String> = HashSet::new();
+        let mut skipped: HashSet<String> = HashSet::new();
+        let mut selected = Vec::new();
+        while let Some(candidate) = queue.pop() {
+            let package = &packages[candidate.tranc_id.as_str()];
+            //skipping what was picked already and packages that changed since they were queued
+            if picked.contains(&candidate.tranc_id) || skipped.contains(&candidate.tranc_id) || (package.fee, package.size) != (candidate.fee, candidate.size) {
+                continue;
+            }
+            if size + package.size > max_size {
+                skipped.insert(candidate.tranc_id);
+                continue;
+            }
+            size += package.size;
+            let brought: Vec<String> = package.tranc_ids.iter().filter(|id| !picked.contains(*id)).cloned().collect();
+            for id in brought {
+                let entry = &self.entries[&id];
+                for descendant in self.descendants_with(&id, &children) {
+                    if picked.contains(&descendant) {
+                        continue;
+                    }
+                    let package = packages.get_mut(descendant.as_str()).expect("every pending transaction has a package");
+                    package.fee = package.fee.saturating_sub(entry.fee);
+                    package.size -= entry.size;
+                    if !skipped.contains(&descendant) {
+                        queue.push(Candidate { fee: package.fee, size: package.size, tranc_id: descendant });
+                    }
+                }
+                selected.push(entry.transaction.clone());
+                picked.insert(id);
+            }
+        }
+        selected
+    }
+
+    //* To mine a block of pending transactions paying their fees to an address, returning its statistics and transactions */
+    pub fn mine_block(&mut self, chain: &mut Blockchain, to: &str, max_size: usize) -> Result<(MiningStats, Vec<Transaction>), io::Error> {
+        let transactions = self.select(max_size);
+        let stats = chain.mine_block(to, transactions.clone())?;
+        self.remove_confirmed(&transactions)?;
+        Ok((stats, transactions))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl PendingTransactions for Mempool {
+    fn pending(&self, tranc_id: &str) -> Option<&Transaction> {
+        self.entries.get(tranc_id).map(|entry| &entry.transaction)
+    }
+
+    fn all_pending(&self) -> Vec<&Transaction> {
+        self.entries().into_iter().map(|entry| &entry.transaction).collect()
+    }
+
+    fn spends(&self, tranc_id: &str, vout: i32) -> bool {
+        self.spent_by.contains_key(&Blockchain::utxo_key(tranc_id, vout))
+    }
+
+    fn fee_of(&self, tranc_id: &str) -> Option<Amount> {
+        self.entries.get(tranc_id).map(|entry| entry.fee)
+    }
+
+    fn replacement_fee(&self, tranc_id: &str, size: usize) -> Option<Amount> {
+        let entry = self.entries.get(tranc_id)?;
+        let replaced_fee = self.descendants(tranc_id).iter().fold(entry.fee, |total, id| total.saturating_add(self.entries[id].fee));
+        Some(min_replacement_fee(replaced_fee, size))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoincash_addr::Address;
+    use crate::miner::difficulty::INITIAL_DIFFICULTY;
+    use crate::miner::mining::Block;
+    use crate::miner::reward::RewardSchedule;
+    use crate::test_utils::{coins, funded_chain, pay, spend, spend_to, test_keys, units, TempDir};
+    use crate::wallet::selection::InOrder;
+    use crate::wallet::transaction::{Fee, SpendOptions};
+    use crate::wallet::tx::{TrancInput, TrancOutput};
+
+    //* helper to drop a pool and load it again from the same database handle, as `open` does after a restart */
+    fn reopen(pool: Mempool, chain: &Blockchain) -> Result<Mempool, io::Error> {
+        Mempool::load(pool.db.clone(), chain)
+    }
+
+    fn ids(txs: &[Transaction]) -> Vec<String> {
+        txs.iter().map(|tx| tx.tranc_id.clone()).collect()
+    }
+
+    fn refusal(e: io::Error) -> MempoolError {
+        *e.into_inner().unwrap().downcast::<MempoolError>().unwrap()
+    }
+
+    #[test]
+    fn test_add_checks_transactions_and_conflicts() {
+        let dir = TempDir::new();
+        let (chain, coinbases) = funded_chain(&dir, 1);
+        let mut pool = Mempool::open(&chain).unwrap();
+
+        let tx = pay(&coinbases[0], 2, 90);
+        assert_eq!(pool.add(tx.clone(), &chain).unwrap(), coins(10));
+        assert_eq!(refusal(pool.add(tx.clone(), &chain).unwrap_err()), MempoolError::AlreadyPending{ tranc_id: tx.tranc_id.clone() });
+        let conflict = pay(&coinbases[0], 3, 90);
+        assert_eq!(refusal(pool.add(conflict.clone(), &chain).unwrap_err()), MempoolError::Conflict{
+            tranc_id: conflict.tranc_id, input: 0, outpoint: Blockchain::utxo_key(&coinbases[0].tranc_id, 0), spent_by: tx.tranc_id.clone(),
+        });
+        assert_eq!(refusal(pool.add(coinbases[0].clone(), &chain).unwrap_err()), MempoolError::Rejected(TransactionError::CoinbaseNotAllowed{ tranc_id: coinbases[0].tranc_id.clone() }));
+
+        //the consensus checks of a block apply
+        let mut missing = pay(&coinbases[0], 2, 90);
+        missing.vin[0].vout = 1;
+        missing.tranc_id = missing.hash().unwrap();
+        assert!(matches!(refusal(pool.add(missing, &chain).unwrap_err()), MempoolError::Rejected(TransactionError::MissingOutput{ .. })));
+        let overspend = pay(&coinbases[0], 2, 101);
+        pool.remove_confirmed(&[tx]).unwrap();
+        assert!(matches!(refusal(pool.add(overspend, &chain).unwrap_err()), MempoolError::Rejected(TransactionError::InputsBelowOutputs{ .. })));
+        assert!(pool.is_empty());
+    }
+
+    #[test]
+    fn test_mine_packs_by_fee_rate() {
+        let dir = TempDir::new();
+        let (mut chain, coinbases) = funded_chain(&dir, 3);
+        let mut pool = Mempool::open(&chain).unwrap();
+        let low = pay(&coinbases[0], 2, 99);
+        let high = pay(&coinbases[1], 2, 97);
+        let middle = pay(&coinbases[2], 2, 98);
+        for tx in [&low, &high, &middle] {
+            pool.add(tx.clone(), &chain).unwrap();
+        }
+        let ids = |txs: Vec<Transaction>| txs.into_iter().map(|tx| tx.tranc_id).collect::<Vec<_>>();
+        assert_eq!(ids(pool.select(MAX_BLOCK_SIZE)), ids(vec![high.clone(), middle.clone(), low.clone()]));
+        //the transactions have the same size, two of them fit
+        let size = high.size().unwrap();
+        assert_eq!(ids(pool.select(2 * size + 1)), ids(vec![high.clone(), middle.clone()]));
+
+        let (_, _, miner) = test_keys(9);
+        let (_, transactions) = pool.mine_block(&mut chain, &miner, 2 * size).unwrap();
+        assert_eq!(transactions.len(), 2);
+        assert_eq!(ids(pool.select(MAX_BLOCK_SIZE)), ids(vec![low]));
+        let block = chain.iter().next().unwrap();
+        assert_eq!(block.get_transaction().len(), 3);
+        let miner_hash = Address::decode(&miner).unwrap().body;
+        assert_eq!(Amount::checked_sum(chain.find_utxo(miner_hash).iter().map(|out| out.value)), Some(coins(105)));
+    }
+
+    #[test]
+    fn test_pool_persists_until_confirmed() {
+        let dir = TempDir::new();
+        let (mut chain, coinbases) = funded_chain(&dir, 2);
+        let mut pool = Mempool::open(&chain).unwrap();
+        let first = pay(&coinbases[0], 2, 90);
+        let second = pay(&coinbases[1], 2, 90);
+        pool.add(first.clone(), &chain).unwrap();
+        pool.add(second.clone(), &chain).unwrap();
+        let pool = reopen(pool, &chain).unwrap();
+        assert_eq!(pool.len(), 2);
+
+        //a block spending the output of the first elsewhere drops it on the next open
+        let (_, _, miner) = test_keys(9);
+        chain.mine_block(&miner, vec![pay(&coinbases[0], 3, 95)]).unwrap();
+        let pool = reopen(pool, &chain).unwrap();
+        assert_eq!(pool.entries().iter().map(|entry| entry.transaction.tranc_id.clone()).collect::<Vec<_>>(), vec![second.tranc_id]);
+    }
+
+    #[test]
+    fn test_confirmed_spends_evict_conflicts() {
+        let dir = TempDir::new();
+        let (chain, coinbases) = funded_chain(&dir, 1);
+        let mut pool = Mempool::open(&chain).unwrap();
+        pool.add(pay(&coinbases[0], 2, 90), &chain).unwrap();
+        pool.remove_confirmed(&[pay(&coinbases[0], 3, 95)]).unwrap();
+        assert!(pool.is_empty());
+        assert!(pool.add(pay(&coinbases[0], 3, 95), &chain).is_ok());
+    }
+
+    #[test]
+    fn test_children_spend_pending_outputs() {
+        let dir = TempDir::new();
+        let (chain, coinbases) = funded_chain(&dir, 1);
+        let mut pool = Mempool::open(&chain).unwrap();
+        let parent = pay(&coinbases[0], 1, 90);
+        pool.add(parent.clone(), &chain).unwrap();
+        let child = pay(&parent, 1, 85);
+
+        //the child can't be checked against the chain alone
+        assert!(matches!(chain.check_pending(&child, &HashMap::new()), Err(TransactionError::MissingOutput{ .. })));
+
+        pool.add(child.clone(), &chain).unwrap();
+        let grandchild = pay(&child, 2, 80);
+        pool.add(grandchild.clone(), &chain).unwrap();
+        assert_eq!(pool.ancestors(&grandchild.tranc_id), ids(&[parent.clone(), child.clone()]));
+        assert_eq!(pool.descendants(&parent.tranc_id), ids(&[child.clone(), grandchild.clone()]));
+        assert!(pool.descendants(&grandchild.tranc_id).is_empty());
+
+        //unconfirmed change can be picked explicitly
+        let (_, _, address) = test_keys(1);
+        let address_hash = Address::decode(&address).unwrap().body;
+        let confirmed = SpendOptions { fee: Fee::Absolute(Amount::ZERO), selector: &InOrder, pending: &pool, unconfirmed: false };
+        assert_eq!(confirmed.find_spendable_outputs(&chain, &address_hash, coins(1)).0, Amount::ZERO);
+        let (spendable, outputs) = SpendOptions { unconfirmed: true, ..confirmed }.find_spendable_outputs(&chain, &address_hash, coins(1));
+        assert_eq!(spendable, Amount::ZERO);//the change of the child is spent by the grandchild already
+        assert!(outputs.is_empty());
+
+        //children come back after a restart whatever the order they're saved in
+        let pool = reopen(pool, &chain).unwrap();
+        assert_eq!(pool.len(), 3);
+        assert_eq!(ids(&pool.select(MAX_BLOCK_SIZE)), ids(&[parent, child, grandchild]));
+    }
+
+    #[test]
+    fn test_packages_pay_for_their_parents() {
+        let dir = TempDir::new();
+        let (mut chain, coinbases) = funded_chain(&dir, 2);
+        let mut pool = Mempool::open(&chain).unwrap();
+        let alone = pay(&coinbases[0], 2, 97);
+        let parent = pay(&coinbases[1], 1, 99);
+        pool.add(alone.clone(), &chain).unwrap();
+        pool.add(parent.clone(), &chain).unwrap();
+        let child = pay(&parent, 2, 89);
+        pool.add(child.clone(), &chain).unwrap();
+
+        //the child pays enough for both, taking its parent before the transaction paying more on its own
+        assert_eq!(ids(&pool.select(MAX_BLOCK_SIZE)), ids(&[parent.clone(), child.clone(), alone.clone()]));
+        let size = alone.size().unwrap();
+        assert_eq!(ids(&pool.select(size)), ids(std::slice::from_ref(&alone)));
+        assert_eq!(ids(&pool.select(2 * size)), ids(&[parent.clone(), child.clone()]));
+
+        let (_, _, miner) = test_keys(9);
+        pool.mine_block(&mut chain, &miner, MAX_BLOCK_SIZE).unwrap();
+        assert!(pool.is_empty());
+        assert!(chain.validate_chain().is_ok());
+    }
+
+    #[test]
+    fn test_picked_parents_leave_the_packages_of_their_children() {
+        let dir = TempDir::new();
+        let (chain, coinbases) = funded_chain(&dir, 2);
+        let mut pool = Mempool::open(&chain).unwrap();
+        let (secret, public, address) = test_keys(1);
+        let (_, _, receiver) = test_keys(2);
+        //a parent with two outputs paying a fee of 2, its children paying 10 and 4, another transaction paying 3
+        let parent = spend_to(&coinbases[0], 0, &secret, &public, vec![TrancOutput::new(coins(49), address.clone()).unwrap(), TrancOutput::new(coins(49), address).unwrap()]);
+        pool.add(parent.clone(), &chain).unwrap();
+        let rich = spend(&parent, 0, &secret, &public, &receiver, 39);
+        let poor = spend(&parent, 1, &secret, &public, &receiver, 45);
+        let other = spend(&coinbases[1], 0, &secret, &public, &receiver, 97);
+        for tx in [&rich, &poor, &other] {
+            pool.add(tx.clone(), &chain).unwrap();
+        }
+
+        //with its parent the poor child pays less per byte than the other transaction, on its own it pays more
+        assert_eq!(ids(&pool.select(MAX_BLOCK_SIZE)), ids(&[parent, rich, poor, other]));
+    }
+
+    #[test]
+    fn test_descendants_follow_their_ancestors_out() {
+        let dir = TempDir::new();
+        let (chain, coinbases) = funded_chain(&dir, 1);
+        let mut pool = Mempool::open(&chain).unwrap();
+        let parent = pay(&coinbases[0], 1, 90);
+        pool.add(parent.clone(), &chain).unwrap();
+        let child = pay(&parent, 2, 85);
+        pool.add(child.clone(), &chain).unwrap();
+
+        //the parent confirmed, the child stays with nothing pending before it
+        pool.remove_confirmed(std::slice::from_ref(&parent)).unwrap();
+        assert_eq!(pool.len(), 1);
+        assert!(pool.ancestors(&child.tranc_id).is_empty());
+
+        //a conflicting spend of the parent confirmed instead, both go
+        pool.add(parent.clone(), &chain).unwrap();
+        assert_eq!(pool.descendants(&parent.tranc_id), vec![child.tranc_id.clone()]);
+        pool.remove_confirmed(&[pay(&coinbases[0], 3, 95)]).unwrap();
+        assert!(pool.is_empty());
+    }
+
+    #[test]
+    fn test_higher_fees_replace_pending_transactions() {
+        let dir = TempDir::new();
+        let (chain, coinbases) = funded_chain(&dir, 1);
+        let mut pool = Mempool::open(&chain).unwrap();
+        let original = pay(&coinbases[0], 1, 90);
+        pool.add(original.clone(), &chain).unwrap();
+        let child = pay(&original, 2, 88);
+        pool.add(child.clone(), &chain).unwrap();
+
+        //the replacement has to pay more than the original and its descendants together
+        let underpaying = pay(&coinbases[0], 3, 89);
+        assert!(matches!(refusal(pool.add(underpaying, &chain).unwrap_err()), MempoolError::Conflict{ spent_by, .. } if spent_by == original.tranc_id));
+        assert_eq!(pool.len(), 2);
+        //paying just above both isn't enough either, the replacement pays for its own bytes on top
+        let (secret, public, _) = test_keys(1);
+        let barely = spend_to(&coinbases[0], 0, &secret, &public, vec![TrancOutput::new(coins(88).checked_sub(units(1)).unwrap(), test_keys(3).2).unwrap()]);
+        assert!(matches!(refusal(pool.add(barely, &chain).unwrap_err()), MempoolError::Conflict{ .. }));
+        let replacement = pay(&coinbases[0], 1, 87);
+        assert_eq!(pool.add(replacement.clone(), &chain).unwrap(), coins(13));
+        assert_eq!(ids(&pool.select(MAX_BLOCK_SIZE)), ids(std::slice::from_ref(&replacement)));
+        assert!(pool.get(&child.tranc_id).is_none());
+
+        //a replacement can't spend what it replaces
+        let grandchild = pay(&replacement, 1, 80);
+        pool.add(grandchild.clone(), &chain).unwrap();
+        let mut self_spending = pay(&replacement, 1, 10);
+        self_spending.vin.push(TrancInput { from: coinbases[0].tranc_id.clone(), vout: 0, signature: Vec::new(), pub_key: test_keys(1).1 });
+        self_spending.tranc_id = self_spending.hash().unwrap();
+        assert!(matches!(refusal(pool.add(self_spending, &chain).unwrap_err()), MempoolError::Rejected(_)));
+
+        //the replaced transactions don't come back with the pool
+        let pool = reopen(pool, &chain).unwrap();
+        assert_eq!(ids(&pool.select(MAX_BLOCK_SIZE)), ids(&[replacement, grandchild]));
+    }
+
+    #[test]
+    fn test_reorganizations_bring_transactions_back() {
+        let dir = TempDir::new();
+        let (mut chain, coinbases) = funded_chain(&dir, 1);
+        let genesis = chain.get_block_by_height(0).unwrap().unwrap();
+        let mut pool = Mempool::open(&chain).unwrap();
+        let parent = pay(&coinbases[0], 1, 90);
+        pool.add(parent.clone(), &chain).unwrap();
+        let child = pay(&parent, 2, 85);
+        pool.add(child.clone(), &chain).unwrap();
+        let (_, _, miner) = test_keys(9);
+        pool.mine_block(&mut chain, &miner, parent.size().unwrap()).unwrap();
+        assert_eq!(pool.len(), 1);
+
+        //another node's longer branch leaves the parent out, it comes back before its child
+        let fork_block = |parent: &Block, transactions: Vec<Transaction>| {
+            let height = parent.get_height() + 1;
+            let mut block = vec![Transaction::new_reward(miner.clone(), format!("fork {} at {}", parent.get_hash(), height), coins(100)).unwrap()];
+            block.extend(transactions);
+            Block::new(block, parent.get_hash(), height, INITIAL_DIFFICULTY).unwrap()
+        };
+        let f1 = fork_block(&genesis, Vec::new());
+        let f2 = fork_block(&f1, Vec::new());
+        assert!(chain.submit_block(f1).unwrap().is_empty());
+        let events = chain.submit_block(f2).unwrap();
+        pool.apply_chain_events(&events, &chain).unwrap();
+        assert_eq!(ids(&pool.select(MAX_BLOCK_SIZE)), ids(&[parent.clone(), child.clone()]));
+
+        //a branch confirming a conflicting spend drops both
+        let conflict = pay(&coinbases[0], 3, 100);
+        let g1 = fork_block(&genesis, vec![conflict]);
+        let g2 = fork_block(&g1, Vec::new());
+        let g3 = fork_block(&g2, Vec::new());
+        let mut events = Vec::new();
+        for block in [g1, g2, g3] {
+            events.extend(chain.submit_block(block).unwrap());
+        }
+        pool.apply_chain_events(&events, &chain).unwrap();
+        assert!(pool.is_empty());
+        assert!(chain.validate_chain().is_ok());
+    }
+
+    #[test]
+    fn test_disconnected_coinbases_evict_their_spends() {
+        let dir = TempDir::new();
+        let (mut chain, coinbases) = funded_chain(&dir, 3);
+        let mut pool = Mempool::open(&chain).unwrap();
+        let tip_spend = pay(&coinbases[0], 1, 90);//coinbase of the tip, mature right away
+        pool.add(tip_spend.clone(), &chain).unwrap();
+        let child = pay(&tip_spend, 1, 85);
+        pool.add(child, &chain).unwrap();
+        let genesis_spend = pay(&coinbases[2], 2, 90);
+        pool.add(genesis_spend.clone(), &chain).unwrap();
+
+        //the tip and its coinbase go away, taking the spend and its child along
+        let events = chain.rollback(1).unwrap();
+        pool.apply_chain_events(&events, &chain).unwrap();
+        assert_eq!(ids(&pool.select(MAX_BLOCK_SIZE)), ids(&[genesis_spend]));
+        assert_eq!(reopen(pool, &chain).unwrap().len(), 1);
+
+        //a coinbase that gets immature again is dropped as well
+        let dir = TempDir::new();
+        let (_, _, address) = test_keys(1);
+        let mut chain = Blockchain::create_blockchain_with(address.clone(), dir.path(), RewardSchedule::new(coins(100), 1000, 2).unwrap()).unwrap();
+        chain.mine_block(&address, Vec::new()).unwrap();
+        let genesis = chain.get_block_by_height(0).unwrap().unwrap();
+        let mut pool = Mempool::open(&chain).unwrap();
+        pool.add(pay(&genesis.get_transaction()[0], 2, 90), &chain).unwrap();
+        let events = chain.rollback(1).unwrap();
+        pool.apply_chain_events(&events, &chain).unwrap();
+        assert!(pool.is_empty());
+    }
+}
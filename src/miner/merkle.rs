@@ -0,0 +1,107 @@
+//? Merkle tree over the transaction ids of a block
+/// Leaves are the SHA256 hashes of the transaction ids and inner nodes the SHA256 of both children,
+/// arranged as the complete binary merkle tree of `merkle-cbt`.
+use crypto::digest::Digest;
+use crypto::sha2::Sha256; //hashing of leaves and nodes
+use merkle_cbt::merkle_tree::{Merge, MerkleProof, CBMT};
+use serde::{Deserialize, Serialize}; // for serialization and deserialization of proofs
+
+//? a node of the merkle tree
+type Node = [u8; 32];
+
+//? merging rule of two nodes: SHA256(left || right)
+struct MergeSha256;
+
+impl Merge for MergeSha256 {
+    type Item = Node;
+
+    fn merge(left: &Node, right: &Node) -> Node {
+        let mut hasher = Sha256::new();
+        hasher.input(left);
+        hasher.input(right);
+        let mut node = [0u8; 32];
+        hasher.result(&mut node);
+        node
+    }
+}
+
+//? complete binary merkle tree with the SHA256 merging rule
+type Tree = CBMT<Node, MergeSha256>;
+
+//* To get the leaf of a transaction id */
+fn leaf(tranc_id: &str) -> Node {
+    let mut hasher = Sha256::new();
+    hasher.input(tranc_id.as_bytes());
+    let mut node = [0u8; 32];
+    hasher.result(&mut node);
+    node
+}
+
+//* To encode a node as lowercase hex like the other hashes of the chain */
+fn to_hex(node: &Node) -> String {
+    node.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+//* To compute the merkle root of a list of transaction ids, all zeros for an empty list */
+pub fn merkle_root(tranc_ids: &[String]) -> String {
+    let leaves: Vec<Node> = tranc_ids.iter().map(|id| leaf(id)).collect();
+    to_hex(&Tree::build_merkle_root(&leaves))
+}
+
+//? Proof that a transaction id is one of the leaves under a merkle root
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InclusionProof {
+    indices: Vec<u32>, //position of the leaf in the tree
+    lemmas: Vec<Node>, //sibling nodes needed to recompute the root
+}
+
+impl InclusionProof {
+    //* To build the proof for the transaction at `index` of the list */
+    pub fn build(tranc_ids: &[String], index: usize) -> Option<InclusionProof> {
+        let leaves: Vec<Node> = tranc_ids.iter().map(|id| leaf(id)).collect();
+        let proof = Tree::build_merkle_proof(&leaves, &[u32::try_from(index).ok()?])?;
+        Some(InclusionProof {
+            indices: proof.indices().to_vec(),
+            lemmas: proof.lemmas().to_vec(),
+        })
+    }
+
+    //* To check that the transaction id is included under the merkle root */
+    pub fn verify(&self, merkle_root: &str, tranc_id: &str) -> bool {
+        let proof: MerkleProof<Node, MergeSha256> = MerkleProof::new(self.indices.clone(), self.lemmas.clone());
+        match proof.root(&[leaf(tranc_id)]) {
+            Some(root) => to_hex(&root) == merkle_root,
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inclusion_proofs() {
+        let ids: Vec<String> = (0..5).map(|i| format!("tx{}", i)).collect();
+        let root = merkle_root(&ids);
+        for (index, id) in ids.iter().enumerate() {
+            let proof = InclusionProof::build(&ids, index).unwrap();
+            assert!(proof.verify(&root, id));
+            assert!(!proof.verify(&root, "other"));
+        }
+        //a proof doesn't carry over to another position or another tree
+        let proof = InclusionProof::build(&ids, 1).unwrap();
+        assert!(!proof.verify(&root, &ids[2]));
+        assert!(!proof.verify(&merkle_root(&ids[..4]), &ids[1]));
+        assert!(InclusionProof::build(&ids, 5).is_none());
+    }
+
+    #[test]
+    fn test_merkle_root_depends_on_order_and_content() {
+        let ids = vec![String::from("a"), String::from("b")];
+        let swapped = vec![String::from("b"), String::from("a")];
+        assert_ne!(merkle_root(&ids), merkle_root(&swapped));
+        assert_eq!(merkle_root(&[]), "0".repeat(64));
+        assert_eq!(merkle_root(&ids[..1]), to_hex(&leaf("a")));
+    }
+}
@@ -1,117 +1,180 @@
 //? modules used in mining the chain
 use crate::wallet::transaction::Transaction;
-use bincode; //to serialize and deserialize to binary code
+use crate::miner::difficulty::{meets_difficulty, INITIAL_DIFFICULTY}; //proof of work rules
+use crate::miner::merkle::{merkle_root, InclusionProof}; //commitment to the transactions
+use crate::miner::pow::{Miner, MiningOutcome, MiningStats, StopReason}; //parallel proof of work search
 use crypto::digest::Digest;
 use crypto::sha2::Sha256; // Sha256 alorithm for hashing
-use log::info; //to  print info related text in the blockchain
 use serde::{Deserialize, Serialize}; // for serialization and deserialization of structs
 use std::io::{self}; //self property of implementation
 use std::time::SystemTime; // for getting timestamp //wallet imported from transaction
 
+//? version of the block format written by this node
+pub const BLOCK_VERSION: u32 = 1;
+
+//? Header of a block, the only part the proof of work is computed over
+///Each header containing
+///  -> version: format version of the block
+///  -> prev_block_hash: containing previous block hash to interate in blockchain
+///  -> merkle_root: root of the merkle tree over the transaction ids of the block
+///  -> timestamp: related to UNIX_EPOX
+///  -> height: position of the block in the chain, genesis being 0
+///  -> bits: difficulty, the number of leading zero bits the hash has to have
+///  -> extra_nonce: changed by the miner once every nonce has been tried
+///  -> nonce: is the random unique number for each block related to the proof-of-work, kept last so miners can swap its bytes
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct BlockHeader {
+    pub version: u32,
+    pub prev_block_hash: String,
+    pub merkle_root: String,
+    pub timestamp: u128,
+    pub height: usize,
+    pub bits: usize,
+    pub extra_nonce: u32,
+    pub nonce: u32,
+}
+
+//? implementations of the 'BlockHeader' struct
+impl BlockHeader {
+    //* To calculate the hash of the header for the current nonce */
+    pub fn hash(&self) -> Result<String, io::Error> {
+        //to serialize the header to binary format(a vector of)
+        let data: Vec<u8> = bincode::serialize(self)
+            .map_err(|e| io::Error::other(e.to_string()))?;//for error mapping
+        let mut hasher = Sha256::new();//using Sha256 algorithm to generate the hash
+        hasher.input(&data[..]);//digesting the hash data
+        Ok(hasher.result_str())
+    }
+
+    //* To validate whther for the current nonce the level of difficulty is reached or not */
+    pub fn meets_difficulty(&self) -> Result<bool, io::Error> {
+        Ok(meets_difficulty(&self.hash()?, self.bits))//checking the number of starting zero bits
+    }
+}
+
 //? Block of blockchain storing list of transactions and proof of work
 ///Each Block containing
-///  -> timestamp: related to UNIX_EPOX
-///  -> transactions: for storing the list of transactions for the particular block in blockchain
-///  -> prev_block_hash: containing previous block hash to interate in blockchain
+///  -> header: the fields covered by the proof of work
 ///  -> hash: the proof of work for the chain
-///  -> height: is basically the difficulty of the proof of work here
-///  -> nonce: is the random unique number for each block related to the proof-of-work
+///  -> transactions: for storing the list of transactions for the particular block in blockchain
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Block {
-    timestamp: u128,
-    transactions: Vec<Transaction>,
-    prev_block_hash: String,
+    header: BlockHeader,
     hash: String,
-    _height: usize,
-    nonce: i32,
+    transactions: Vec<Transaction>,
 }
 
-//? global difficulty for the proof of work
-static DIFFICULTY: usize = 1;
+//? Result of mining a block: the block or the progress made before the search was stopped
+#[derive(Debug)]
+pub enum MinedBlock {
+    Mined(Block, MiningStats),
+    Stopped(StopReason, MiningStats),
+}
 
-#[allow(dead_code)]
 //? implementations of the 'Block' struct
 impl Block {
-    //* new function to create a new block in the blockchain */
+    //* new function to create a new block in the blockchain, mined on every available core */
     pub fn new(
         data: Vec<Transaction>,
         prev_block_hash: String,
         height: usize,
+        difficulty: usize,
     ) -> Result<Self, io::Error> {
+        match Block::mine(data, prev_block_hash, height, difficulty, 0, &Miner::default())? {
+            MinedBlock::Mined(block, _) => Ok(block),
+            MinedBlock::Stopped(reason, stats) => Err(io::Error::new(io::ErrorKind::Interrupted, format!("MINING_STOPPED: {} after {}", reason, stats))),
+        }
+    }
+
+    //* To create a new block using a given miner, which may stop the search before a proof of work is found */
+    /// min_timestamp: earliest timestamp the chain accepts for the block, used when the clock is behind it
+    pub fn mine(
+        data: Vec<Transaction>,
+        prev_block_hash: String,
+        height: usize,
+        difficulty: usize,
+        min_timestamp: u128,
+        miner: &Miner,
+    ) -> Result<MinedBlock, io::Error> {
         let timestamp: u128 = SystemTime::now()
             .duration_since(SystemTime::UNIX_EPOCH)
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?
-            .as_millis(); //getting timestamp in miliseconds
+            .map_err(io::Error::other)?
+            .as_millis()
+            .max(min_timestamp); //getting timestamp in miliseconds
 
-        let mut block: Block = Block {
-            timestamp,//time of block creation
-            transactions: data,//transaction vector dataset
+        let header = BlockHeader {
+            version: BLOCK_VERSION,//current block format
             prev_block_hash,//previus block hash used to iterate the block chain
-            hash: String::new(),//proof of work 
-            _height: height,//height of the block
+            merkle_root: Block::compute_merkle_root(&data),//commits the header to the transactions
+            timestamp,//time of block creation
+            height,//height of the block
+            bits: difficulty,//difficulty required by the chain at this block
+            extra_nonce: 0,//only changed when the nonces run out
             nonce: 0,//random number 
+        };
+        let mut block: Block = Block {
+            header,
+            hash: String::new(),//proof of work 
+            transactions: data,//transaction vector dataset
         };//creating a new instance of the block struct
 
-        block.generate_proof_of_work();//generating proof of work for our block 
-        Ok(block)
+        //generating proof of work for our block 
+        match block.generate_proof_of_work(miner)? {
+            MiningOutcome::Found(stats) => Ok(MinedBlock::Mined(block, stats)),
+            MiningOutcome::Stopped(reason, stats) => Ok(MinedBlock::Stopped(reason, stats)),
+        }
     }
 
     //* to create a default block in the blockchain for the miner coinbase */
     pub fn default(coinbase: Transaction) -> Block {
         //no previous block hash
         //coinbase for miner passed as data
-        Block::new(vec![coinbase], String::new(), DIFFICULTY).unwrap()
+        Block::new(vec![coinbase], String::new(), 0, INITIAL_DIFFICULTY).unwrap()
     }
 
-    //* Function to prepare list of data for generating the proof of work */
-    fn prepare_hash_data(&self) -> Result<Vec<u8>, io::Error> {
-        //the whole content will collectively act as a source for generating POW
-        let content: (String, Vec<Transaction>, u128, usize, i32) = (
-            self.prev_block_hash.clone(),//previous block hash
-            self.transactions.clone(),//transactions
-            self.timestamp,//timestamp 
-            DIFFICULTY,//difficulty of the POW
-            self.nonce,//nonce
-        );
+    //* To compute the merkle root over the ids of a list of transactions */
+    fn compute_merkle_root(transactions: &[Transaction]) -> String {
+        let ids: Vec<String> = transactions.iter().map(|tx| tx.tranc_id.clone()).collect();
+        merkle_root(&ids)
+    }
 
-        //to serialize the list to binary format(a vector of)
-        let bytes: Vec<u8> = bincode::serialize(&content)
-            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;//for error mapping
-        Ok(bytes)
+    //* To check the merkle root of the header against the transactions of the block */
+    pub(crate) fn has_valid_merkle_root(&self) -> bool {
+        Block::compute_merkle_root(&self.transactions) == self.header.merkle_root
     }
 
-    //* To validate whther for the particular nonce the level of difficulty is reached or not */
-    fn validate(&self) -> Result<bool, io::Error> {
-        let data: Vec<u8> = self.prepare_hash_data()?;//getting hash data for validation
-        let mut hasher = Sha256::new();//using Sha256 algorithm to generate the hash
-        hasher.input(&data[..]);//digesting the hash data
-        Ok(hasher.result_str().starts_with(&"0".repeat(DIFFICULTY)))//checking the number of starting zeroes
+    //* To build a proof that a transaction is part of this block */
+    pub fn prove_transaction(&self, tranc_id: &str) -> Option<InclusionProof> {
+        let ids: Vec<String> = self.transactions.iter().map(|tx| tx.tranc_id.clone()).collect();
+        let index = ids.iter().position(|id| id == tranc_id)?;
+        InclusionProof::build(&ids, index)
+    }
+
+    //* To calculate the hash of the block for the current nonce, only the header is hashed */
+    pub(crate) fn calculate_hash(&self) -> Result<String, io::Error> {
+        self.header.hash()
     }
 
     //* Generating the POW for a particular block to validate the chain */
-    fn generate_proof_of_work(&mut self) {
-        info!("Doing the mining work on the block");//info message 
-        while !self.validate().unwrap_or_else(|e| {
-            todo!("NONCE_VALIDATION_ERROR: {}", e);//handling validation error
-        }) {
-            self.nonce += 1;//adjusting the nonce
+    fn generate_proof_of_work(&mut self, miner: &Miner) -> Result<MiningOutcome, io::Error> {
+        let outcome = miner.mine(&mut self.header)?;//searching the nonce on all worker threads
+        if let MiningOutcome::Found(_) = outcome {
+            //setting the POW for the particular block
+            self.hash = self.calculate_hash()?;
         }
-
-        //preparing the POW for the particular block
-        let data = self.prepare_hash_data().unwrap_or_else(|e| {
-            todo!("FETCHING_ERROR: {}", e);//handling error during fetching data
-        });
-
-        let mut hasher = Sha256::new();// using Sha256 algo to prepare the POW
-        hasher.input(&data);//digesting the hash data
-        self.hash = hasher.result_str();//setting the POW for the particular block
+        Ok(outcome)
     }
 
     //? Additional implementations of the Block Struct
 
     //* To get the previous hash of the block */
     pub fn get_previus_hash(&self) -> String {
-        self.prev_block_hash.clone()
+        self.header.prev_block_hash.clone()
+    }
+
+    //* To get the header of the block */
+    pub fn get_header(&self) -> &BlockHeader {
+        &self.header
     }
 
     //* To get the POW of the current block */
@@ -119,6 +182,16 @@ impl Block {
         self.hash.clone()
     }
 
+    //* To get the height of the block in the chain */
+    pub fn get_height(&self) -> usize {
+        self.header.height
+    }
+
+    //* To get the difficulty the block was mined with */
+    pub fn get_difficulty(&self) -> usize {
+        self.header.bits
+    }
+
     //* To get transaction details of the block */
     pub fn get_transaction(&self) -> Vec<Transaction> {
         self.transactions.clone()
@@ -0,0 +1,317 @@
+//? Parallel proof of work search over the nonce space of a block header
+use crate::miner::difficulty::digest_meets_difficulty;
+use crate::miner::mining::BlockHeader;
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use log::info;
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+//? size of the nonce at the end of the serialized header (bincode writes it as a little endian u32)
+const NONCE_SIZE: usize = 4;
+//? number of hashes a worker does between two looks at the clock
+const DEADLINE_CHECK_INTERVAL: u64 = 1024;
+
+//? Shared flag asking a running search to stop, cloned handles all see the same flag
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelToken {
+    //* To create a token that isn't cancelled yet */
+    pub fn new() -> Self {
+        CancelToken::default()
+    }
+
+    //* To ask every search using this token to stop */
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    //* To check whether the token was cancelled */
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+}
+
+//? Configuration of the proof of work search
+#[derive(Debug, Clone)]
+pub struct Miner {
+    threads: usize,//number of worker threads
+    max_nonce: u32,//last nonce tried before rolling the extra nonce over
+    cancel: CancelToken,//stops the search when cancelled
+    deadline: Option<Instant>,//time after which the search gives up
+    max_hashes: Option<u64>,//number of hashes after which the search gives up
+}
+
+//? Why a search ended without finding a proof of work
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StopReason {
+    Cancelled,
+    DeadlineReached,
+    MaxHashesReached,
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StopReason::Cancelled => write!(f, "cancelled"),
+            StopReason::DeadlineReached => write!(f, "deadline reached"),
+            StopReason::MaxHashesReached => write!(f, "hash limit reached"),
+        }
+    }
+}
+
+//? Result of a search: a proof of work or the progress made before it was stopped
+#[derive(Debug, Clone, PartialEq)]
+pub enum MiningOutcome {
+    Found(MiningStats),//the nonce fields of the header are set
+    Stopped(StopReason, MiningStats),//the header holds no valid proof of work
+}
+
+//? Outcome of a finished search
+#[derive(Debug, Clone, PartialEq)]
+pub struct MiningStats {
+    pub hashes: u64,//number of headers hashed by all threads
+    pub elapsed: Duration,//wall clock time of the search
+    pub threads: usize,//number of worker threads used
+}
+
+impl MiningStats {
+    //* To get the number of hashes per second */
+    pub fn hash_rate(&self) -> f64 {
+        self.hashes as f64 / self.elapsed.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+impl fmt::Display for MiningStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let rate = self.hash_rate();
+        let (rate, unit) = if rate >= 1e6 { (rate / 1e6, "MH/s") } else if rate >= 1e3 { (rate / 1e3, "kH/s") } else { (rate, "H/s") };
+        write!(f, "{} hashes in {:.2}s on {} threads ({:.2} {})", self.hashes, self.elapsed.as_secs_f64(), self.threads, rate, unit)
+    }
+}
+
+impl Default for Miner {
+    //* miner using every available core */
+    fn default() -> Self {
+        Miner::new(0)
+    }
+}
+
+impl Miner {
+    //* To create a miner with a number of worker threads, 0 meaning one per available core */
+    pub fn new(threads: usize) -> Self {
+        let threads = match threads {
+            0 => thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+            n => n,
+        };
+        Miner { threads, max_nonce: u32::MAX, cancel: CancelToken::new(), deadline: None, max_hashes: None }
+    }
+
+    //* To stop the search once the token is cancelled */
+    pub fn with_cancel(mut self, cancel: CancelToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    //* To give up the search at a point in time */
+    pub fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    //* To give up the search after a number of hashes over all threads */
+    pub fn with_max_hashes(mut self, max_hashes: u64) -> Self {
+        self.max_hashes = Some(max_hashes);
+        self
+    }
+
+    //* To check whether the search has to stop before trying another nonce */
+    fn stop_reason(&self, hashes: u64) -> Option<StopReason> {
+        if self.cancel.is_cancelled() {
+            Some(StopReason::Cancelled)
+        } else if self.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+            Some(StopReason::DeadlineReached)
+        } else if self.max_hashes.is_some_and(|max| hashes >= max) {
+            Some(StopReason::MaxHashesReached)
+        } else {
+            None
+        }
+    }
+
+    //* To search a nonce (and extra nonce) giving the header a hash meeting its difficulty */
+    /// Worker `i` of `n` tries the nonces `i, i+n, i+2n, ...`, the extra nonce being bumped once the 32-bit space is exhausted.
+    /// When found the nonce fields of the header are set. Either way the statistics of the search are returned.
+    pub fn mine(&self, header: &mut BlockHeader) -> Result<MiningOutcome, io::Error> {
+        let start = Instant::now();
+        let mut hashes = 0;
+        loop {
+            let stats = |hashes| MiningStats { hashes, elapsed: start.elapsed(), threads: self.threads };
+            if let Some(reason) = self.stop_reason(hashes) {
+                info!("Mining of block at height {} stopped, {}: {}", header.height, reason, stats(hashes));
+                return Ok(MiningOutcome::Stopped(reason, stats(hashes)));
+            }
+            let data: Vec<u8> = bincode::serialize(header)
+                .map_err(|e| io::Error::other(e.to_string()))?;//serialized once per extra nonce
+            let budget = self.max_hashes.map(|max| max - hashes);//hashes left for this round
+            let (nonce, tried) = self.search(&data, header.bits, budget);
+            hashes += tried;
+            if let Some(nonce) = nonce {
+                header.nonce = nonce;
+                let stats = stats(hashes);
+                info!("Mined block at height {}: {}", header.height, stats);//reporting the hash rate
+                return Ok(MiningOutcome::Found(stats));
+            }
+            if self.stop_reason(hashes).is_some() {
+                continue;//stopped during the round, reported above
+            }
+            //the nonce space is exhausted, changing the header and starting over
+            header.extra_nonce = header.extra_nonce.checked_add(1)
+                .ok_or_else(|| io::Error::other("EXTRA_NONCE_EXHAUSTED"))?;
+            info!("Nonce space exhausted, extra nonce rolled over to {}", header.extra_nonce);
+        }
+    }
+
+    //* To try every nonce of the current header on all threads, returning the winning nonce and the number of hashes */
+    /// budget: maximum number of hashes over all threads, split evenly between them
+    fn search(&self, data: &[u8], bits: usize, budget: Option<u64>) -> (Option<u32>, u64) {
+        let found = AtomicBool::new(false);//stops the other workers once a nonce is found
+        let winner: Mutex<Option<u32>> = Mutex::new(None);
+        let hashes = AtomicU64::new(0);
+        thread::scope(|scope| {
+            for worker in 0..self.threads {
+                let (found, winner, hashes) = (&found, &winner, &hashes);
+                //share of the budget of this worker, the first ones taking the remainder
+                let budget = budget.map(|budget| budget / self.threads as u64 + u64::from((worker as u64) < budget % self.threads as u64));
+                scope.spawn(move || {
+                    let mut buffer = data.to_vec();
+                    let offset = buffer.len() - NONCE_SIZE;
+                    let mut hasher = Sha256::new();
+                    let mut digest = [0u8; 32];
+                    let mut tried = 0;
+                    let mut nonce = worker as u64;//u64 so the last stride can't overflow
+                    while nonce <= self.max_nonce as u64 && !found.load(Ordering::Relaxed) && !self.cancel.is_cancelled() {
+                        if budget.is_some_and(|budget| tried >= budget) {
+                            break;
+                        }
+                        if tried % DEADLINE_CHECK_INTERVAL == 0 && self.deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                            break;
+                        }
+                        buffer[offset..].copy_from_slice(&(nonce as u32).to_le_bytes());//swapping only the nonce
+                        hasher.reset();
+                        hasher.input(&buffer);
+                        hasher.result(&mut digest);
+                        tried += 1;
+                        if digest_meets_difficulty(&digest, bits) {
+                            if !found.swap(true, Ordering::Relaxed) {
+                                *winner.lock().unwrap_or_else(|e| e.into_inner()) = Some(nonce as u32);
+                            }
+                            break;
+                        }
+                        nonce += self.threads as u64;
+                    }
+                    hashes.fetch_add(tried, Ordering::Relaxed);
+                });
+            }
+        });
+        let nonce = *winner.lock().unwrap_or_else(|e| e.into_inner());
+        (nonce, hashes.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::miner::mining::BLOCK_VERSION;
+
+    fn header(bits: usize) -> BlockHeader {
+        BlockHeader {
+            version: BLOCK_VERSION,
+            prev_block_hash: String::from("prev"),
+            merkle_root: String::from("root"),
+            timestamp: 1,
+            height: 1,
+            bits,
+            extra_nonce: 0,
+            nonce: 0,
+        }
+    }
+
+    #[test]
+    fn test_nonce_is_last_in_serialized_header() {
+        let mut h = header(4);
+        h.nonce = 0x01020304;
+        let data = bincode::serialize(&h).unwrap();
+        assert_eq!(&data[data.len() - NONCE_SIZE..], &0x01020304u32.to_le_bytes());
+    }
+
+    #[test]
+    fn test_parallel_mining_meets_difficulty() {
+        for threads in [1, 4] {
+            let mut h = header(10);
+            let stats = match Miner::new(threads).mine(&mut h).unwrap() {
+                MiningOutcome::Found(stats) => stats,
+                stopped => panic!("unbounded search stopped: {:?}", stopped),
+            };
+            assert!(h.meets_difficulty().unwrap());
+            assert_eq!(stats.threads, threads);
+            assert!(stats.hashes >= 1);
+            assert!(stats.hash_rate() > 0.0);
+        }
+    }
+
+    #[test]
+    fn test_extra_nonce_rolls_over() {
+        //a tiny nonce space forces the miner to change the extra nonce
+        let miner = Miner { max_nonce: 3, ..Miner::new(2) };
+        let mut h = header(12);
+        let MiningOutcome::Found(stats) = miner.mine(&mut h).unwrap() else { panic!("search stopped") };
+        assert!(h.meets_difficulty().unwrap());
+        assert!(h.extra_nonce > 0);
+        assert!(h.nonce <= 3);
+        //every exhausted round hashed the 4 nonces, the last one stopped early at most
+        let rounds = h.extra_nonce as u64;
+        assert!(stats.hashes > 4 * rounds && stats.hashes <= 4 * (rounds + 1));
+    }
+
+    #[test]
+    fn test_cancelled_search_stops() {
+        let cancel = CancelToken::new();
+        let miner = Miner::new(2).with_cancel(cancel.clone());
+        let canceller = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            cancel.cancel();
+        });
+        let mut h = header(255);//never found
+        let outcome = miner.mine(&mut h).unwrap();
+        canceller.join().unwrap();
+        assert!(matches!(outcome, MiningOutcome::Stopped(StopReason::Cancelled, _)));
+    }
+
+    #[test]
+    fn test_search_stops_at_deadline() {
+        let miner = Miner::new(2).with_deadline(Instant::now() + Duration::from_millis(50));
+        let mut h = header(255);
+        let outcome = miner.mine(&mut h).unwrap();
+        assert!(matches!(outcome, MiningOutcome::Stopped(StopReason::DeadlineReached, ref stats) if stats.hashes > 0));
+    }
+
+    #[test]
+    fn test_search_stops_after_max_hashes() {
+        //the limit holds across extra nonce rounds too
+        let miner = Miner { max_nonce: 99, ..Miner::new(3) }.with_max_hashes(1000);
+        let mut h = header(255);
+        let outcome = miner.mine(&mut h).unwrap();
+        assert_eq!(h.extra_nonce, 9);//ten rounds of 100 nonces, the last one not rolled over
+        match outcome {
+            MiningOutcome::Stopped(StopReason::MaxHashesReached, stats) => assert_eq!(stats.hashes, 1000),
+            other => panic!("unexpected outcome {:?}", other),
+        }
+    }
+}
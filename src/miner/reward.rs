@@ -0,0 +1,123 @@
+//? Block reward rules of the chain
+use serde::{Deserialize, Serialize};
+use std::io;
+use crate::wallet::amount::Amount;
+
+//? subsidy of the genesis block and every block up to the first halving
+pub const INITIAL_SUBSIDY: Amount = Amount::from_coins(100);
+//? number of blocks between two halvings of the subsidy
+pub const HALVING_INTERVAL: usize = 1000;
+//? number of blocks after its own a coinbase output becomes spendable, well past a retarget so reorgs rarely undo spent rewards
+pub const COINBASE_MATURITY: usize = 100;
+//? coinbase maturity of genesis blocks written before the schedule was part of them
+const LEGACY_COINBASE_MATURITY: usize = 1;
+//? marker in front of the schedule in the genesis coinbase data
+const GENESIS_TAG: &str = " rewards:";
+
+//? Reward rules a chain was created with
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct RewardSchedule {
+    pub initial_subsidy: Amount,//subsidy before the first halving
+    pub halving_interval: usize,//blocks between two halvings
+    pub coinbase_maturity: usize,//blocks to wait before spending a coinbase
+}
+
+impl Default for RewardSchedule {
+    fn default() -> Self {
+        RewardSchedule {
+            initial_subsidy: INITIAL_SUBSIDY,
+            halving_interval: HALVING_INTERVAL,
+            coinbase_maturity: COINBASE_MATURITY,
+        }
+    }
+}
+
+impl RewardSchedule {
+    //* To create a schedule, refusing intervals that can't work */
+    pub fn new(initial_subsidy: Amount, halving_interval: usize, coinbase_maturity: usize) -> Result<Self, io::Error> {
+        if halving_interval == 0 || coinbase_maturity == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!(
+                "INVALID_REWARD_SCHEDULE: halving interval {} and coinbase maturity {} must be at least 1",
+                halving_interval, coinbase_maturity,
+            )));
+        }
+        Ok(RewardSchedule { initial_subsidy, halving_interval, coinbase_maturity })
+    }
+
+    //* To write the schedule after the message of a genesis coinbase, so the genesis hash commits to it */
+    pub fn genesis_data(&self, message: &str) -> String {
+        format!("{}{}{}/{}/{}", message, GENESIS_TAG, self.initial_subsidy.units(), self.halving_interval, self.coinbase_maturity)
+    }
+
+    //* To read the schedule back from the data of a genesis coinbase, genesis blocks without one follow the rules they were created with */
+    pub fn from_genesis_data(data: &[u8]) -> Result<Self, io::Error> {
+        let data = String::from_utf8_lossy(data);
+        let Some((_, schedule)) = data.rsplit_once(GENESIS_TAG) else {
+            return Ok(RewardSchedule { coinbase_maturity: LEGACY_COINBASE_MATURITY, ..RewardSchedule::default() });
+        };
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, format!("INVALID_REWARD_SCHEDULE: can't read '{}' from the genesis coinbase", schedule));
+        let mut parts = schedule.split('/');
+        let mut next = || parts.next().ok_or_else(invalid);
+        let initial_subsidy = next()?.parse::<u64>().map_err(|_| invalid())?;
+        let halving_interval = next()?.parse::<usize>().map_err(|_| invalid())?;
+        let coinbase_maturity = next()?.parse::<usize>().map_err(|_| invalid())?;
+        if parts.next().is_some() {
+            return Err(invalid());
+        }
+        RewardSchedule::new(Amount::from_units(initial_subsidy), halving_interval, coinbase_maturity)
+    }
+
+    //* To get the subsidy a block at a height may create */
+    pub fn subsidy(&self, height: usize) -> Amount {
+        self.initial_subsidy.halved(height / self.halving_interval)
+    }
+
+    //* To check whether a coinbase confirmed at a height can be spent by a block at another height */
+    pub fn is_mature(&self, coinbase_height: usize, spend_height: usize) -> bool {
+        spend_height >= coinbase_height + self.coinbase_maturity
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subsidy_halves() {
+        let schedule = RewardSchedule::new(Amount::from_units(100), 10, 1).unwrap();
+        assert_eq!(schedule.subsidy(0), Amount::from_units(100));
+        assert_eq!(schedule.subsidy(9), Amount::from_units(100));
+        assert_eq!(schedule.subsidy(10), Amount::from_units(50));
+        assert_eq!(schedule.subsidy(25), Amount::from_units(25));
+        assert_eq!(schedule.subsidy(60), Amount::from_units(1));
+        assert_eq!(schedule.subsidy(70), Amount::ZERO);
+        assert_eq!(schedule.subsidy(usize::MAX), Amount::ZERO);
+        assert_eq!(RewardSchedule::default().subsidy(640 * HALVING_INTERVAL), Amount::ZERO);
+    }
+
+    #[test]
+    fn test_coinbase_maturity() {
+        let schedule = RewardSchedule::new(INITIAL_SUBSIDY, 10, 3).unwrap();
+        assert!(!schedule.is_mature(5, 5));
+        assert!(!schedule.is_mature(5, 7));
+        assert!(schedule.is_mature(5, 8));
+        assert!(RewardSchedule::new(INITIAL_SUBSIDY, 0, 1).is_err());
+        assert!(RewardSchedule::new(INITIAL_SUBSIDY, 10, 0).is_err());
+        //by default a reward stays unspendable for longer than a difficulty period
+        assert!(RewardSchedule::default().coinbase_maturity > crate::miner::difficulty::RETARGET_INTERVAL);
+    }
+
+    #[test]
+    fn test_genesis_data_round_trip() {
+        let schedule = RewardSchedule::new(Amount::from_units(4000), 20, 3).unwrap();
+        let data = schedule.genesis_data("Default coinbase");
+        assert_eq!(data, "Default coinbase rewards:4000/20/3");
+        assert_eq!(RewardSchedule::from_genesis_data(data.as_bytes()).unwrap(), schedule);
+        //genesis blocks written before the schedule was part of them
+        let legacy = RewardSchedule::from_genesis_data(b"Default coinbase").unwrap();
+        assert_eq!(legacy, RewardSchedule::new(INITIAL_SUBSIDY, HALVING_INTERVAL, 1).unwrap());
+        assert!(RewardSchedule::from_genesis_data(b"Default coinbase rewards:4000/20").is_err());
+        assert!(RewardSchedule::from_genesis_data(b"Default coinbase rewards:4000/0/3").is_err());
+        assert!(RewardSchedule::from_genesis_data(b"Default coinbase rewards:4000/20/3/1").is_err());
+    }
+}
@@ -0,0 +1,112 @@
+//? Messages nodes exchange over TCP
+use std::io::{self, Read, Write};
+use serde::{Deserialize, Serialize};
+use crate::miner::mining::{Block, BlockHeader};
+use crate::wallet::transaction::Transaction;
+
+//? version of the protocol spoken by this node
+pub const PROTOCOL_VERSION: u32 = 2;
+//? size in bytes above which a message is refused
+pub const MAX_MESSAGE_SIZE: usize = 32 * 1024 * 1024;
+//? number of headers sent at most in answer to a `GetHeaders`, a full batch meaning more follow
+pub const MAX_HEADERS: usize = 2000;
+
+//? Id of a block or transaction a node can send
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Inventory {
+    Block(String),//block hash
+    Transaction(String),//transaction id
+}
+
+//? A message of the protocol
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Message {
+    Version {
+        version: u32,//PROTOCOL_VERSION of the sender
+        genesis: String,//hash of the genesis block, nodes of other chains are dropped
+        best_height: usize,//height of the tip of the sender
+        nonce: u64,//random for every node run, telling connections to itself apart
+    },
+    Verack,//the version of the other side is accepted
+    Inv(Vec<Inventory>),//blocks and transactions the sender has
+    GetData(Vec<Inventory>),//blocks and transactions the sender wants
+    GetHeaders { locator: Vec<String> },//hashes of the best chain of the sender, from its last header down to genesis
+    Headers(Vec<BlockHeader>),//headers following the locator, parents first
+    Block(Block),
+    Transaction(Transaction),
+}
+
+impl Message {
+    //* To get the name of the message, for logs */
+    pub fn name(&self) -> &'static str {
+        match self {
+            Message::Version { .. } => "version",
+            Message::Verack => "verack",
+            Message::Inv(_) => "inv",
+            Message::GetData(_) => "getdata",
+            Message::GetHeaders { .. } => "getheaders",
+            Message::Headers(_) => "headers",
+            Message::Block(_) => "block",
+            Message::Transaction(_) => "tx",
+        }
+    }
+}
+
+//* To write a message with its length in front */
+pub fn write_message<W: Write>(writer: &mut W, message: &Message) -> Result<(), io::Error> {
+    let data = bincode::serialize(message).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+    if data.len() > MAX_MESSAGE_SIZE {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("MESSAGE_TOO_LARGE: {} bytes", data.len())));
+    }
+    writer.write_all(&(data.len() as u32).to_be_bytes())?;
+    writer.write_all(&data)?;
+    writer.flush()
+}
+
+//* To read the next message, failing with `ErrorKind::UnexpectedEof` once the other side closed the connection */
+pub fn read_message<R: Read>(reader: &mut R) -> Result<Message, io::Error> {
+    let mut length = [0u8; 4];
+    reader.read_exact(&mut length)?;
+    let length = u32::from_be_bytes(length) as usize;
+    if length > MAX_MESSAGE_SIZE {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("MESSAGE_TOO_LARGE: {} bytes", length)));
+    }
+    let mut data = vec![0u8; length];
+    reader.read_exact(&mut data)?;
+    bincode::deserialize(&data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, format!("BAD_MESSAGE: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::miner::reward::INITIAL_SUBSIDY;
+    use crate::test_utils::test_keys;
+
+    #[test]
+    fn test_messages_round_trip() {
+        let (_, _, address) = test_keys(1);
+        let block = Block::default(Transaction::new_reward(address, String::new(), INITIAL_SUBSIDY).unwrap());
+        let mut wire = Vec::new();
+        write_message(&mut wire, &Message::Version { version: PROTOCOL_VERSION, genesis: block.get_hash(), best_height: 3, nonce: 7 }).unwrap();
+        write_message(&mut wire, &Message::Inv(vec![Inventory::Block(block.get_hash())])).unwrap();
+        write_message(&mut wire, &Message::Block(block.clone())).unwrap();
+        write_message(&mut wire, &Message::Headers(vec![block.get_header().clone()])).unwrap();
+
+        let mut reader = io::Cursor::new(wire);
+        assert!(matches!(read_message(&mut reader).unwrap(), Message::Version { best_height: 3, nonce: 7, .. }));
+        assert!(matches!(read_message(&mut reader).unwrap(), Message::Inv(items) if items == vec![Inventory::Block(block.get_hash())]));
+        assert!(matches!(read_message(&mut reader).unwrap(), Message::Block(received) if received.get_hash() == block.get_hash()));
+        assert!(matches!(read_message(&mut reader).unwrap(), Message::Headers(headers) if headers == vec![block.get_header().clone()]));
+        assert_eq!(read_message(&mut reader).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_bad_frames_are_refused() {
+        let mut oversized = io::Cursor::new(((MAX_MESSAGE_SIZE + 1) as u32).to_be_bytes().to_vec());
+        assert!(read_message(&mut oversized).unwrap_err().to_string().starts_with("MESSAGE_TOO_LARGE"));
+        let mut garbage = io::Cursor::new([0, 0, 0, 2, 0xff, 0xff].to_vec());
+        assert!(read_message(&mut garbage).unwrap_err().to_string().starts_with("BAD_MESSAGE"));
+        let mut truncated = io::Cursor::new([0, 0, 0, 9, 1].to_vec());
+        assert_eq!(read_message(&mut truncated).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,814 @@
+//? Peer to peer node relaying blocks and transactions over TCP
+use std::collections::HashMap;
+use std::io;
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use log::{debug, info, warn};
+use crate::miner::chain::{Blockchain, ChainEvent};
+use crate::miner::mempool::{Mempool, MAX_BLOCK_SIZE};
+use crate::miner::mining::{Block, MinedBlock};
+use crate::miner::pow::{CancelToken, Miner, MiningStats};
+use crate::network::message::{read_message, write_message, Inventory, Message, MAX_HEADERS, PROTOCOL_VERSION};
+use crate::wallet::amount::Amount;
+use crate::wallet::transaction::Transaction;
+
+//? address the node listens on when none is given
+pub const DEFAULT_LISTEN_ADDR: &str = "127.0.0.1:8335";
+//? number of peers dialing the node it serves at once when none is given
+pub const DEFAULT_MAX_INBOUND: usize = 32;
+//? number of messages waiting to be written to a peer before it is dropped
+const OUTBOX_SIZE: usize = 256;
+//? time between two attempts to reach the configured peers
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+//? time given to a configured peer to accept a connection
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+//? time between two checks for new connections or a shutdown
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+//? number of blocks asked from a single peer at once
+const MAX_BLOCKS_IN_FLIGHT: usize = 16;
+//? number of blocks above the active chain looked at for downloads
+const DOWNLOAD_WINDOW: usize = 1024;
+//? time after which a block asked for is asked again, from any peer
+const BLOCK_TIMEOUT: Duration = Duration::from_secs(30);
+//? time between two checks for slow downloads
+const DOWNLOAD_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+//? What a node is started with
+#[derive(Debug, Clone)]
+pub struct NodeConfig {
+    pub listen: String,//address accepting peers, port 0 for any free port
+    pub peers: Vec<String>,//addresses of the peers to stay connected to
+    pub max_inbound: usize,//number of peers dialing the node it serves at once, the configured peers aside
+    pub mine_to: Option<String>,//address rewarded for the blocks mined by the node, no mining when not given
+    pub threads: usize,//number of threads mining, 0 for one per core
+}
+
+//? Connection to a peer
+struct Peer {
+    addr: SocketAddr,
+    stream: TcpStream,//shut down to close the connection
+    outbox: SyncSender<Message>,//messages waiting for the thread writing to the peer
+    outbound: Option<String>,//configured address when this node dialed the peer
+    version: bool,//the version of the peer was accepted
+    verack: bool,//the peer accepted the version of this node
+    best_height: usize,//highest block the peer is known to have
+}
+
+impl Peer {
+    //* To check whether the handshake with the peer is done */
+    fn is_ready(&self) -> bool {
+        self.version && self.verack
+    }
+
+    //* To queue a message for the thread writing to the peer, closing the connection when the peer doesn't keep up */
+    fn queue(&self, message: &Message) {
+        match self.outbox.try_send(message.clone()) {
+            Ok(()) => (),
+            Err(TrySendError::Full(_)) => {
+                warn!("Dropping peer {}: {} messages waiting to be sent", self.addr, OUTBOX_SIZE);
+                let _ = self.stream.shutdown(Shutdown::Both);//the thread serving the peer cleans up
+            }
+            Err(TrySendError::Disconnected(_)) => (),//the writer gave up on the connection already
+        }
+    }
+}
+
+//? State shared by the threads of a node
+struct Shared {
+    chain: Mutex<Blockchain>,
+    mempool: Mutex<Mempool>,//always locked after the chain when both are
+    peers: Mutex<HashMap<usize, Peer>>,
+    in_flight: Mutex<HashMap<String, (usize, Instant)>>,//blocks asked for, with the peer and when, locked before the peers
+    next_peer: AtomicUsize,
+    max_inbound: usize,
+    genesis: String,//hash of the genesis block, peers have to agree on it
+    nonce: u64,//sent in the version to find connections to this node itself
+    threads: usize,
+    mining: Mutex<CancelToken>,//stops the current mining attempt
+    stop: CancelToken,//stops every thread of the node
+}
+
+//? Running node, stopped by `shutdown`
+pub struct Node {
+    shared: Arc<Shared>,
+    local_addr: SocketAddr,
+    workers: Vec<JoinHandle<()>>,
+}
+
+//? Access to the chain and mempool of a running node from other threads, like the ones answering RPC calls
+#[derive(Clone)]
+pub struct NodeHandle {
+    shared: Arc<Shared>,
+}
+
+impl NodeHandle {
+    //* To look at the chain and the mempool, both locked meanwhile */
+    pub fn with_chain<R>(&self, f: impl FnOnce(&Blockchain, &Mempool) -> R) -> R {
+        let chain = lock(&self.shared.chain);
+        let mempool = lock(&self.shared.mempool);
+        f(&chain, &mempool)
+    }
+
+    //* To add a transaction to the mempool and announce it to the peers, returning its fee */
+    pub fn add_transaction(&self, tx: Transaction) -> Result<Amount, io::Error> {
+        self.shared.add_transaction(tx, None)
+    }
+
+    //* To get the number of peers the handshake is done with */
+    pub fn peer_count(&self) -> usize {
+        self.shared.peer_count()
+    }
+}
+
+impl Node {
+    //* To start listening, dialing the configured peers and mining when an address to reward is given */
+    pub fn start(chain: Blockchain, mempool: Mempool, config: NodeConfig) -> Result<Node, io::Error> {
+        let genesis = chain.get_header_by_height(0)?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "MISSING_GENESIS: the chain has no block at height 0"))?;
+        let genesis = genesis.hash()?;
+        let listener = TcpListener::bind(&config.listen)?;
+        listener.set_nonblocking(true)?;//polled, to notice the shutdown
+        let local_addr = listener.local_addr()?;
+        let shared = Arc::new(Shared {
+            chain: Mutex::new(chain),
+            mempool: Mutex::new(mempool),
+            peers: Mutex::new(HashMap::new()),
+            in_flight: Mutex::new(HashMap::new()),
+            next_peer: AtomicUsize::new(0),
+            max_inbound: config.max_inbound,
+            genesis,
+            nonce: rand::random(),
+            threads: config.threads,
+            mining: Mutex::new(CancelToken::new()),
+            stop: CancelToken::new(),
+        });
+        info!("Listening for peers on {}", local_addr);
+
+        let mut workers = Vec::new();
+        let listening = Arc::clone(&shared);
+        workers.push(thread::spawn(move || listening.accept_peers(listener)));
+        let watching = Arc::clone(&shared);
+        workers.push(thread::spawn(move || watching.watch_downloads()));
+        if !config.peers.is_empty() {
+            let dialing = Arc::clone(&shared);
+            workers.push(thread::spawn(move || dialing.connect_peers(config.peers)));
+        }
+        if let Some(to) = config.mine_to {
+            let mining = Arc::clone(&shared);
+            workers.push(thread::spawn(move || mining.mine_continuously(&to)));
+        }
+        Ok(Node { shared, local_addr, workers })
+    }
+
+    //* To get the address the node listens on */
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    //* To get a handle on the node for the threads serving it */
+    pub fn handle(&self) -> NodeHandle {
+        NodeHandle { shared: Arc::clone(&self.shared) }
+    }
+
+    //* To close every connection and wait for the threads of the node, returning the hash and height of the tip it stopped at */
+    pub fn shutdown(self) -> Result<(String, usize), io::Error> {
+        self.shared.stop.cancel();
+        lock(&self.shared.mining).cancel();
+        for peer in lock(&self.shared.peers).values() {
+            let _ = peer.stream.shutdown(Shutdown::Both);//ends the thread reading from it
+        }
+        for worker in self.workers {
+            let _ = worker.join();
+        }
+        info!("Node stopped");
+        let chain = lock(&self.shared.chain);
+        Ok((chain.tip_hash(), chain.get_best_height()?))
+    }
+}
+
+//* To lock a mutex, going on with the data when a thread panicked while holding it */
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+//* To build the error dropping a peer breaking the protocol */
+fn protocol_error(reason: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+impl Shared {
+    //* To accept incoming connections until the shutdown */
+    fn accept_peers(self: Arc<Self>, listener: TcpListener) {
+        while !self.stop.is_cancelled() {
+            match listener.accept() {
+                Ok((stream, addr)) => {
+                    if lock(&self.peers).values().filter(|peer| peer.outbound.is_none()).count() >= self.max_inbound {
+                        debug!("Turning {} away, {} peers connected to this node already", addr, self.max_inbound);
+                        let _ = stream.shutdown(Shutdown::Both);
+                        continue;
+                    }
+                    if let Err(e) = stream.set_nonblocking(false).and_then(|_| Arc::clone(&self).add_peer(stream, None)) {
+                        warn!("Can't serve an incoming peer: {}", e);
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(POLL_INTERVAL),
+                Err(e) => {
+                    warn!("Can't accept peers: {}", e);
+                    thread::sleep(POLL_INTERVAL);
+                }
+            }
+        }
+    }
+
+    //* To dial the configured peers not connected, again and again until the shutdown */
+    fn connect_peers(self: Arc<Self>, addresses: Vec<String>) {
+        while !self.stop.is_cancelled() {
+            for address in &addresses {
+                let connected = lock(&self.peers).values().any(|peer| peer.outbound.as_ref() == Some(address));
+                if connected || self.stop.is_cancelled() {
+                    continue;
+                }
+                let stream = address.to_socket_addrs().and_then(|mut addrs| {
+                    let addr = addrs.next().ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("NO_ADDRESS: {}", address)))?;
+                    TcpStream::connect_timeout(&addr, CONNECT_TIMEOUT)
+                });
+                match stream.and_then(|stream| Arc::clone(&self).add_peer(stream, Some(address.clone()))) {
+                    Ok(()) => (),
+                    Err(e) => debug!("Can't reach peer {}: {}", address, e),
+                }
+            }
+            let wake = Instant::now() + RECONNECT_INTERVAL;
+            while Instant::now() < wake && !self.stop.is_cancelled() {
+                thread::sleep(POLL_INTERVAL);
+            }
+        }
+    }
+
+    //* To register a connection and serve it from new threads, sending the version first on connections this node opened */
+    fn add_peer(self: Arc<Self>, stream: TcpStream, outbound: Option<String>) -> Result<(), io::Error> {
+        let addr = stream.peer_addr()?;
+        let id = self.next_peer.fetch_add(1, Ordering::SeqCst);
+        let (outbox, queue) = mpsc::sync_channel::<Message>(OUTBOX_SIZE);
+        let mut writer = stream.try_clone()?;
+        thread::spawn(move || {
+            //ends once the peer is forgotten along with the sending side of the queue
+            for message in queue {
+                if let Err(e) = write_message(&mut writer, &message) {
+                    debug!("Can't send {} to {}: {}", message.name(), addr, e);
+                    let _ = writer.shutdown(Shutdown::Both);//the thread serving the peer cleans up
+                    break;
+                }
+            }
+        });
+        let peer = Peer { addr, stream: stream.try_clone()?, outbox, outbound, version: false, verack: false, best_height: 0 };
+        let dialed = peer.outbound.is_some();
+        lock(&self.peers).insert(id, peer);
+        if dialed {
+            let version = self.version()?;
+            self.send(id, &version);
+        }
+        thread::spawn(move || {
+            self.serve(id, stream);
+            if let Some(peer) = lock(&self.peers).remove(&id) {
+                info!("Disconnected from {}", peer.addr);
+            }
+            //its downloads go to the other peers
+            lock(&self.in_flight).retain(|_, (peer, _)| *peer != id);
+            if let Err(e) = self.request_blocks() {
+                warn!("Can't ask for blocks: {}", e);
+            }
+        });
+        Ok(())
+    }
+
+    //* To handle the messages of a peer until it leaves or breaks the protocol */
+    fn serve(&self, id: usize, mut stream: TcpStream) {
+        loop {
+            match read_message(&mut stream) {
+                Ok(message) => {
+                    if let Err(e) = self.handle(id, message) {
+                        warn!("Dropping peer {}: {}", self.peer_addr(id), e);
+                        break;
+                    }
+                }
+                Err(e) => {
+                    if e.kind() != io::ErrorKind::UnexpectedEof && !self.stop.is_cancelled() {
+                        warn!("Dropping peer {}: {}", self.peer_addr(id), e);
+                    }
+                    break;
+                }
+            }
+        }
+        let _ = stream.shutdown(Shutdown::Both);
+    }
+
+    //* To answer a message of a peer, failing when the peer has to be dropped */
+    fn handle(&self, id: usize, message: Message) -> Result<(), io::Error> {
+        debug!("Received {} from {}", message.name(), self.peer_addr(id));
+        let ready = lock(&self.peers).get(&id).is_some_and(Peer::is_ready);
+        match message {
+            Message::Version { version, genesis, best_height, nonce } => {
+                if nonce == self.nonce {
+                    return Err(protocol_error(String::from("SELF_CONNECTION: the peer is this node")));
+                }
+                if version != PROTOCOL_VERSION {
+                    return Err(protocol_error(format!("VERSION_MISMATCH: speaking version {}, found {}", PROTOCOL_VERSION, version)));
+                }
+                if genesis != self.genesis {
+                    return Err(protocol_error(format!("GENESIS_MISMATCH: the peer follows the chain of {}", genesis)));
+                }
+                let dialed = {
+                    let mut peers = lock(&self.peers);
+                    let peer = peers.get_mut(&id).ok_or_else(|| protocol_error(String::from("UNKNOWN_PEER")))?;
+                    if peer.version {
+                        return Err(protocol_error(String::from("DUPLICATE_VERSION")));
+                    }
+                    peer.version = true;
+                    peer.best_height = best_height;
+                    peer.outbound.is_some()
+                };
+                info!("Connected to {} at height {}", self.peer_addr(id), best_height);
+                if !dialed {
+                    let version = self.version()?;
+                    self.send(id, &version);
+                }
+                self.send(id, &Message::Verack);
+                self.on_handshake(id)
+            }
+            Message::Verack => {
+                if let Some(peer) = lock(&self.peers).get_mut(&id) {
+                    peer.verack = true;
+                }
+                self.on_handshake(id)
+            }
+            _ if !ready => Err(protocol_error(format!("HANDSHAKE_MISSING: {} before the version", message.name()))),
+            Message::Inv(items) => {
+                let mut wanted = Vec::new();
+                {
+                    let chain = lock(&self.chain);
+                    let mempool = lock(&self.mempool);
+                    for item in items {
+                        let known = match &item {
+                            Inventory::Block(hash) => chain.has_block(hash)?,
+                            Inventory::Transaction(tranc_id) => mempool.get(tranc_id).is_some(),
+                        };
+                        if !known {
+                            wanted.push(item);
+                        }
+                    }
+                }
+                {
+                    let mut in_flight = lock(&self.in_flight);
+                    wanted.retain(|item| match item {
+                        Inventory::Block(hash) if in_flight.contains_key(hash) => false,
+                        Inventory::Block(hash) => in_flight.insert(hash.clone(), (id, Instant::now())).is_none(),
+                        Inventory::Transaction(_) => true,
+                    });
+                }
+                if !wanted.is_empty() {
+                    self.send(id, &Message::GetData(wanted));
+                }
+                Ok(())
+            }
+            Message::GetData(items) => {
+                let mut answers = Vec::new();
+                {
+                    let chain = lock(&self.chain);
+                    let mempool = lock(&self.mempool);
+                    for item in items {
+                        match item {
+                            Inventory::Block(hash) => answers.extend(chain.get_block_by_hash(&hash)?.map(Message::Block)),
+                            Inventory::Transaction(tranc_id) => answers.extend(mempool.get(&tranc_id).map(|entry| Message::Transaction(entry.transaction.clone()))),
+                        }
+                    }
+                }
+                for answer in answers {
+                    self.send(id, &answer);
+                }
+                Ok(())
+            }
+            Message::GetHeaders { locator } => {
+                let headers = lock(&self.chain).headers_after(&locator, MAX_HEADERS)?;
+                if !headers.is_empty() {
+                    self.send(id, &Message::Headers(headers));
+                }
+                Ok(())
+            }
+            Message::Headers(headers) => {
+                if headers.len() > MAX_HEADERS {
+                    return Err(protocol_error(format!("TOO_MANY_HEADERS: {} in one message", headers.len())));
+                }
+                let last_height = headers.last().map_or(0, |header| header.height);
+                let added = match lock(&self.chain).submit_headers(&headers) {
+                    Ok(added) => added,
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                        debug!("Headers of {} don't link to the chain: {}", self.peer_addr(id), e);
+                        return Ok(());
+                    }
+                    Err(e) => return Err(e),//invalid headers
+                };
+                if let Some(peer) = lock(&self.peers).get_mut(&id) {
+                    peer.best_height = peer.best_height.max(last_height);
+                }
+                if added > 0 {
+                    info!("Received {} headers up to height {} from {}", added, last_height, self.peer_addr(id));
+                }
+                if headers.len() == MAX_HEADERS {
+                    self.ask_headers(id)?;//more follow
+                }
+                self.request_blocks()
+            }
+            Message::Block(block) => {
+                lock(&self.in_flight).remove(&block.get_hash());
+                if let Some(peer) = lock(&self.peers).get_mut(&id) {
+                    peer.best_height = peer.best_height.max(block.get_height());
+                }
+                self.accept_block(block, Some(id))?;
+                self.request_blocks()
+            }
+            Message::Transaction(tx) => {
+                let tranc_id = tx.tranc_id.clone();
+                if let Err(e) = self.add_transaction(tx, Some(id)) {
+                    debug!("Refused transaction {} from {}: {}", tranc_id, self.peer_addr(id), e);
+                }
+                Ok(())
+            }
+        }
+    }
+
+    //* To catch up with a peer and announce the mempool to it once the handshake is done */
+    fn on_handshake(&self, id: usize) -> Result<(), io::Error> {
+        if !lock(&self.peers).get(&id).is_some_and(Peer::is_ready) {
+            return Ok(());
+        }
+        self.ask_headers(id)?;
+        self.request_blocks()?;//left over from an interrupted sync
+        let pending: Vec<Inventory> = lock(&self.mempool).entries().into_iter()
+            .map(|entry| Inventory::Transaction(entry.transaction.tranc_id.clone()))
+            .collect();
+        if !pending.is_empty() {
+            self.send(id, &Message::Inv(pending));
+        }
+        Ok(())
+    }
+
+    //* To ask a peer for the headers following the best known header */
+    /// Done once the handshake is done, when a block arrives without its parent and after every full batch of headers.
+    fn ask_headers(&self, id: usize) -> Result<(), io::Error> {
+        let locator = lock(&self.chain).locator()?;
+        self.send(id, &Message::GetHeaders { locator });
+        Ok(())
+    }
+
+    //* To ask the peers for the missing blocks of the best header chain not asked for yet */
+    /// Headers and blocks are kept by the chain as they arrive, so a sync stopped halfway goes on at the next start.
+    fn request_blocks(&self) -> Result<(), io::Error> {
+        let missing = lock(&self.chain).missing_blocks(DOWNLOAD_WINDOW)?;
+        if missing.is_empty() {
+            return Ok(());
+        }
+        let requests = {
+            let mut in_flight = lock(&self.in_flight);
+            let missing: Vec<(String, usize)> = missing.into_iter().filter(|(hash, _)| !in_flight.contains_key(hash)).collect();
+            let mut peers: Vec<(usize, usize, usize)> = lock(&self.peers).iter()
+                .filter(|(_, peer)| peer.is_ready())
+                .map(|(id, peer)| (*id, peer.best_height, in_flight.values().filter(|(asked, _)| asked == id).count()))
+                .collect();
+            let requests = assign_downloads(&missing, &mut peers);
+            let now = Instant::now();
+            for (id, hashes) in &requests {
+                for hash in hashes {
+                    in_flight.insert(hash.clone(), (*id, now));
+                }
+            }
+            requests
+        };
+        for (id, hashes) in requests {
+            debug!("Asking {} for {} blocks", self.peer_addr(id), hashes.len());
+            self.send(id, &Message::GetData(hashes.into_iter().map(Inventory::Block).collect()));
+        }
+        Ok(())
+    }
+
+    //* To ask again for the blocks peers are too slow to send, until the shutdown */
+    fn watch_downloads(self: Arc<Self>) {
+        let mut next_check = Instant::now() + DOWNLOAD_CHECK_INTERVAL;
+        while !self.stop.is_cancelled() {
+            thread::sleep(POLL_INTERVAL);
+            if Instant::now() < next_check {
+                continue;
+            }
+            next_check = Instant::now() + DOWNLOAD_CHECK_INTERVAL;
+            lock(&self.in_flight).retain(|hash, (id, asked)| {
+                let late = asked.elapsed() > BLOCK_TIMEOUT;
+                if late {
+                    debug!("Block {} asked from peer {} is late", hash, id);
+                }
+                !late
+            });
+            if let Err(e) = self.request_blocks() {
+                warn!("Can't ask for blocks: {}", e);
+            }
+        }
+    }
+
+    //* To submit a block to the chain, updating the mempool and announcing the blocks connected to the other peers */
+    /// A block from a peer without its parent makes the node ask that peer for the headers it misses;
+    /// other refused blocks are only logged.
+    fn accept_block(&self, block: Block, from: Option<usize>) -> Result<(), io::Error> {
+        lock(&self.mining).cancel();//the template is probably stale now
+        let hash = block.get_hash();
+        let result = {
+            let mut chain = lock(&self.chain);
+            match chain.submit_block(block) {
+                Ok(events) => {
+                    lock(&self.mempool).apply_chain_events(&events, &chain)?;
+                    Ok(events)
+                }
+                Err(e) => Err(e),
+            }
+        };
+        let events = match result {
+            Ok(events) => events,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                debug!("Block {} is an orphan, catching up: {}", hash, e);
+                return match from {
+                    Some(id) => self.ask_headers(id),
+                    None => Ok(()),
+                };
+            }
+            Err(e) => {
+                warn!("Refused block {}: {}", hash, e);
+                return Ok(());
+            }
+        };
+        //the chain logs every event already
+        let connected: Vec<Inventory> = events.into_iter().filter_map(|event| match event {
+            ChainEvent::Connected(block) => Some(Inventory::Block(block.get_hash())),
+            _ => None,
+        }).collect();
+        if !connected.is_empty() {
+            self.broadcast(&Message::Inv(connected), from);
+        }
+        Ok(())
+    }
+
+    //* To add a transaction to the mempool and announce it to the peers but its sender, returning its fee */
+    fn add_transaction(&self, tx: Transaction, from: Option<usize>) -> Result<Amount, io::Error> {
+        let tranc_id = tx.tranc_id.clone();
+        let fee = {
+            let chain = lock(&self.chain);
+            let mut mempool = lock(&self.mempool);
+            mempool.add(tx, &chain)?
+        };
+        info!("Accepted transaction {} paying a fee of {}", tranc_id, fee);
+        self.broadcast(&Message::Inv(vec![Inventory::Transaction(tranc_id)]), from);
+        Ok(fee)
+    }
+
+    //* To mine a block of the mempool, holding the chain only while preparing the template */
+    fn mine_block(&self, to: &str) -> Result<MiningStats, io::Error> {
+        let cancel = CancelToken::new();
+        *lock(&self.mining) = cancel.clone();//set first, so blocks arriving from now on stop this attempt
+        if self.stop.is_cancelled() {
+            cancel.cancel();
+        }
+        let template = {
+            let chain = lock(&self.chain);
+            let transactions = lock(&self.mempool).select(MAX_BLOCK_SIZE);
+            chain.block_template(to, transactions)?
+        };
+        match template.mine(&Miner::new(self.threads).with_cancel(cancel))? {
+            MinedBlock::Mined(block, stats) => {
+                info!("Mined block {} at height {} in {}", block.get_hash(), block.get_height(), stats);
+                self.accept_block(block, None)?;
+                Ok(stats)
+            }
+            MinedBlock::Stopped(reason, stats) => Err(io::Error::new(io::ErrorKind::Interrupted, format!("MINING_STOPPED: {} after {}", reason, stats))),
+        }
+    }
+
+    //* To mine blocks one after the other until the shutdown */
+    fn mine_continuously(self: Arc<Self>, to: &str) {
+        while !self.stop.is_cancelled() {
+            match self.mine_block(to) {
+                Ok(_) => (),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+                Err(e) => {
+                    warn!("Can't mine: {}", e);
+                    thread::sleep(RECONNECT_INTERVAL);
+                }
+            }
+        }
+    }
+
+    //* To build the version of this node */
+    fn version(&self) -> Result<Message, io::Error> {
+        let best_height = lock(&self.chain).get_best_height()?;
+        Ok(Message::Version { version: PROTOCOL_VERSION, genesis: self.genesis.clone(), best_height, nonce: self.nonce })
+    }
+
+    //* To queue a message for a peer */
+    fn send(&self, id: usize, message: &Message) {
+        if let Some(peer) = lock(&self.peers).get(&id) {
+            peer.queue(message);
+        }
+    }
+
+    //* To queue a message for every peer the handshake is done with, but one */
+    fn broadcast(&self, message: &Message, except: Option<usize>) {
+        for (id, peer) in lock(&self.peers).iter() {
+            if peer.is_ready() && Some(*id) != except {
+                peer.queue(message);
+            }
+        }
+    }
+
+    //* To get the number of peers the handshake is done with */
+    fn peer_count(&self) -> usize {
+        lock(&self.peers).values().filter(|peer| peer.is_ready()).count()
+    }
+
+    //* To get the address of a peer, for logs */
+    fn peer_addr(&self, id: usize) -> String {
+        lock(&self.peers).get(&id).map_or_else(|| String::from("?"), |peer| peer.addr.to_string())
+    }
+}
+
+//* To spread missing blocks over the peers having them, least busy first, each peer getting at most its share in flight */
+/// missing: (hash, height) of the blocks to ask for, lowest first
+/// peers: (id, best height, blocks in flight) of the peers to ask, the blocks in flight counting the new ones on return
+fn assign_downloads(missing: &[(String, usize)], peers: &mut [(usize, usize, usize)]) -> HashMap<usize, Vec<String>> {
+    let mut requests: HashMap<usize, Vec<String>> = HashMap::new();
+    for (hash, height) in missing {
+        let peer = peers.iter_mut()
+            .filter(|(_, best_height, in_flight)| best_height >= height && *in_flight < MAX_BLOCKS_IN_FLIGHT)
+            .min_by_key(|(id, _, in_flight)| (*in_flight, *id));
+        if let Some((id, _, in_flight)) = peer {
+            *in_flight += 1;
+            requests.entry(*id).or_default().push(hash.clone());
+        }
+    }
+    requests
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{coins, spend, test_keys, test_rewards, TempDir};
+
+    //* helper to get the hash and height of the tip of a node */
+    fn tip(node: &Node) -> (String, usize) {
+        node.handle().with_chain(|chain, _| (chain.tip_hash(), chain.get_best_height().unwrap()))
+    }
+
+    //* helper to start a node on a free port of the loopback interface */
+    fn start(chain: Blockchain, peers: Vec<String>) -> Node {
+        let mempool = Mempool::open(&chain).unwrap();
+        Node::start(chain, mempool, NodeConfig { listen: String::from("127.0.0.1:0"), peers, max_inbound: DEFAULT_MAX_INBOUND, mine_to: None, threads: 1 }).unwrap()
+    }
+
+    //* helper to wait for a condition the nodes reach in the background */
+    fn wait_for(what: &str, condition: impl Fn() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(30);
+        while !condition() {
+            assert!(Instant::now() < deadline, "timed out waiting for {}", what);
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    #[test]
+    fn test_nodes_converge_on_the_same_chain() {
+        let (secret, public, address) = test_keys(1);
+        let (_, _, receiver) = test_keys(2);
+        let (dir_a, dir_b) = (TempDir::new(), TempDir::new());
+        let mut chain_a = Blockchain::create_blockchain_with(address.clone(), dir_a.path(), test_rewards()).unwrap();
+        let mut chain_b = Blockchain::create_from_genesis(chain_a.get_block_by_height(0).unwrap().unwrap(), dir_b.path()).unwrap();
+        for _ in 0..12 {
+            chain_a.mine_block(&address, Vec::new()).unwrap();
+        }
+        chain_b.mine_block(&receiver, Vec::new()).unwrap();//a lighter branch of its own
+        let coinbase = chain_a.get_block_by_height(1).unwrap().unwrap().get_transaction()[0].clone();
+        let tx = spend(&coinbase, 0, &secret, &public, &receiver, 99);
+
+        //the node behind catches up once connected
+        let a = start(chain_a, Vec::new());
+        let b = start(chain_b, vec![a.local_addr().to_string()]);
+        wait_for("the handshake", || a.handle().peer_count() == 1 && b.handle().peer_count() == 1);
+        wait_for("the sync", || tip(&b) == tip(&a));
+        assert_eq!(tip(&b).1, 12);
+
+        //transactions and new blocks are relayed both ways
+        assert_eq!(b.handle().add_transaction(tx.clone()).unwrap(), coins(1));
+        wait_for("the transaction", || lock(&a.shared.mempool).get(&tx.tranc_id).is_some());
+        a.shared.mine_block(&address).unwrap();
+        wait_for("the new block", || tip(&b) == tip(&a));
+        assert_eq!(tip(&a).1, 13);
+        assert!(lock(&b.shared.mempool).is_empty());
+        let block = lock(&b.shared.chain).get_block_by_height(13).unwrap().unwrap();
+        assert_eq!(block.get_transaction()[1].tranc_id, tx.tranc_id);
+
+        b.shared.mine_block(&address).unwrap();
+        wait_for("the block of the other node", || tip(&a) == tip(&b));
+        b.shutdown().unwrap();
+        wait_for("the disconnection", || a.handle().peer_count() == 0);
+        a.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_downloads_are_spread_over_the_peers_having_the_blocks() {
+        let missing: Vec<(String, usize)> = (1..=40).map(|height| (format!("b{}", height), height)).collect();
+        //peer 2 only has the first 10 blocks, peer 3 is busy until peer 1 is as busy
+        let mut peers = vec![(1, 40, 0), (2, 10, 0), (3, 40, MAX_BLOCKS_IN_FLIGHT - 2)];
+        let requests = assign_downloads(&missing, &mut peers);
+        let expected = |heights: &[usize]| heights.iter().map(|height| format!("b{}", height)).collect::<Vec<String>>();
+        assert_eq!(requests[&2], expected(&[2, 4, 6, 8, 10]));
+        assert_eq!(requests[&3], expected(&[21, 23]));
+        assert_eq!(requests[&1].len(), MAX_BLOCKS_IN_FLIGHT);
+        assert_eq!(requests[&1][..6], expected(&[1, 3, 5, 7, 9, 11]));
+        assert_eq!(peers, vec![(1, 40, MAX_BLOCKS_IN_FLIGHT), (2, 10, 5), (3, 40, MAX_BLOCKS_IN_FLIGHT)]);
+        assert!(assign_downloads(&missing[10..], &mut peers).is_empty());//only full peers have them
+    }
+
+    #[test]
+    fn test_new_nodes_download_the_chain_from_several_peers() {
+        let (_, _, address) = test_keys(1);
+        let (dir_a, dir_b, dir_c) = (TempDir::new(), TempDir::new(), TempDir::new());
+        let mut chain_a = Blockchain::create_blockchain_with(address.clone(), dir_a.path(), test_rewards()).unwrap();
+        let genesis = chain_a.get_block_by_height(0).unwrap().unwrap();
+        let mut chain_b = Blockchain::create_from_genesis(genesis.clone(), dir_b.path()).unwrap();
+        let chain_c = Blockchain::create_from_genesis(genesis, dir_c.path()).unwrap();
+        for _ in 0..40 {
+            chain_a.mine_block(&address, Vec::new()).unwrap();
+            chain_b.submit_block(chain_a.iter().next().unwrap()).unwrap();
+        }
+        let a = start(chain_a, Vec::new());
+        let b = start(chain_b, Vec::new());
+        let c = start(chain_c, vec![a.local_addr().to_string(), b.local_addr().to_string()]);
+        wait_for("the download", || tip(&c) == tip(&a));
+        assert_eq!(tip(&c).1, 40);
+        wait_for("the last answers", || lock(&c.shared.in_flight).is_empty());//blocks asked twice while they arrived
+        assert!(lock(&c.shared.chain).missing_blocks(DOWNLOAD_WINDOW).unwrap().is_empty());
+        c.shutdown().unwrap();
+        a.shutdown().unwrap();
+        b.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_nodes_of_other_chains_are_dropped() {
+        let (_, _, address) = test_keys(1);
+        let (dir_a, dir_b) = (TempDir::new(), TempDir::new());
+        let a = start(Blockchain::create_blockchain_with(address.clone(), dir_a.path(), test_rewards()).unwrap(), Vec::new());
+        let b = start(Blockchain::create_blockchain_with(address, dir_b.path(), test_rewards()).unwrap(), Vec::new());
+        let (addr_a, addr_b) = (a.local_addr().to_string(), b.local_addr().to_string());
+
+        //another genesis block
+        Arc::clone(&b.shared).add_peer(TcpStream::connect(&addr_a).unwrap(), Some(addr_a)).unwrap();
+        wait_for("the other chain to be dropped", || lock(&a.shared.peers).is_empty() && lock(&b.shared.peers).is_empty());
+        //a connection of a node to itself
+        Arc::clone(&b.shared).add_peer(TcpStream::connect(&addr_b).unwrap(), Some(addr_b)).unwrap();
+        wait_for("the loop to be dropped", || lock(&b.shared.peers).is_empty());
+        assert_eq!(a.handle().peer_count() + b.handle().peer_count(), 0);
+        a.shutdown().unwrap();
+        b.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_inbound_peers_are_capped() {
+        let (_, _, address) = test_keys(1);
+        let (dir_a, dir_b, dir_c) = (TempDir::new(), TempDir::new(), TempDir::new());
+        let chain_a = Blockchain::create_blockchain_with(address, dir_a.path(), test_rewards()).unwrap();
+        let genesis = chain_a.get_block_by_height(0).unwrap().unwrap();
+        let (chain_b, chain_c) = (Blockchain::create_from_genesis(genesis.clone(), dir_b.path()).unwrap(), Blockchain::create_from_genesis(genesis, dir_c.path()).unwrap());
+        let mempool = Mempool::open(&chain_a).unwrap();
+        let a = Node::start(chain_a, mempool, NodeConfig { listen: String::from("127.0.0.1:0"), peers: Vec::new(), max_inbound: 1, mine_to: None, threads: 1 }).unwrap();
+        let b = start(chain_b, vec![a.local_addr().to_string()]);
+        wait_for("the first peer", || a.handle().peer_count() == 1 && b.handle().peer_count() == 1);
+        let c = start(chain_c, vec![a.local_addr().to_string()]);
+        thread::sleep(Duration::from_millis(500));//the time it takes the first one to connect, many times over
+        assert_eq!((a.handle().peer_count(), lock(&a.shared.peers).len(), c.handle().peer_count()), (1, 1, 0));
+        c.shutdown().unwrap();
+        b.shutdown().unwrap();
+        a.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_peers_not_keeping_up_are_dropped() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut remote = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, addr) = listener.accept().unwrap();
+        let (outbox, _queue) = mpsc::sync_channel::<Message>(OUTBOX_SIZE);//nobody writing
+        let peer = Peer { addr, stream, outbox, outbound: None, version: true, verack: true, best_height: 0 };
+        for _ in 0..OUTBOX_SIZE {
+            peer.queue(&Message::Verack);
+        }
+        remote.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+        assert_eq!(io::Read::read(&mut remote, &mut [0; 1]).unwrap_err().kind(), io::ErrorKind::WouldBlock);//still open
+        peer.queue(&Message::Verack);
+        assert_eq!(io::Read::read(&mut remote, &mut [0; 1]).unwrap(), 0);//closed
+    }
+}
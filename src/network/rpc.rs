@@ -0,0 +1,691 @@
+//? JSON-RPC server giving programs access to a running node
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use bitcoincash_addr::Address;
+use log::{debug, info, warn};
+use serde_json::{json, Value};
+use crate::miner::pow::CancelToken;
+use crate::network::node::NodeHandle;
+use crate::wallet::amount::Amount;
+use crate::wallet::selection::Strategy;
+use crate::wallet::transaction::{Fee, SpendOptions, Transaction};
+use crate::wallet::wallet::Wallets;
+
+//? address answering calls when none is given
+pub const DEFAULT_RPC_ADDR: &str = "127.0.0.1:8336";
+//? name of the cookie file in the data directory, whoever can read it can call the node
+const COOKIE_FILE: &str = ".cookie";
+//? user name of the cookie credentials
+const COOKIE_USER: &str = "__cookie__";
+//? bytes of the request line and headers of a request at most
+const MAX_HEADER_SIZE: usize = 8 * 1024;
+//? bytes of the body of a request at most
+const MAX_BODY_SIZE: usize = 1024 * 1024;
+//? time a client gets to send its request
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+//? number of clients served at once
+const MAX_CLIENTS: usize = 16;
+//? time a client turned away gets to finish sending its request, so closing the connection doesn't reset it
+const REJECT_TIMEOUT: Duration = Duration::from_millis(50);
+//? time between two checks for new connections or a shutdown
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+//? error codes of JSON-RPC 2.0
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const INTERNAL_ERROR: i64 = -32603;
+//? error codes of the node: no such block, transaction or wallet
+pub const NOT_FOUND: i64 = -5;
+//? error codes of the node: the transaction can't be built or is refused by the mempool
+pub const REJECTED: i64 = -26;
+
+//? Error answered to a call
+#[derive(Debug, Clone, PartialEq)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> RpcError {
+        RpcError { code, message: message.into() }
+    }
+}
+
+impl From<io::Error> for RpcError {
+    fn from(e: io::Error) -> Self {
+        let code = match e.kind() {
+            io::ErrorKind::NotFound => NOT_FOUND,
+            io::ErrorKind::InvalidInput | io::ErrorKind::InvalidData => REJECTED,
+            _ => INTERNAL_ERROR,
+        };
+        RpcError::new(code, e.to_string())
+    }
+}
+
+//? HTTP request of a client, only what the server looks at
+#[derive(Debug, PartialEq)]
+struct HttpRequest {
+    method: String,
+    authorization: Option<String>,//value of the Authorization header
+    body: Vec<u8>,
+}
+
+//? Why a request couldn't be read
+#[derive(Debug)]
+enum RequestError {
+    TooLarge(String),//over the size limits, answered with 413
+    Bad(String),//not a request the server understands, answered with 400
+    Io(io::Error),//the connection failed or ended early, nothing is answered
+}
+
+impl fmt::Display for RequestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RequestError::TooLarge(reason) => write!(f, "REQUEST_TOO_LARGE: {}", reason),
+            RequestError::Bad(reason) => write!(f, "BAD_REQUEST: {}", reason),
+            RequestError::Io(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<io::Error> for RequestError {
+    fn from(e: io::Error) -> Self {
+        RequestError::Io(e)
+    }
+}
+
+//? HTTP response to a client
+struct HttpResponse {
+    status: u16,
+    headers: Vec<(&'static str, String)>,//headers besides the content ones
+    body: String,
+}
+
+impl HttpResponse {
+    //* To answer with a JSON body */
+    fn json(body: &Value) -> HttpResponse {
+        HttpResponse { status: 200, headers: Vec::new(), body: body.to_string() }
+    }
+
+    //* To answer with an HTTP error, before any call is made */
+    fn error(status: u16, reason: String) -> HttpResponse {
+        HttpResponse { status, headers: Vec::new(), body: json!({ "error": reason }).to_string() }
+    }
+}
+
+//? State shared by the threads answering calls
+struct Handler {
+    node: NodeHandle,//calls wait for the locks of the node, never the other way around
+    authorization: String,//Authorization header expected from callers
+    wallets: Mutex<Wallets>,//wallets of the data directory, opened once for the life of the server
+    clients: AtomicUsize,//clients being served
+    stop: CancelToken,
+}
+
+//? Place of a client among the ones served at once, given back when dropped
+struct ClientSlot<'a>(&'a AtomicUsize);
+
+impl Drop for ClientSlot<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+//? Running RPC server, stopped by `shutdown`
+pub struct RpcServer {
+    handler: Arc<Handler>,
+    local_addr: SocketAddr,
+    cookie: PathBuf,
+    worker: JoinHandle<()>,
+}
+
+impl RpcServer {
+    //* To start answering calls to the node on an address, writing the cookie file callers authenticate with */
+    pub fn start(node: NodeHandle, bind: &str) -> Result<RpcServer, io::Error> {
+        let data_dir = node.with_chain(|chain, _| chain.data_dir().to_path_buf());
+        let wallets = Wallets::new(&data_dir)?;
+        let listener = TcpListener::bind(bind)?;
+        listener.set_nonblocking(true)?;//polled, to notice the shutdown
+        let local_addr = listener.local_addr()?;
+        let password: String = (0..32).map(|_| format!("{:02x}", rand::random::<u8>())).collect();
+        let credentials = format!("{}:{}", COOKIE_USER, password);
+        let cookie = data_dir.join(COOKIE_FILE);
+        write_cookie(&cookie, &credentials)?;
+        let handler = Arc::new(Handler {
+            node,
+            authorization: format!("Basic {}", base64(credentials.as_bytes())),
+            wallets: Mutex::new(wallets),
+            clients: AtomicUsize::new(0),
+            stop: CancelToken::new(),
+        });
+        info!("Answering RPC calls on {}, authenticated by {}", local_addr, cookie.display());
+
+        let accepting = Arc::clone(&handler);
+        let worker = thread::spawn(move || accepting.accept_clients(listener));
+        Ok(RpcServer { handler, local_addr, cookie, worker })
+    }
+
+    //* To get the address the server answers on */
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    //* To get the path of the cookie file holding the credentials of the callers */
+    pub fn cookie_file(&self) -> &Path {
+        &self.cookie
+    }
+
+    //* To stop accepting calls and remove the cookie file, calls being answered still finishing */
+    pub fn shutdown(self) -> Result<(), io::Error> {
+        self.handler.stop.cancel();
+        let _ = self.worker.join();
+        match fs::remove_file(&self.cookie) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+//* To write the cookie file readable by its owner only, replacing the one of an earlier run */
+fn write_cookie(path: &Path, credentials: &str) -> Result<(), io::Error> {
+    let _ = fs::remove_file(path);//the mode only applies to new files
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)?.write_all(credentials.as_bytes())
+}
+
+//* To encode bytes as standard base64 with padding, as HTTP basic auth wants them */
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [chunk[0], chunk.get(1).copied().unwrap_or(0), chunk.get(2).copied().unwrap_or(0)];
+        let bits = u32::from(bytes[0]) << 16 | u32::from(bytes[1]) << 8 | u32::from(bytes[2]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(bits >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+//* To compare secrets in a time not depending on where they differ */
+fn same_secret(given: &[u8], expected: &[u8]) -> bool {
+    given.len() == expected.len() && given.iter().zip(expected).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+//* To read the request line, headers and body of a request */
+fn read_request<R: BufRead>(reader: &mut R) -> Result<HttpRequest, RequestError> {
+    let bad_request = |reason: &str| RequestError::Bad(reason.to_string());
+    let mut head = reader.by_ref().take(MAX_HEADER_SIZE as u64);
+    let mut lines = Vec::new();
+    loop {
+        let mut line = String::new();
+        head.read_line(&mut line)?;
+        if !line.ends_with('\n') {
+            if head.limit() == 0 {
+                return Err(RequestError::TooLarge(format!("headers over {} bytes", MAX_HEADER_SIZE)));
+            }
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the request ends in its headers").into());
+        }
+        let line = line.trim_end().to_string();
+        if line.is_empty() {
+            break;
+        }
+        lines.push(line);
+    }
+
+    let mut lines = lines.into_iter();
+    let request_line = lines.next().ok_or_else(|| bad_request("no request line"))?;
+    let mut parts = request_line.split_whitespace();
+    let (method, _target, version) = (parts.next(), parts.next(), parts.next());
+    let method = match (method, version) {
+        (Some(method), Some(version)) if version.starts_with("HTTP/1.") => method.to_string(),
+        _ => return Err(bad_request(&format!("not an HTTP/1 request line '{}'", request_line))),
+    };
+    let mut authorization = None;
+    let mut length = 0;
+    for line in lines {
+        let (name, value) = line.split_once(':').ok_or_else(|| bad_request(&format!("not a header '{}'", line)))?;
+        let value = value.trim();
+        match name.trim().to_ascii_lowercase().as_str() {
+            "authorization" => authorization = Some(value.to_string()),
+            "content-length" => length = value.parse::<usize>().map_err(|_| bad_request(&format!("bad Content-Length '{}'", value)))?,
+            "transfer-encoding" => return Err(bad_request("only bodies with a Content-Length are read")),
+            _ => (),
+        }
+    }
+    if length > MAX_BODY_SIZE {
+        return Err(RequestError::TooLarge(format!("{} bytes of body, at most {}", length, MAX_BODY_SIZE)));
+    }
+    let mut body = vec![0u8; length];
+    reader.read_exact(&mut body)?;
+    Ok(HttpRequest { method, authorization, body })
+}
+
+//* To answer a client over the limit with 503 from the thread accepting clients, without reading its request */
+fn turn_away(stream: TcpStream) -> Result<(), io::Error> {
+    stream.set_nonblocking(false)?;
+    stream.set_write_timeout(Some(REJECT_TIMEOUT))?;
+    write_response(&mut &stream, &HttpResponse::error(503, format!("TOO_MANY_CLIENTS: {} calls being answered, try again", MAX_CLIENTS)))?;
+    stream.shutdown(Shutdown::Write)?;
+    //the request read meanwhile, closing with unread data would reset the connection before the client reads the answer
+    stream.set_read_timeout(Some(REJECT_TIMEOUT))?;
+    let _ = io::copy(&mut (&stream).take(MAX_HEADER_SIZE as u64), &mut io::sink());
+    Ok(())
+}
+
+//* To write a response, the connection being closed after it */
+fn write_response<W: Write>(writer: &mut W, response: &HttpResponse) -> Result<(), io::Error> {
+    let reason = match response.status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        503 => "Service Unavailable",
+        _ => "Error",
+    };
+    let mut head = format!("HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n", response.status, reason, response.body.len());
+    for (name, value) in &response.headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    writer.write_all(head.as_bytes())?;
+    writer.write_all(response.body.as_bytes())?;
+    writer.flush()
+}
+
+//? Parameters of a call, given by position or by name
+struct Params<'a>(&'a Value);
+
+impl Params<'_> {
+    //* To get a parameter, null ones counting as not given */
+    fn get(&self, index: usize, name: &str) -> Option<&Value> {
+        let value = match self.0 {
+            Value::Array(values) => values.get(index),
+            Value::Object(values) => values.get(name),
+            _ => None,
+        };
+        value.filter(|value| !value.is_null())
+    }
+
+    //* To get an optional string parameter */
+    fn string(&self, index: usize, name: &str) -> Result<Option<&str>, RpcError> {
+        match self.get(index, name) {
+            None => Ok(None),
+            Some(Value::String(value)) => Ok(Some(value)),
+            Some(_) => Err(RpcError::new(INVALID_PARAMS, format!("INVALID_PARAMS: {} has to be a string", name))),
+        }
+    }
+
+    //* To get a string parameter the call needs */
+    fn required(&self, index: usize, name: &str) -> Result<&str, RpcError> {
+        self.string(index, name)?.ok_or_else(|| RpcError::new(INVALID_PARAMS, format!("INVALID_PARAMS: missing {}", name)))
+    }
+
+    //* To get an optional whole number parameter */
+    fn number(&self, index: usize, name: &str) -> Result<Option<u64>, RpcError> {
+        match self.get(index, name) {
+            None => Ok(None),
+            Some(value) => value.as_u64().map(Some).ok_or_else(|| RpcError::new(INVALID_PARAMS, format!("INVALID_PARAMS: {} has to be a whole number", name))),
+        }
+    }
+
+    //* To get an optional amount parameter, given as decimal coins */
+    fn amount(&self, index: usize, name: &str) -> Result<Option<Amount>, RpcError> {
+        let text = match self.get(index, name) {
+            None => return Ok(None),
+            Some(Value::String(text)) => text.clone(),
+            Some(Value::Number(number)) => number.to_string(),
+            Some(_) => return Err(RpcError::new(INVALID_PARAMS, format!("INVALID_PARAMS: {} has to be an amount like \"1.25\"", name))),
+        };
+        Amount::from_str(&text).map(Some).map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))
+    }
+}
+
+//* To convert a value of the chain to JSON */
+fn to_json<T: serde::Serialize>(value: &T) -> Result<Value, RpcError> {
+    serde_json::to_value(value).map_err(|e| RpcError::new(INTERNAL_ERROR, e.to_string()))
+}
+
+impl Handler {
+    //* To accept clients until the shutdown, serving each from its own thread */
+    fn accept_clients(self: Arc<Self>, listener: TcpListener) {
+        while !self.stop.is_cancelled() {
+            match listener.accept() {
+                Ok((stream, addr)) => {
+                    if self.clients.fetch_add(1, Ordering::SeqCst) >= MAX_CLIENTS {
+                        self.clients.fetch_sub(1, Ordering::SeqCst);
+                        debug!("Turning RPC client {} away, {} clients being served", addr, MAX_CLIENTS);
+                        if let Err(e) = turn_away(stream) {
+                            debug!("Can't answer RPC client {}: {}", addr, e);
+                        }
+                        continue;
+                    }
+                    let serving = Arc::clone(&self);
+                    thread::spawn(move || {
+                        let _slot = ClientSlot(&serving.clients);
+                        if let Err(e) = serving.serve(stream) {
+                            debug!("Can't answer RPC client {}: {}", addr, e);
+                        }
+                    });
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(POLL_INTERVAL),
+                Err(e) => {
+                    warn!("Can't accept RPC clients: {}", e);
+                    thread::sleep(POLL_INTERVAL);
+                }
+            }
+        }
+    }
+
+    //* To read the request of a client and answer it */
+    fn serve(&self, stream: TcpStream) -> Result<(), io::Error> {
+        stream.set_nonblocking(false)?;
+        stream.set_read_timeout(Some(READ_TIMEOUT))?;
+        let response = match read_request(&mut BufReader::new(&stream)) {
+            Ok(request) => self.respond(request),
+            Err(e @ RequestError::TooLarge(_)) => HttpResponse::error(413, e.to_string()),
+            Err(e @ RequestError::Bad(_)) => HttpResponse::error(400, e.to_string()),
+            Err(RequestError::Io(e)) => return Err(e),
+        };
+        write_response(&mut &stream, &response)
+    }
+
+    //* To answer an authenticated call or batch of calls */
+    fn respond(&self, request: HttpRequest) -> HttpResponse {
+        if request.method != "POST" {
+            let mut response = HttpResponse::error(405, format!("METHOD_NOT_ALLOWED: {}, calls are POSTed", request.method));
+            response.headers.push(("Allow", String::from("POST")));
+            return response;
+        }
+        let authorized = request.authorization.is_some_and(|given| same_secret(given.as_bytes(), self.authorization.as_bytes()));
+        if !authorized {
+            let mut response = HttpResponse::error(401, String::from("UNAUTHORIZED: use the credentials of the cookie file"));
+            response.headers.push(("WWW-Authenticate", String::from("Basic realm=\"jsonrpc\"")));
+            return response;
+        }
+        match serde_json::from_slice::<Value>(&request.body) {
+            Ok(Value::Array(calls)) if !calls.is_empty() => HttpResponse::json(&Value::Array(calls.into_iter().map(|call| self.answer(call)).collect())),
+            Ok(Value::Array(_)) => HttpResponse::json(&reply(Value::Null, Err(RpcError::new(INVALID_REQUEST, "INVALID_REQUEST: empty batch")))),
+            Ok(call) => HttpResponse::json(&self.answer(call)),
+            Err(e) => HttpResponse::json(&reply(Value::Null, Err(RpcError::new(PARSE_ERROR, format!("PARSE_ERROR: {}", e))))),
+        }
+    }
+
+    //* To answer a single call */
+    fn answer(&self, call: Value) -> Value {
+        let id = call.get("id").cloned().unwrap_or(Value::Null);
+        let result = match (call.get("method").and_then(Value::as_str), call.get("params")) {
+            (Some(method), params) => {
+                debug!("RPC call {}", method);
+                self.call(method, Params(params.unwrap_or(&Value::Null)))
+            }
+            (None, _) => Err(RpcError::new(INVALID_REQUEST, "INVALID_REQUEST: a call is an object with a method")),
+        };
+        reply(id, result)
+    }
+
+    //* To run a method of the node */
+    fn call(&self, method: &str, params: Params) -> Result<Value, RpcError> {
+        match method {
+            "getbalance" => {
+                let address = params.required(0, "address")?;
+                let pub_key_hash = Address::decode(address)
+                    .map_err(|_| RpcError::new(INVALID_PARAMS, format!("INVALID_ADDRESS: {}", address)))?
+                    .body;
+                let outputs = self.node.with_chain(|chain, _| chain.find_utxo(pub_key_hash));
+                let balance = Amount::checked_sum(outputs.iter().map(|out| out.value))
+                    .ok_or_else(|| RpcError::new(INTERNAL_ERROR, "VALUE_OVERFLOW: the balance doesn't fit an amount"))?;
+                Ok(json!({ "address": address, "balance": balance.to_string(), "units": balance.units() }))
+            }
+            "getblock" => {
+                let hash = params.required(0, "hash")?;
+                let block = self.node.with_chain(|chain, _| chain.get_block_by_hash(hash))?;
+                to_json(&block.ok_or_else(|| RpcError::new(NOT_FOUND, format!("BLOCK_NOT_FOUND: {}", hash)))?)
+            }
+            "getblockbyheight" => {
+                let height = params.number(0, "height")?.ok_or_else(|| RpcError::new(INVALID_PARAMS, "INVALID_PARAMS: missing height"))?;
+                let block = self.node.with_chain(|chain, _| chain.get_block_by_height(height as usize))?;
+                to_json(&block.ok_or_else(|| RpcError::new(NOT_FOUND, format!("BLOCK_NOT_FOUND: no block at height {}", height)))?)
+            }
+            "gettransaction" => {
+                let txid = params.required(0, "txid")?;
+                self.node.with_chain(|chain, mempool| {
+                    if let Some(entry) = mempool.get(txid) {
+                        return Ok(json!({ "transaction": to_json(&entry.transaction)?, "pending": true, "fee": entry.fee.to_string() }));
+                    }
+                    let (transaction, height) = chain.find_transaction_with_height(txid)?
+                        .ok_or_else(|| RpcError::new(NOT_FOUND, format!("TRANSACTION_NOT_FOUND: {}", txid)))?;
+                    let confirmations = chain.get_best_height()? + 1 - height;
+                    Ok(json!({ "transaction": to_json(&transaction)?, "pending": false, "height": height, "confirmations": confirmations }))
+                })
+            }
+            "sendtransaction" => {
+                let from = params.required(0, "from")?;
+                let to = params.required(1, "to")?;
+                let amount = params.amount(2, "amount")?.ok_or_else(|| RpcError::new(INVALID_PARAMS, "INVALID_PARAMS: missing amount"))?;
+                let fee = match (params.amount(3, "fee")?, params.number(4, "fee_rate")?) {
+                    (Some(_), Some(_)) => return Err(RpcError::new(INVALID_PARAMS, "INVALID_PARAMS: give either a fee or a fee_rate")),
+                    (_, Some(rate)) => Fee::PerByte(Amount::from_units(rate)),
+                    (fee, None) => Fee::Absolute(fee.unwrap_or(Amount::ZERO)),
+                };
+                let strategy = match params.string(5, "coin_selection")? {
+                    Some(name) => name.parse::<Strategy>().map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))?,
+                    None => Strategy::default(),
+                };
+                //the keys are copied first, so the wallets are never locked along with the chain
+                let wallets = self.wallets.lock().unwrap_or_else(PoisonError::into_inner).clone();
+                let selector = strategy.selector();
+                let tx = self.node.with_chain(|chain, mempool| Transaction::new_utxo(&wallets, from, to, amount, chain, &SpendOptions { fee, selector: selector.as_ref(), pending: mempool, unconfirmed: false }))
+                    .map_err(|e| RpcError::new(REJECTED, e.to_string()))?;
+                let txid = tx.tranc_id.clone();
+                let fee = self.node.add_transaction(tx)?;
+                Ok(json!({ "txid": txid, "fee": fee.to_string() }))
+            }
+            "createwallet" => {
+                let mut wallets = self.wallets.lock().unwrap_or_else(PoisonError::into_inner);
+                let address = wallets.create_wallet();
+                wallets.save_all()?;
+                Ok(json!(address))
+            }
+            "listaddresses" => {
+                let mut addresses = self.wallets.lock().unwrap_or_else(PoisonError::into_inner).get_all_addresses();
+                addresses.sort();
+                Ok(json!(addresses))
+            }
+            "getchaininfo" => {
+                let mut info = self.node.with_chain(|chain, mempool| -> Result<Value, RpcError> {
+                    Ok(json!({
+                        "height": chain.get_best_height()?,
+                        "tip": chain.tip_hash(),
+                        "difficulty": chain.next_difficulty()?,
+                        "pending": mempool.len(),
+                    }))
+                })?;
+                info["peers"] = json!(self.node.peer_count());
+                Ok(info)
+            }
+            _ => Err(RpcError::new(METHOD_NOT_FOUND, format!("METHOD_NOT_FOUND: {}", method))),
+        }
+    }
+}
+
+//* To build the JSON-RPC response to a call */
+fn reply(id: Value, result: Result<Value, RpcError>) -> Value {
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "result": result, "id": id }),
+        Err(e) => json!({ "jsonrpc": "2.0", "error": { "code": e.code, "message": e.message }, "id": id }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::miner::chain::Blockchain;
+    use crate::miner::mempool::{Mempool, MAX_BLOCK_SIZE};
+    use crate::network::node::{Node, NodeConfig, DEFAULT_MAX_INBOUND};
+    use crate::test_utils::{test_keys, test_rewards, TempDir};
+
+    //* helper to start a node on a chain and a server answering calls for it */
+    fn serve(chain: Blockchain) -> (Node, RpcServer) {
+        let mempool = Mempool::open(&chain).unwrap();
+        let node = Node::start(chain, mempool, NodeConfig { listen: String::from("127.0.0.1:0"), peers: Vec::new(), max_inbound: DEFAULT_MAX_INBOUND, mine_to: None, threads: 1 }).unwrap();
+        let server = RpcServer::start(node.handle(), "127.0.0.1:0").unwrap();
+        (node, server)
+    }
+
+    //* helper to send a raw request and get the status and body of the response */
+    fn send(addr: SocketAddr, request: &str) -> (u16, String) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        (head.split_whitespace().nth(1).unwrap().parse().unwrap(), body.to_string())
+    }
+
+    //* helper to make a call with the credentials of the cookie file */
+    fn call(server: &RpcServer, method: &str, params: Value) -> Value {
+        let credentials = fs::read_to_string(server.cookie_file()).unwrap();
+        let body = json!({ "jsonrpc": "2.0", "method": method, "params": params, "id": 1 }).to_string();
+        let (status, body) = send(server.local_addr(), &format!(
+            "POST / HTTP/1.1\r\nAuthorization: Basic {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            base64(credentials.as_bytes()), body.len(), body,
+        ));
+        assert_eq!(status, 200);
+        serde_json::from_str(&body).unwrap()
+    }
+
+    #[test]
+    fn test_base64_and_requests() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foobar"), "Zm9vYmFy");
+        assert_eq!(base64(b"Aladdin:open sesame"), "QWxhZGRpbjpvcGVuIHNlc2FtZQ==");
+        assert!(same_secret(b"secret", b"secret"));
+        assert!(!same_secret(b"secret", b"secreT") && !same_secret(b"secret", b"secrets"));
+
+        let mut reader = io::Cursor::new("POST / HTTP/1.1\r\nauthorization: Basic Zm9v\r\nContent-Length: 4\r\n\r\n{}{}trailing");
+        assert_eq!(read_request(&mut reader).unwrap(), HttpRequest {
+            method: String::from("POST"),
+            authorization: Some(String::from("Basic Zm9v")),
+            body: b"{}{}".to_vec(),
+        });
+        let oversized = format!("POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n", MAX_BODY_SIZE + 1);
+        assert!(matches!(read_request(&mut io::Cursor::new(oversized)), Err(RequestError::TooLarge(_))));
+        let long_headers = format!("POST / HTTP/1.1\r\nX-Padding: {}\r\n\r\n", "a".repeat(MAX_HEADER_SIZE));
+        assert!(matches!(read_request(&mut io::Cursor::new(long_headers)), Err(RequestError::TooLarge(_))));
+        assert!(matches!(read_request(&mut io::Cursor::new("hello\r\n\r\n")), Err(RequestError::Bad(_))));
+        assert!(matches!(read_request(&mut io::Cursor::new("POST / HTTP/1.1\r\n")), Err(RequestError::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof));
+    }
+
+    #[test]
+    fn test_calls_over_http() {
+        let (_, _, address) = test_keys(1);
+        let dir = TempDir::new();
+        let mut chain = Blockchain::create_blockchain_with(address, dir.path(), test_rewards()).unwrap();
+        //the wallet sending coins is funded by blocks mined to it before the node starts
+        let mut wallets = Wallets::new(dir.path()).unwrap();
+        let sender = wallets.create_wallet();
+        wallets.save_all().unwrap();
+        drop(wallets);
+        chain.mine_block(&sender, Vec::new()).unwrap();
+        chain.mine_block(&sender, Vec::new()).unwrap();
+        let (node, server) = serve(chain);
+        let cookie = server.cookie_file().to_path_buf();
+        assert!(fs::read_to_string(&cookie).unwrap().starts_with("__cookie__:"));
+
+        //calls without the cookie are refused
+        let body = r#"{"jsonrpc":"2.0","method":"getchaininfo","id":1}"#;
+        let (status, _) = send(server.local_addr(), &format!("POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n{}", body.len(), body));
+        assert_eq!(status, 401);
+        let (status, _) = send(server.local_addr(), &format!("POST / HTTP/1.1\r\nAuthorization: Basic {}\r\nContent-Length: {}\r\n\r\n{}", base64(b"__cookie__:guess"), body.len(), body));
+        assert_eq!(status, 401);
+        assert_eq!(send(server.local_addr(), "GET / HTTP/1.1\r\n\r\n").0, 405);
+
+        //wallets are created next to the ones of the data directory
+        let receiver = call(&server, "createwallet", json!([]))["result"].as_str().unwrap().to_string();
+        let mut expected = vec![sender.clone(), receiver.clone()];
+        expected.sort();
+        assert_eq!(call(&server, "listaddresses", json!([]))["result"], json!(expected));
+        let info = call(&server, "getchaininfo", json!([]))["result"].clone();
+        assert_eq!((info["height"].clone(), info["pending"].clone(), info["peers"].clone()), (json!(2), json!(0), json!(0)));
+        assert_eq!(call(&server, "getbalance", json!([sender]))["result"]["balance"], "200");
+        let block = call(&server, "getblockbyheight", json!({ "height": 2 }))["result"].clone();
+        assert_eq!(block["hash"], info["tip"]);
+        assert_eq!(call(&server, "getblock", json!([info["tip"]]))["result"], block);
+
+        //a transaction sent is pending until mined
+        let sent = call(&server, "sendtransaction", json!({ "from": sender, "to": receiver, "amount": "12.5", "fee": "0.5" }))["result"].clone();
+        assert_eq!(sent["fee"], "0.5");
+        let txid = sent["txid"].as_str().unwrap().to_string();
+        let pending = call(&server, "gettransaction", json!([txid]))["result"].clone();
+        assert_eq!((pending["pending"].clone(), pending["fee"].clone()), (json!(true), json!("0.5")));
+
+        //the pending transaction is saved and mined after a restart
+        server.shutdown().unwrap();
+        node.shutdown().unwrap();
+        let mut chain = Blockchain::new(dir.path()).unwrap();
+        let mut mempool = Mempool::open(&chain).unwrap();
+        assert_eq!(mempool.len(), 1);
+        mempool.mine_block(&mut chain, &sender, MAX_BLOCK_SIZE).unwrap();
+        drop(mempool);
+        let (node, server) = serve(chain);
+        let confirmed = call(&server, "gettransaction", json!([txid]))["result"].clone();
+        assert_eq!((confirmed["pending"].clone(), confirmed["height"].clone(), confirmed["confirmations"].clone()), (json!(false), json!(3), json!(1)));
+        assert_eq!(call(&server, "getbalance", json!([receiver]))["result"]["units"], json!(1_250_000_000u64));
+
+        //errors are answered with their codes, batches call by call
+        assert_eq!(call(&server, "getblock", json!(["missing"]))["error"]["code"], json!(NOT_FOUND));
+        assert_eq!(call(&server, "getbalance", json!([]))["error"]["code"], json!(INVALID_PARAMS));
+        assert_eq!(call(&server, "sendtransaction", json!([sender, receiver, "100000"]))["error"]["code"], json!(REJECTED));
+        assert_eq!(call(&server, "stop", json!([]))["error"]["code"], json!(METHOD_NOT_FOUND));
+        let credentials = fs::read_to_string(&cookie).unwrap();
+        let batch = r#"[{"jsonrpc":"2.0","method":"getchaininfo","id":"a"},{"jsonrpc":"2.0","id":"b"}] "#;
+        let (_, body) = send(server.local_addr(), &format!("POST / HTTP/1.1\r\nAuthorization: Basic {}\r\nContent-Length: {}\r\n\r\n{}", base64(credentials.as_bytes()), batch.len(), batch));
+        let answers: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!((answers[0]["id"].clone(), answers[0]["result"]["height"].clone()), (json!("a"), json!(3)));
+        assert_eq!((answers[1]["id"].clone(), answers[1]["error"]["code"].clone()), (json!("b"), json!(INVALID_REQUEST)));
+        let (_, body) = send(server.local_addr(), &format!("POST / HTTP/1.1\r\nAuthorization: Basic {}\r\nContent-Length: 5\r\n\r\n{{oops", base64(credentials.as_bytes())));
+        assert_eq!(serde_json::from_str::<Value>(&body).unwrap()["error"]["code"], json!(PARSE_ERROR));
+
+        //clients over the limit are turned away until the others are done
+        let idle: Vec<TcpStream> = (0..MAX_CLIENTS).map(|_| TcpStream::connect(server.local_addr()).unwrap()).collect();
+        let (status, body) = send(server.local_addr(), "GET / HTTP/1.1\r\n\r\n");
+        assert_eq!(status, 503);
+        assert!(body.contains("TOO_MANY_CLIENTS"));
+        drop(idle);
+        let deadline = std::time::Instant::now() + Duration::from_secs(10);
+        while send(server.local_addr(), "GET / HTTP/1.1\r\n\r\n").0 != 405 {
+            assert!(std::time::Instant::now() < deadline, "the slots of the closed clients weren't given back");
+            thread::sleep(Duration::from_millis(20));
+        }
+
+        server.shutdown().unwrap();
+        assert!(!cookie.exists());
+        node.shutdown().unwrap();
+    }
+}
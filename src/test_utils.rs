@@ -0,0 +1,124 @@
+//? Helpers shared by the unit tests
+use std::{env, fs, path::{Path, PathBuf}, process};
+use std::sync::atomic::{AtomicUsize, Ordering}; //for unique directory names
+use bitcoincash_addr::{Address, HashType, Scheme}; //for building addresses
+use crypto::ed25519; //for deterministic key pairs
+use crate::miner::chain::Blockchain;
+use crate::miner::reward::{RewardSchedule, HALVING_INTERVAL, INITIAL_SUBSIDY};
+use crate::wallet::amount::Amount;
+use crate::wallet::transaction::Transaction;
+use crate::wallet::tx::{TrancInput, TrancOutput};
+use crate::wallet::wallet::{hash_pub_key, Wallets};
+
+//? counter keeping the directories of parallel tests apart
+static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+//? Temporary data directory, removed again when dropped
+pub struct TempDir{
+    path: PathBuf, //location of the directory
+}
+
+impl TempDir{
+    //* creates a fresh, empty directory under the system temp dir */
+    pub fn new() -> TempDir{
+        let id = NEXT_DIR.fetch_add(1, Ordering::SeqCst);
+        let path = env::temp_dir().join(format!("bchain-test-{}-{}", process::id(), id));
+        let _ = fs::remove_dir_all(&path); //leftovers of an earlier run with the same pid
+        fs::create_dir_all(&path).unwrap();
+        TempDir{ path }
+    }
+
+    //* To get the path of the directory */
+    pub fn path(&self) -> &Path{
+        &self.path
+    }
+}
+
+impl Drop for TempDir{
+    fn drop(&mut self){
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
+
+//* helper to get a deterministic key pair (secret, public) and its address */
+pub fn test_keys(seed: u8) -> (Vec<u8>, Vec<u8>, String){
+    let (secret, public) = ed25519::keypair(&[seed; 32]);
+    let mut pub_key_hash = public.to_vec();
+    hash_pub_key(&mut pub_key_hash);
+    let address = Address{
+        body: pub_key_hash,
+        scheme: Scheme::Base58,
+        hash_type: HashType::Script,
+        ..Default::default()
+    };
+    (secret.to_vec(), public.to_vec(), address.encode().unwrap())
+}
+
+//* helper to get the reward rules of test chains, the default ones but coinbases spendable from the next block */
+pub fn test_rewards() -> RewardSchedule{
+    RewardSchedule::new(INITIAL_SUBSIDY, HALVING_INTERVAL, 1).unwrap()
+}
+
+//* helper to get an amount of whole coins */
+pub fn coins(coins: u64) -> Amount{
+    Amount::from_coins(coins)
+}
+
+//* helper to get an amount of base units */
+pub fn units(units: u64) -> Amount{
+    Amount::from_units(units)
+}
+
+//* helper to create a chain whose first blocks pay 100 coins each to test_keys(1), returning their coinbases */
+pub fn funded_chain(dir: &TempDir, blocks: usize) -> (Blockchain, Vec<Transaction>){
+    let (_, _, address) = test_keys(1);
+    let mut chain = Blockchain::create_blockchain_with(address.clone(), dir.path(), test_rewards()).unwrap();
+    for _ in 1..blocks{
+        chain.mine_block(&address, Vec::new()).unwrap();
+    }
+    let coinbases = chain.iter().map(|block| block.get_transaction()[0].clone()).collect();
+    (chain, coinbases)
+}
+
+//* helper to create two wallets and a chain paying its genesis subsidy to the first */
+pub fn funded_wallets(dir: &TempDir, subsidy: Amount) -> (Wallets, String, String, Blockchain){
+    let mut wallets = Wallets::new(dir.path()).unwrap();
+    let (from, to) = (wallets.create_wallet(), wallets.create_wallet());
+    wallets.save_all().unwrap();
+    let chain = Blockchain::create_blockchain_with(from.clone(), dir.path(), RewardSchedule::new(subsidy, HALVING_INTERVAL, 1).unwrap()).unwrap();
+    (wallets, from, to, chain)
+}
+
+//* helper to create the coinbase of a block at a height, paid to a miner outside the tests */
+pub fn reward(height: usize) -> Transaction{
+    let (_, _, miner) = test_keys(9);
+    Transaction::new_reward(miner, format!("reward at {}", height), INITIAL_SUBSIDY).unwrap()
+}
+
+//* helper to spend an output of a previous transaction into several outputs */
+pub fn spend_to(prev: &Transaction, vout: i32, secret: &[u8], public: &[u8], outputs: Vec<TrancOutput>) -> Transaction{
+    let mut tx = Transaction{
+        tranc_id: String::new(),
+        vin: vec![TrancInput{ from: prev.tranc_id.clone(), vout, signature: Vec::new(), pub_key: public.to_vec() }],
+        vout: outputs,
+    };
+    tx.sign(secret, &[prev.vout[vout as usize].clone()]).unwrap();
+    tx.tranc_id = tx.hash().unwrap();
+    tx
+}
+
+//* helper to spend an output of a previous transaction, paying whole coins */
+pub fn spend(prev: &Transaction, vout: i32, secret: &[u8], public: &[u8], to: &str, value: u64) -> Transaction{
+    spend_to(prev, vout, secret, public, vec![TrancOutput::new(coins(value), to.to_string()).unwrap()])
+}
+
+//* helper to spend the first output of a transaction of test_keys(1) to test_keys(to), paying the rest as a fee */
+pub fn pay(prev: &Transaction, to: u8, value: u64) -> Transaction{
+    let (secret, public, _) = test_keys(1);
+    spend(prev, 0, &secret, &public, &test_keys(to).2, value)
+}
+
+//* helper to get the fee a transaction leaves out of the value of the outputs it spends */
+pub fn paid_fee(tx: &Transaction, inputs: Amount) -> Amount{
+    inputs.checked_sub(Amount::checked_sum(tx.vout.iter().map(|out| out.value)).unwrap()).unwrap()
+}
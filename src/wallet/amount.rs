@@ -0,0 +1,139 @@
+//? Amounts of coins
+/// Amounts are counted in whole base units, `COIN` of them making a coin, so they are never negative and never
+/// rounded. They are displayed and parsed as decimal coins like `1.25`, and every sum is checked for overflow.
+use serde::{Deserialize, Serialize};
+use std::{fmt, io, str::FromStr};
+
+//? number of decimal places of a coin
+pub const DECIMALS: usize = 8;
+//? number of base units in a coin
+pub const COIN: u64 = 100_000_000;
+
+//? An amount of base units
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct Amount(u64);
+
+impl Amount {
+    pub const ZERO: Amount = Amount(0);
+    pub const MAX: Amount = Amount(u64::MAX);
+
+    //* To get an amount of base units */
+    pub const fn from_units(units: u64) -> Amount {
+        Amount(units)
+    }
+
+    //* To get an amount of whole coins, panicking when it doesn't fit */
+    pub const fn from_coins(coins: u64) -> Amount {
+        match coins.checked_mul(COIN) {
+            Some(units) => Amount(units),
+            None => panic!("AMOUNT_OVERFLOW: too many coins"),
+        }
+    }
+
+    //* To get the number of base units */
+    pub const fn units(&self) -> u64 {
+        self.0
+    }
+
+    pub const fn is_zero(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn checked_add(self, other: Amount) -> Option<Amount> {
+        self.0.checked_add(other.0).map(Amount)
+    }
+
+    pub fn checked_sub(self, other: Amount) -> Option<Amount> {
+        self.0.checked_sub(other.0).map(Amount)
+    }
+
+    pub fn checked_mul(self, factor: u64) -> Option<Amount> {
+        self.0.checked_mul(factor).map(Amount)
+    }
+
+    pub fn saturating_add(self, other: Amount) -> Amount {
+        Amount(self.0.saturating_add(other.0))
+    }
+
+    pub fn saturating_sub(self, other: Amount) -> Amount {
+        Amount(self.0.saturating_sub(other.0))
+    }
+
+    pub fn saturating_mul(self, factor: u64) -> Amount {
+        Amount(self.0.saturating_mul(factor))
+    }
+
+    //* To halve an amount a number of times, down to zero */
+    pub fn halved(self, times: usize) -> Amount {
+        u32::try_from(times).ok().and_then(|times| self.0.checked_shr(times)).map_or(Amount::ZERO, Amount)
+    }
+
+    //* To add up amounts, None when the sum overflows */
+    pub fn checked_sum<I: IntoIterator<Item = Amount>>(amounts: I) -> Option<Amount> {
+        amounts.into_iter().try_fold(Amount::ZERO, Amount::checked_add)
+    }
+}
+
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (coins, units) = (self.0 / COIN, self.0 % COIN);
+        if units == 0 {
+            return write!(f, "{}", coins);
+        }
+        let decimals = format!("{:0width$}", units, width = DECIMALS);
+        write!(f, "{}.{}", coins, decimals.trim_end_matches('0'))
+    }
+}
+
+impl FromStr for Amount {
+    type Err = io::Error;
+
+    //* To parse decimal coins, refusing signs, more than `DECIMALS` decimals and amounts that don't fit */
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason: &str| io::Error::new(io::ErrorKind::InvalidInput, format!("INVALID_AMOUNT '{}': {}", text, reason));
+        let (coins, decimals) = text.split_once('.').unwrap_or((text, ""));
+        let is_digits = |part: &str| part.bytes().all(|b| b.is_ascii_digit());
+        if coins.is_empty() || !is_digits(coins) || !is_digits(decimals) || (text.contains('.') && decimals.is_empty()) {
+            return Err(invalid("expected a number of coins like 1.25"));
+        }
+        if decimals.len() > DECIMALS {
+            return Err(invalid(&format!("at most {} decimals", DECIMALS)));
+        }
+        let coins: u64 = coins.parse().map_err(|_| invalid("too large"))?;
+        let units: u64 = format!("{:0<width$}", decimals, width = DECIMALS).parse().map_err(|_| invalid("bad decimals"))?;
+        coins.checked_mul(COIN)
+            .and_then(|base| base.checked_add(units))
+            .map(Amount)
+            .ok_or_else(|| invalid("too large"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display_and_parse() {
+        for (text, units) in [("0", 0), ("1", COIN), ("1.25", 125_000_000), ("0.00000001", 1), ("21000000", 21_000_000 * COIN)] {
+            let amount: Amount = text.parse().unwrap();
+            assert_eq!(amount, Amount::from_units(units));
+            assert_eq!(amount.to_string(), text);
+        }
+        assert_eq!("1.50".parse::<Amount>().unwrap().to_string(), "1.5");
+        for text in ["", "-1", "+1", "1.", ".5", "1.000000001", "1,5", "abc", "184467440738"] {
+            assert!(text.parse::<Amount>().is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn test_checked_arithmetic() {
+        let one = Amount::from_coins(1);
+        assert_eq!(one.checked_add(one), Some(Amount::from_coins(2)));
+        assert_eq!(Amount::MAX.checked_add(Amount::from_units(1)), None);
+        assert_eq!(Amount::ZERO.checked_sub(Amount::from_units(1)), None);
+        assert_eq!(Amount::checked_sum([one, one, one]), Some(Amount::from_coins(3)));
+        assert_eq!(Amount::checked_sum([Amount::MAX, one]), None);
+        assert_eq!(Amount::from_units(100).halved(2), Amount::from_units(25));
+        assert_eq!(Amount::from_units(100).halved(64), Amount::ZERO);
+    }
+}
@@ -0,0 +1,336 @@
+//? Coin selection strategies of the wallet
+/// A selector picks which unspent outputs of an address pay for a new transaction.
+/// Whatever the strategy, the change left by a selection is either nothing or at least `DUST_LIMIT`
+/// when the coins allow it, otherwise every coin is selected.
+use rand::{seq::SliceRandom, Rng};
+use std::{collections::HashMap, fmt, io, str::FromStr};
+use super::amount::Amount;
+use super::transaction::DUST_LIMIT; //change below it goes to the miner
+
+//? number of branches branch-and-bound explores before falling back to largest-first
+const BNB_MAX_TRIES: usize = 100_000;
+
+//? An unspent output that can be selected
+#[derive(Debug, Clone, PartialEq)]
+pub struct Coin {
+    pub tranc_id: String,//transaction holding the output
+    pub vout: i32,//index of the output in it
+    pub value: Amount,//coins it holds
+}
+
+//? Strategy choosing the coins paying for a target amount
+pub trait CoinSelector {
+    //* To select coins worth at least the target, or every coin when they're not enough */
+    fn select(&self, coins: &[Coin], target: Amount) -> Vec<Coin>;
+}
+
+//? Selects coins in the order they are given, the order of the UTXO set
+#[derive(Debug, Clone, Copy, Default)]
+pub struct InOrder;
+
+//? Selects the largest coins first, using as few inputs as possible
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LargestFirst;
+
+//? Selects the smallest coins first, consolidating many small outputs
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SmallestFirst;
+
+//? Searches for coins matching the target without a change output, falling back to largest-first
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BranchAndBound;
+
+//? Selects random coins, then keeps adding some while the change gets closer to the amount sent
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RandomImprove;
+
+//* To accumulate coins in order until the change is settled */
+fn accumulate<'a>(coins: impl IntoIterator<Item = &'a Coin>, target: Amount) -> Vec<Coin> {
+    let mut selected = Vec::new();
+    let mut accumulated = Amount::ZERO;
+    for coin in coins {
+        selected.push(coin.clone());
+        accumulated = accumulated.saturating_add(coin.value);
+        if settles(accumulated, target) {
+            break;
+        }
+    }
+    selected
+}
+
+//* To check that selected coins cover the target without leaving dust */
+fn settles(accumulated: Amount, target: Amount) -> bool {
+    match accumulated.checked_sub(target) {
+        Some(change) => change.is_zero() || change >= DUST_LIMIT,
+        None => false,
+    }
+}
+
+fn total(coins: &[Coin]) -> Amount {
+    coins.iter().fold(Amount::ZERO, |total, coin| total.saturating_add(coin.value))
+}
+
+//* To get how far apart two amounts are */
+fn distance(a: Amount, b: Amount) -> Amount {
+    a.saturating_sub(b).saturating_add(b.saturating_sub(a))
+}
+
+//* To select coins for a target, grouping the selected output indexes by transaction along with their total */
+pub fn select_outputs(selector: &dyn CoinSelector, coins: &[Coin], target: Amount) -> (Amount, HashMap<String, Vec<i32>>) {
+    let mut outputs: HashMap<String, Vec<i32>> = HashMap::new();
+    let mut accumulated = Amount::ZERO;
+    for coin in selector.select(coins, target) {
+        outputs.entry(coin.tranc_id).or_default().push(coin.vout);
+        accumulated = accumulated.saturating_add(coin.value);
+    }
+    (accumulated, outputs)
+}
+
+impl CoinSelector for InOrder {
+    fn select(&self, coins: &[Coin], target: Amount) -> Vec<Coin> {
+        accumulate(coins, target)
+    }
+}
+
+impl CoinSelector for LargestFirst {
+    fn select(&self, coins: &[Coin], target: Amount) -> Vec<Coin> {
+        let mut sorted: Vec<&Coin> = coins.iter().collect();
+        sorted.sort_by_key(|coin| std::cmp::Reverse(coin.value));
+        accumulate(sorted, target)
+    }
+}
+
+impl CoinSelector for SmallestFirst {
+    fn select(&self, coins: &[Coin], target: Amount) -> Vec<Coin> {
+        let mut sorted: Vec<&Coin> = coins.iter().collect();
+        sorted.sort_by_key(|coin| coin.value);
+        accumulate(sorted, target)
+    }
+}
+
+impl BranchAndBound {
+    //* To search depth first for the selection closest above the target without a change output */
+    fn search(coins: &[&Coin], target: Amount) -> Option<Vec<usize>> {
+        //sums in base units can't overflow as u128
+        let target = u128::from(target.units());
+        let upper = target + u128::from(DUST_LIMIT.units());//anything below is paid as fee instead of change
+        let mut remaining: Vec<u128> = vec![0; coins.len() + 1];//value of the coins after each index
+        for i in (0..coins.len()).rev() {
+            remaining[i] = remaining[i + 1] + u128::from(coins[i].value.units());
+        }
+
+        let mut best: Option<(u128, Vec<usize>)> = None;
+        let mut tries = 0;
+        //stack of (next coin index, selected indexes, selected value)
+        let mut stack: Vec<(usize, Vec<usize>, u128)> = vec![(0, Vec::new(), 0)];
+        while let Some((index, selected, value)) = stack.pop() {
+            tries += 1;
+            if tries > BNB_MAX_TRIES {
+                break;
+            }
+            if value >= target {
+                if best.as_ref().is_none_or(|(excess, _)| value - target < *excess) {
+                    best = Some((value - target, selected));
+                    if value == target {
+                        break;//can't do better than an exact match
+                    }
+                }
+                continue;
+            }
+            if index == coins.len() || value + remaining[index] < target {
+                continue;//not enough left on this branch
+            }
+            //excluding the coin is explored after including it
+            stack.push((index + 1, selected.clone(), value));
+            let with = value + u128::from(coins[index].value.units());
+            if with < upper {
+                let mut selected = selected;
+                selected.push(index);
+                stack.push((index + 1, selected, with));
+            }
+        }
+        best.map(|(_, selected)| selected)
+    }
+}
+
+impl CoinSelector for BranchAndBound {
+    fn select(&self, coins: &[Coin], target: Amount) -> Vec<Coin> {
+        let mut sorted: Vec<&Coin> = coins.iter().collect();
+        sorted.sort_by_key(|coin| std::cmp::Reverse(coin.value));
+        match BranchAndBound::search(&sorted, target) {
+            Some(selected) => selected.into_iter().map(|i| sorted[i].clone()).collect(),
+            None => LargestFirst.select(coins, target),
+        }
+    }
+}
+
+impl RandomImprove {
+    //* To select with a given source of randomness */
+    fn select_with<R: Rng>(coins: &[Coin], target: Amount, rng: &mut R) -> Vec<Coin> {
+        let mut shuffled: Vec<&Coin> = coins.iter().collect();
+        shuffled.shuffle(rng);
+
+        //random coins until the target is covered
+        let mut selected = Vec::new();
+        let mut rest = shuffled.into_iter();
+        for coin in rest.by_ref() {
+            selected.push(coin.clone());
+            if total(&selected) >= target {
+                break;
+            }
+        }
+        if total(&selected) < target {
+            return selected;//every coin taken
+        }
+
+        //improving: change as large as the amount is ideal, twice the amount the limit
+        let ideal = target.saturating_mul(2);
+        let limit = target.saturating_mul(3);
+        let mut skipped = Vec::new();
+        for coin in rest {
+            let accumulated = total(&selected);
+            let with = accumulated.saturating_add(coin.value);
+            if with <= limit && distance(ideal, with) < distance(ideal, accumulated) {
+                selected.push(coin.clone());
+            } else {
+                skipped.push(coin);
+            }
+        }
+
+        //topping up change that would be dust with the coins left
+        if !settles(total(&selected), target) {
+            for coin in skipped {
+                selected.push(coin.clone());
+                if settles(total(&selected), target) {
+                    break;
+                }
+            }
+        }
+        selected
+    }
+}
+
+impl CoinSelector for RandomImprove {
+    fn select(&self, coins: &[Coin], target: Amount) -> Vec<Coin> {
+        RandomImprove::select_with(coins, target, &mut rand::thread_rng())
+    }
+}
+
+//? Coin selection strategies that can be chosen by name
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Strategy {
+    #[default]
+    InOrder,
+    LargestFirst,
+    SmallestFirst,
+    BranchAndBound,
+    RandomImprove,
+}
+
+impl Strategy {
+    //? names of the strategies, as parsed and displayed
+    pub const NAMES: [&'static str; 5] = ["in-order", "largest-first", "smallest-first", "branch-and-bound", "random-improve"];
+
+    //* To get the selector of a strategy */
+    pub fn selector(&self) -> Box<dyn CoinSelector> {
+        match self {
+            Strategy::InOrder => Box::new(InOrder),
+            Strategy::LargestFirst => Box::new(LargestFirst),
+            Strategy::SmallestFirst => Box::new(SmallestFirst),
+            Strategy::BranchAndBound => Box::new(BranchAndBound),
+            Strategy::RandomImprove => Box::new(RandomImprove),
+        }
+    }
+}
+
+impl fmt::Display for Strategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Strategy::InOrder => Strategy::NAMES[0],
+            Strategy::LargestFirst => Strategy::NAMES[1],
+            Strategy::SmallestFirst => Strategy::NAMES[2],
+            Strategy::BranchAndBound => Strategy::NAMES[3],
+            Strategy::RandomImprove => Strategy::NAMES[4],
+        };
+        f.write_str(name)
+    }
+}
+
+impl FromStr for Strategy {
+    type Err = io::Error;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "in-order" => Ok(Strategy::InOrder),
+            "largest-first" => Ok(Strategy::LargestFirst),
+            "smallest-first" => Ok(Strategy::SmallestFirst),
+            "branch-and-bound" => Ok(Strategy::BranchAndBound),
+            "random-improve" => Ok(Strategy::RandomImprove),
+            _ => Err(io::Error::new(io::ErrorKind::InvalidInput, format!(
+                "UNKNOWN_COIN_SELECTION {}: expected one of {}", name, Strategy::NAMES.join(", "),
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+    use crate::test_utils::units;
+
+    //* helper to get coins of some values, each from its own transaction */
+    fn coins_of(values: &[u64]) -> Vec<Coin> {
+        values.iter().enumerate().map(|(i, value)| Coin { tranc_id: format!("tx{}", i), vout: 0, value: units(*value) }).collect()
+    }
+
+    fn values(selected: &[Coin]) -> Vec<u64> {
+        selected.iter().map(|coin| coin.value.units()).collect()
+    }
+
+    #[test]
+    fn test_ordered_selectors() {
+        let available = coins_of(&[2000, 500, 6000, 1000]);
+        assert_eq!(values(&InOrder.select(&available, units(2500))), vec![2000, 500]);
+        assert_eq!(values(&LargestFirst.select(&available, units(2500))), vec![6000]);
+        assert_eq!(values(&SmallestFirst.select(&available, units(2500))), vec![500, 1000, 2000]);
+        //change of 3 from the two smallest would be dust
+        assert_eq!(values(&SmallestFirst.select(&available, units(1497))), vec![500, 1000, 2000]);
+        assert_eq!(values(&LargestFirst.select(&available, units(20000))), vec![6000, 2000, 1000, 500]);
+    }
+
+    #[test]
+    fn test_branch_and_bound_avoids_change() {
+        let available = coins_of(&[5000, 3000, 2300, 1700, 800]);
+        assert_eq!(values(&BranchAndBound.select(&available, units(4000))), vec![2300, 1700]);
+        //no exact match, the least excess below the dust limit wins
+        let mut selected = values(&BranchAndBound.select(&available, units(5798)));
+        selected.sort();
+        assert_eq!(selected, vec![800, 5000]);
+        //nothing without change, falling back to largest-first
+        assert_eq!(values(&BranchAndBound.select(&coins_of(&[5000, 3000]), units(1000))), vec![5000]);
+        assert_eq!(values(&BranchAndBound.select(&coins_of(&[500, 500]), units(2000))), vec![500, 500]);
+    }
+
+    #[test]
+    fn test_random_improve() {
+        let available = coins_of(&[1000; 20]);
+        for seed in 0..20 {
+            let selected = RandomImprove::select_with(&available, units(3000), &mut StdRng::seed_from_u64(seed));
+            let total = total(&selected).units();
+            //improving towards twice the target, never past three times
+            assert!((6000..=9000).contains(&total), "selected {}", total);
+            assert!(settles(units(total), units(3000)));
+        }
+        //not enough coins selects them all
+        assert_eq!(RandomImprove.select(&coins_of(&[1, 2]), units(10)).len(), 2);
+    }
+
+    #[test]
+    fn test_strategy_names() {
+        for name in Strategy::NAMES {
+            assert_eq!(name.parse::<Strategy>().unwrap().to_string(), name);
+        }
+        assert!("best".parse::<Strategy>().is_err());
+    }
+}
@@ -6,9 +6,73 @@ use failure::format_err; //for handling errors
 use serde::{Deserialize, Serialize}; // for serialization and deserialization
 use std::{collections::HashMap,io}; // for generating hashmaps
 use crate::miner::chain::Blockchain; //importing the blockchain module 
+use crate::wallet::amount::Amount; //values of outputs and fees
+use crate::wallet::selection::{select_outputs, Coin, CoinSelector}; //strategy choosing the spent outputs
 use crate::wallet::tx::{TrancInput,TrancOutput}; //imporint the Transaction Input-Output structs
 use super::wallet::{hash_pub_key, Wallets}; //using wallet functions
 
+//? change below this is left to the miner instead of creating an output that costs more to spend than it holds
+pub const DUST_LIMIT: Amount = Amount::from_units(5);
+//? number of times the fee of a new transaction is recomputed for its size before giving up
+const MAX_FEE_ROUNDS: usize = 8;
+
+//? Fee a new transaction pays to the miner of its block
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fee {
+    Absolute(Amount),//the same fee whatever the size
+    PerByte(Amount),//fee in base units for every byte of the bincode serialized transaction
+}
+
+impl Fee {
+    //* To get the fee of a transaction of a given size */
+    pub fn for_size(&self, size: usize) -> Amount {
+        match self {
+            Fee::Absolute(fee) => *fee,
+            Fee::PerByte(rate) => rate.saturating_mul(u64::try_from(size).unwrap_or(u64::MAX)),
+        }
+    }
+}
+
+//? Transactions waiting for a block that a new one has to account for, such as those of the mempool
+pub trait PendingTransactions {
+    //* To get a pending transaction by id */
+    fn pending(&self, tranc_id: &str) -> Option<&Transaction>;
+    //* To get every pending transaction */
+    fn all_pending(&self) -> Vec<&Transaction>;
+    //* To check whether a pending transaction already spends an output */
+    fn spends(&self, tranc_id: &str, vout: i32) -> bool;
+    //* To get the fee a pending transaction pays */
+    fn fee_of(&self, tranc_id: &str) -> Option<Amount>;
+    //* To get the lowest fee a transaction of a given size pays to replace a pending one along with its descendants */
+    fn replacement_fee(&self, tranc_id: &str, size: usize) -> Option<Amount>;
+}
+
+//? How a new transaction pays its fee and picks the outputs it spends
+pub struct SpendOptions<'a> {
+    pub fee: Fee,//paid to the miner on top of the amount, change that would be dust is added to it
+    pub selector: &'a dyn CoinSelector,//strategy picking the unspent outputs of the sender
+    pub pending: &'a dyn PendingTransactions,//transactions waiting for a block, whose inputs can't be spent again
+    pub unconfirmed: bool,//whether outputs of pending transactions can be spent
+}
+
+impl SpendOptions<'_> {
+    //* To find the outputs of an address the selector picks to pay an amount, leaving out those pending transactions spend */
+    pub fn find_spendable_outputs(&self, blockchain: &Blockchain, address: &[u8], amount: Amount) -> (Amount, HashMap<String, Vec<i32>>) {
+        let mut coins: Vec<Coin> = blockchain.spendable_coins(address);
+        if self.unconfirmed {
+            for tx in self.pending.all_pending() {
+                for (index, out) in tx.vout.iter().enumerate() {
+                    if out.can_be_unlock_with(address.to_vec()) {
+                        coins.push(Coin { tranc_id: tx.tranc_id.clone(), vout: index as i32, value: out.value });
+                    }
+                }
+            }
+        }
+        coins.retain(|coin| !self.pending.spends(&coin.tranc_id, coin.vout));
+        select_outputs(self.selector, &coins, amount)
+    }
+}
+
 //? Blockchain transaction struct
 #[derive(Debug, Deserialize, Serialize, Clone)]
 pub struct Transaction {
@@ -21,14 +85,17 @@ pub struct Transaction {
 impl Transaction {
 
     //* For creating a new transaction utxo */
+    /// wallets: wallets holding the keys of the sender and the receiver
     /// from: sender address
     /// to: receiver address
-    /// amount: to be sent from sender to receiver
+    /// amount: to be sent from sender to receiver, can't be zero
     /// blockchain: the involved blockchain
-    pub fn new_utxo(from: &str, to: &str, amount: i32, blockchain: &Blockchain) -> Result<Transaction,failure::Error>{
-        let mut vin = Vec::new();//for storing previous UTXO outputs to refer as input
-        let wallets = Wallets::new()?; // creating a wallets object 
-        
+    /// options: fee, coin selection and pending transactions to account for
+    pub fn new_utxo(wallets: &Wallets, from: &str, to: &str, amount: Amount, blockchain: &Blockchain, options: &SpendOptions) -> Result<Transaction,failure::Error>{
+        if amount.is_zero(){
+            return Err(format_err!("ZERO_AMOUNT: an output has to hold some coins"));
+        }
+
         //getting a wallet for a particular address(from) from the wallets hashmap 
         let wallet = match wallets.get_wallet(from){
             Some(w) => w, //returning it there is 
@@ -36,62 +103,167 @@ impl Transaction {
         };
 
         //checking if reciever address is correct or not
-        if let None = wallets.get_wallet(to){
+        if wallets.get_wallet(to).is_none(){
             return Err(format_err!("RECEIVER_WALLET_NOT_FOUND!"));//handlin error from receiver side
         }
 
         let mut pub_key_hash: Vec<u8> = wallet.public_key.clone(); //cloning senders public key
         hash_pub_key(&mut pub_key_hash); //Creating hash using Sha256 and Rimpemd160
 
-        //for findnig spendable UTXO outputs from previous transactions
-        let acc_v = blockchain.find_spendable_outputs(pub_key_hash, amount); //storing spendable UTXOs 
-        if acc_v.0<amount {
-            eprintln!("NOT_ENOUGH_BALANCE");//for handling error in case 
-            return Err(format_err!("NOT_ENOUGH_BALANCE: Current Balance {}",acc_v.0));//handling error for not enough amount in senders account
-        }
-
-        //iterating through the transaction output UTXOs to include them in vin 
-        for tx in acc_v.1{
-            for out in tx.1{
-                let input = TrancInput{
-                    from:tx.0.clone(),//previous UTXO senders name
-                    vout:out,//amount taken from the UTXO
-                    signature:Vec::new(),//signature initialisation 
-                    pub_key: wallet.public_key.clone(), //public key of the sender
-                };
-                vin.push(input);
+        //a fee per byte depends on the inputs selected for it, so selecting again until the fee covers the size
+        let fee = options.fee;
+        let mut fee_target = match fee{
+            Fee::Absolute(fee) => fee,
+            Fee::PerByte(_) => Amount::ZERO,
+        };
+        for _ in 0..MAX_FEE_ROUNDS{
+            //for findnig spendable UTXO outputs from previous transactions
+            let target = amount.checked_add(fee_target).ok_or_else(|| format_err!("AMOUNT_OVERFLOW: {} plus a fee of {}", amount, fee_target))?;
+            let acc_v = options.find_spendable_outputs(blockchain, &pub_key_hash, target); //storing spendable UTXOs 
+            if acc_v.0<target {
+                return Err(format_err!("NOT_ENOUGH_BALANCE: Current Balance {}, needed {} with a fee of {}",acc_v.0,target,fee_target));//handling error for not enough amount in senders account
+            }
+
+            //iterating through the transaction output UTXOs to include them in vin 
+            let mut vin = Vec::new();//for storing previous UTXO outputs to refer as input
+            for tx in acc_v.1{
+                for out in tx.1{
+                    vin.push(TrancInput{
+                        from:tx.0.clone(),//previous UTXO senders name
+                        vout:out,//amount taken from the UTXO
+                        signature:Vec::new(),//signature initialisation 
+                        pub_key: wallet.public_key.clone(), //public key of the sender
+                    });
+                }
+            }
+            //setting up vout
+            let mut vout = vec![TrancOutput::new(
+                amount,//amount to be transfered to the receiver
+                to.to_string()//public address of the receiver
+            )?];
+            let change = acc_v.0.saturating_sub(target);
+            if change >= DUST_LIMIT{
+                vout.push(
+                    TrancOutput::new(
+                        change,//showing the remaining amount in the account 
+                        from.to_string()//senders public address
+                    )?
+                )
+            }
+
+            //creating the transaction
+            let mut transaction = Transaction{
+                tranc_id: String::new(),//initialising transaction id 
+                vin,//UTXO inputs from previous outputs
+                vout,//UTXO output from affordable inputs
+            };
+            transaction.sign_spending(&wallet.secret_key, blockchain, options.pending)?; //signing the transaction for auth 
+            transaction.tranc_id = transaction.hash()?; //setting up the transaction ID over the signed content
+
+            let outputs = Amount::checked_sum(transaction.vout.iter().map(|out| out.value)).ok_or_else(|| format_err!("AMOUNT_OVERFLOW"))?;
+            let paid = acc_v.0.saturating_sub(outputs);
+            let needed = fee.for_size(transaction.size()?);
+            if needed <= paid{
+                return Ok(transaction)//successful tansaction
             }
+            fee_target = needed;
         }
-        //setting up vout
-        let mut vout = vec![TrancOutput::new(
-            amount,//amount to be transfered to the receiver
-            to.to_string()//public address of the receiver
-        )?];
-        if acc_v.0>amount{
-            vout.push(
-                TrancOutput::new(
-                    acc_v.0-amount,//showing the remaining amount in the account 
-                    from.to_string()//senders public address
-                )?
-            )
-        }
-
-        //creating the transaction
-        let mut transaction = Transaction{
-            tranc_id: String::new(),//initialising transaction id 
-            vin,//UTXO inputs from previous outputs
-            vout,//UTXO output from affordable inputs
+        Err(format_err!("FEE_NOT_SETTLED: the fee kept growing with the selected inputs"))
+    }
+
+    //* For rebuilding a pending transaction from the same inputs with a higher fee taken from its change */
+    /// The replacement evicts the pending descendants of the original too, so its fee has to cover all of theirs
+    /// along with the increment the pending transactions ask of a replacement for its size.
+    /// wallets: wallets holding the key of the sender
+    /// tranc_id: id of the pending transaction to replace
+    /// fee: paid to the miner by the replacement, change that would be dust is added to it
+    /// blockchain: the involved blockchain
+    /// pending: pending transactions, holding the original
+    pub fn bump_fee(wallets: &Wallets, tranc_id: &str, fee: Fee, blockchain: &Blockchain, pending: &dyn PendingTransactions) -> Result<Transaction,failure::Error>{
+        let (original, original_fee) = match (pending.pending(tranc_id), pending.fee_of(tranc_id)){
+            (Some(original), Some(fee)) => (original, fee),
+            _ => return Err(format_err!("NOT_PENDING: {} is not in the mempool", tranc_id)),
+        };
+        let pub_key = match original.vin.first(){
+            Some(vin) => vin.pub_key.clone(),
+            None => return Err(format_err!("NOT_PENDING: {} has no inputs", tranc_id)),
+        };
+        if original.vin.iter().any(|vin| vin.pub_key != pub_key){
+            return Err(format_err!("MIXED_SENDERS: the inputs of {} belong to several wallets", tranc_id));
+        }
+
+        //finding the wallet of the sender
+        let wallet = match wallets.get_all_addresses().iter().filter_map(|address| wallets.get_wallet(address)).find(|w| w.public_key == pub_key){
+            Some(w) => w,
+            None => return Err(format_err!("SENDER_WALLET_NOT_FOUND!")),
         };
-        transaction.tranc_id = transaction.hash()?; //setting up the transaction ID
-        let _response = blockchain.sign_transaction(&mut transaction, &wallet.secret_key); //signing the transaction for auth 
-        Ok(transaction)//successful tansaction
+        let mut pub_key_hash = pub_key;
+        hash_pub_key(&mut pub_key_hash);
+
+        //the change is the last output paying the sender back, the other outputs are paid as they were
+        let change_index = match original.vout.iter().rposition(|out| out.can_be_unlock_with(pub_key_hash.clone())){
+            Some(index) => index,
+            None => return Err(format_err!("NO_CHANGE: {} has no change to pay a higher fee from", tranc_id)),
+        };
+        let outputs = Amount::checked_sum(original.vout.iter().map(|out| out.value)).ok_or_else(|| format_err!("AMOUNT_OVERFLOW"))?;
+        let inputs = outputs.checked_add(original_fee).ok_or_else(|| format_err!("AMOUNT_OVERFLOW"))?;
+        let payments = outputs.saturating_sub(original.vout[change_index].value);
+
+        let mut fee_target = match fee{
+            Fee::Absolute(fee) => fee,
+            Fee::PerByte(rate) => rate.saturating_mul(u64::try_from(original.size()?).unwrap_or(u64::MAX)),
+        };
+        for _ in 0..MAX_FEE_ROUNDS{
+            let change = match inputs.checked_sub(payments).and_then(|left| left.checked_sub(fee_target)){
+                Some(change) => change,
+                None => return Err(format_err!("NOT_ENOUGH_CHANGE: {} can't pay a fee of {} from its change", tranc_id, fee_target)),
+            };
+            let mut vout = original.vout.clone();
+            if change >= DUST_LIMIT{
+                vout[change_index].value = change;
+            }else{
+                vout.remove(change_index);
+            }
+
+            let mut transaction = Transaction{
+                tranc_id: String::new(),
+                vin: original.vin.iter().map(|vin| TrancInput{ signature: Vec::new(), ..vin.clone() }).collect(),
+                vout,
+            };
+            transaction.sign_spending(&wallet.secret_key, blockchain, pending)?; //signing over the same inputs
+            transaction.tranc_id = transaction.hash()?;
+
+            let outputs = Amount::checked_sum(transaction.vout.iter().map(|out| out.value)).ok_or_else(|| format_err!("AMOUNT_OVERFLOW"))?;
+            let paid = inputs.saturating_sub(outputs);
+            let size = transaction.size()?;
+            let needed = fee.for_size(size);
+            if needed > paid{
+                fee_target = needed;
+                continue;
+            }
+            //the package being replaced sets the lowest fee, whatever was asked for
+            let replacing = pending.replacement_fee(tranc_id, size).ok_or_else(|| format_err!("NOT_PENDING: {} is not in the mempool", tranc_id))?;
+            if paid < replacing{
+                return Err(format_err!("FEE_NOT_HIGHER: a fee of {} doesn't replace {} and its pending descendants, which takes at least {}", paid, tranc_id, replacing));
+            }
+            return Ok(transaction)
+        }
+        Err(format_err!("FEE_NOT_SETTLED: the fee kept growing with the size of the replacement"))
+    }
+
+    //* function to get the size of the bincode serialized transaction, the size fees per byte are paid for */
+    pub fn size(&self) -> Result<usize,io::Error>{
+        bincode::serialized_size(self)
+            .map(|size| size as usize)
+            .map_err(|e| io::Error::other(e.to_string()))
     }
 
-    //* function for creating a new coinbase transaction */
+    //* function for creating a coinbase transaction paying a given reward */
     //to: miner address
-    //data: Message for the miner
-    pub fn new_coinbase(to: String, mut data: String) -> Result<Self, io::Error> {
-        if data == String::from("") {//default minor data condition
+    //data: Message for the miner, also keeping coinbases to the same address apart
+    //value: subsidy of the block plus the fees it collects
+    pub fn new_reward(to: String, mut data: String, value: Amount) -> Result<Self, io::Error> {
+        if data.is_empty() {//default minor data condition
             data += &format!("Reward to {}", to);//message
         }
 
@@ -104,7 +276,7 @@ impl Transaction {
                 signature: Vec::new(),//no signature needed 
                 pub_key: Vec::from(data.as_bytes()),//just a default address
             }],
-            vout: vec![TrancOutput::new(100,to).unwrap()],//100coins rewarded to the miner
+            vout: vec![TrancOutput::new(value, to).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?],//reward of the miner
         };
         transaction.tranc_id = transaction.hash()?;//setting the coinbase transaction id 
         Ok(transaction)//successfull transaction
@@ -112,66 +284,72 @@ impl Transaction {
 
     //* function to check whether a block is a coin base */
     pub fn is_coinbase(&self) -> bool {
-        self.vin.len() == 1 && self.tranc_id.is_empty() && self.vin[0].vout == -1 //condition for coinbase
+        self.vin.len() == 1 && self.vin[0].from.is_empty() && self.vin[0].vout == -1 //condition for coinbase
+    }
+
+    //* function to check that the stored transaction id matches its content */
+    pub fn has_valid_id(&self) -> Result<bool,io::Error>{
+        let mut tranc_copy = self.clone(); //hashing a copy so the stored id stays untouched
+        Ok(tranc_copy.hash()? == self.tranc_id)
+    }
+
+    //* function to sign the inputs of a transaction spending confirmed outputs or outputs of pending transactions */
+    /// The confirmed outputs come from the UTXO set, so nothing is searched in the blocks.
+    pub fn sign_spending(&mut self, private_key: &[u8], blockchain: &Blockchain, pending: &dyn PendingTransactions) -> Result<(),io::Error>{
+        let mut spent: Vec<TrancOutput> = Vec::new();//outputs spent by the inputs, in input order
+        for vin in &self.vin{
+            let output = match pending.pending(&vin.from){
+                Some(tx) => usize::try_from(vin.vout).ok().and_then(|index| tx.vout.get(index)).cloned(),
+                None => blockchain.unspent_output(&vin.from, vin.vout)?.map(|unspent| unspent.output),
+            };
+            spent.push(output.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("MISSING_OUTPUT: {}:{} is neither unspent nor pending", vin.from, vin.vout)))?);
+        }
+        self.sign(private_key, &spent).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))
     }
 
-    //* function to sign the signature */
+    //* function to sign the signature given the outputs its inputs spend, in input order */
     //prinvate_key = private key of the sender
-    pub fn sign(&mut self, private_key: &[u8], prev_txs: HashMap<String,Transaction>) -> Result<(),failure::Error>{
+    /// Every input signs the trimmed copy of the transaction in which only that input carries
+    /// the pub_key_hash of the output it spends, so the signature commits to every input and output.
+    pub fn sign(&mut self, private_key: &[u8], spent: &[TrancOutput]) -> Result<(),failure::Error>{
         if self.is_coinbase(){ //checking is the given transaction is a coinbase trnsaction 
             return Ok(());//so signature required for coinbase transaction
         }
-
-        //checking if all transactions are validated by ids or not
-        for vin in &self.vin{
-            if prev_txs.get(&vin.from).unwrap().tranc_id.is_empty(){//checking condition
-                return Err(format_err!("PREVIOUS_TRANSACTION_FAULT_ID_ERROR")); //handling error
-            }
+        if spent.len() != self.vin.len(){
+            return Err(format_err!("ERROR: {} inputs but {} spent outputs", self.vin.len(), spent.len()));
         }
-        let mut tranc_copy = self.trim_copy(); //getting a full clone of the transaction
 
-        for in_id in 0..tranc_copy.vin.len(){
-            let prev_tx = prev_txs.get(&tranc_copy.vin[in_id].from).unwrap();//getting transaction
-            tranc_copy.vin[in_id].signature.clear(); //clearing the previous signature
-            tranc_copy.vin[in_id].pub_key = prev_tx.vout[tranc_copy.vin[in_id].vout as usize]
-                .pub_key_hash.clone();
-            tranc_copy.tranc_id = tranc_copy.hash()?;
-            tranc_copy.vin[in_id].pub_key = Vec::new();//removing public key
-            let signature = ed25519::signature(tranc_copy.tranc_id.as_bytes(), private_key); //signing each UTXO input from previous output
+        for (in_id, prev_out) in spent.iter().enumerate(){
+            let message = self.signature_message(in_id, prev_out)?;
+            let signature = ed25519::signature(message.as_bytes(), private_key); //signing each UTXO input from previous output
             self.vin[in_id].signature = signature.to_vec(); //assigning signature to the transaction vin
         }
         Ok(())
     }
 
-    //* Function to verify the signature of a transaction */
-    ///similar to sign function
-    ///only difference in verification and signing step 
-    pub fn verify(&mut self, prev_txs: HashMap<String,Transaction>) -> Result<bool,failure::Error>{
+    //* Function to verify the signatures of a transaction given the outputs its inputs spend, in input order */
+    /// Each input has to carry the public key the output it spends is locked to
+    /// and a signature of the trimmed copy made with that key, see `sign`.
+    pub fn verify_outputs(&self, spent: &[TrancOutput]) -> Result<bool,failure::Error>{
         if self.is_coinbase(){
             return Ok(true);
         }
-
-        for vin in &self.vin{
-            if prev_txs.get(&vin.from).unwrap().tranc_id.is_empty(){
-                return Err(format_err!("ERROR: Previous transaction is not correct"));
-            }
+        if spent.len() != self.vin.len(){
+            return Err(format_err!("ERROR: {} inputs but {} spent outputs", self.vin.len(), spent.len()));
         }
 
-        let mut tranc_copy = self.trim_copy();//making copy
-
-        for in_id in 0..self.vin.len(){
-            let prev_tx = prev_txs.get(&self.vin[in_id].from).unwrap();
-            tranc_copy.vin[in_id].signature.clear();
-            tranc_copy.vin[in_id].pub_key = prev_tx.vout[tranc_copy.vin[in_id].vout as usize]
-                .pub_key_hash.clone();
-            tranc_copy.tranc_id = tranc_copy.hash()?;
-            tranc_copy.vin[in_id].pub_key = Vec::new();
-
+        for (in_id, prev_out) in spent.iter().enumerate(){
+            let vin = &self.vin[in_id];
+            //the key has to be the one the output is locked to
+            if vin.pub_key.len() != 32 || !vin.can_unlock_output_with(prev_out.pub_key_hash.clone()){
+                return Ok(false);
+            }
             //using elliptic curve for signature verification
-            if !ed25519::verify(
-                &tranc_copy.tranc_id.as_bytes(), //transaction id
-                &self.vin[in_id].signature, //transaction public address
-                &self.vin[in_id].signature, //previously generated signatire
+            let message = self.signature_message(in_id, prev_out)?;
+            if vin.signature.len() != 64 || !ed25519::verify(
+                message.as_bytes(), //hash of the trimmed copy
+                &vin.pub_key, //public key of the sender
+                &vin.signature, //previously generated signatire
             ){
                 return Ok(false);
             }
@@ -179,8 +357,15 @@ impl Transaction {
         Ok(true)
     }
 
+    //* function to build the message signed by an input */
+    fn signature_message(&self, in_id: usize, prev_out: &TrancOutput) -> Result<String,io::Error>{
+        let mut tranc_copy = self.trim_copy(); //copy without signatures and keys
+        tranc_copy.vin[in_id].pub_key = prev_out.pub_key_hash.clone();
+        tranc_copy.hash()
+    }
+
     //* function to hash a transaction id can return it */
-    fn hash(&mut self) -> Result<String,io::Error>{
+    pub(crate) fn hash(&mut self) -> Result<String,io::Error>{
         self.tranc_id = String::new(); //initialising
         let data = bincode::serialize(&self).unwrap_or_else(|e|{
             todo!("SIG_SERIALIZATION_ERR:{}",e);
@@ -190,7 +375,7 @@ impl Transaction {
         Ok(hasher.result_str())
     }
 
-    //* function to copy a transaction and return it */
+    //* function to copy a transaction without the signatures and public keys of its inputs */
     //Done so as not to effect the original data
     fn trim_copy(&self) -> Transaction{
         let mut vin = Vec::new(); //for storing UTXO inputs
@@ -200,16 +385,16 @@ impl Transaction {
         for v in &self.vin{
             vin.push(TrancInput{
                 from: v.from.clone(),
-                vout: v.vout.clone(),
-                signature: v.signature.clone(),
-                pub_key: v.pub_key.clone(),
+                vout: v.vout,
+                signature: Vec::new(),
+                pub_key: Vec::new(),
             });
         }
 
         //cloning the vout
         for v in &self.vout{
             vout.push(TrancOutput{
-                value: v.value.clone(),
+                value: v.value,
                 pub_key_hash: v.pub_key_hash.clone(),
             });
         }
@@ -218,3 +403,242 @@ impl Transaction {
     }
 }
 
+#[cfg(test)]
+mod tests{
+    use super::*;
+    use crate::miner::mempool::Mempool;
+    use crate::miner::reward::INITIAL_SUBSIDY;
+    use crate::wallet::selection::InOrder;
+    use crate::test_utils::{funded_wallets, paid_fee, test_keys, units, TempDir};
+
+    //* helper to build an unsigned transaction spending outputs of previous transactions */
+    fn unsigned(inputs: &[(&Transaction, i32, &[u8])], to: &str, value: u64) -> Transaction{
+        Transaction{
+            tranc_id: String::new(),
+            vin: inputs.iter().map(|(prev, vout, public)| TrancInput{ from: prev.tranc_id.clone(), vout: *vout, signature: Vec::new(), pub_key: public.to_vec() }).collect(),
+            vout: vec![TrancOutput::new(units(value), to.to_string()).unwrap()],
+        }
+    }
+
+    //* helper to get the first output of each previous transaction, the outputs the inputs built by `unsigned` spend */
+    fn spent(txs: &[&Transaction]) -> Vec<TrancOutput>{
+        txs.iter().map(|tx| tx.vout[0].clone()).collect()
+    }
+
+    #[test]
+    fn test_verify_signed_inputs(){
+        let (secret, public, address) = test_keys(1);
+        let (_, _, receiver) = test_keys(2);
+        let first = Transaction::new_reward(address.clone(), String::from("first"), INITIAL_SUBSIDY).unwrap();
+        let second = Transaction::new_reward(address, String::from("second"), INITIAL_SUBSIDY).unwrap();
+        let mut tx = unsigned(&[(&first, 0, &public), (&second, 0, &public)], &receiver, 200);
+        tx.sign(&secret, &spent(&[&first, &second])).unwrap();
+        assert!(tx.verify_outputs(&spent(&[&first, &second])).unwrap());
+        //every input needs the output it spends
+        let mut unmatched = unsigned(&[(&first, 0, &public)], &receiver, 100);
+        assert!(unmatched.sign(&secret, &[]).is_err());
+        assert!(unmatched.verify_outputs(&[]).is_err());
+    }
+
+    #[test]
+    fn test_verify_rejects_forged_signature(){
+        let (secret, public, address) = test_keys(1);
+        let (_, _, receiver) = test_keys(2);
+        let prev = Transaction::new_reward(address, String::new(), INITIAL_SUBSIDY).unwrap();
+        let mut tx = unsigned(&[(&prev, 0, &public)], &receiver, 100);
+        tx.sign(&secret, &spent(&[&prev])).unwrap();
+        tx.vin[0].signature[10] ^= 1;
+        assert!(!tx.verify_outputs(&spent(&[&prev])).unwrap());
+        tx.vin[0].signature.truncate(32);
+        assert!(!tx.verify_outputs(&spent(&[&prev])).unwrap());
+        //an unsigned input
+        let unsigned_tx = unsigned(&[(&prev, 0, &public)], &receiver, 100);
+        assert!(!unsigned_tx.verify_outputs(&spent(&[&prev])).unwrap());
+    }
+
+    #[test]
+    fn test_verify_rejects_swapped_key(){
+        let (_, public, address) = test_keys(1);
+        let (thief_secret, thief_public, thief) = test_keys(3);
+        let prev = Transaction::new_reward(address, String::new(), INITIAL_SUBSIDY).unwrap();
+        //a valid signature under a key that doesn't own the output
+        let mut tx = unsigned(&[(&prev, 0, &thief_public)], &thief, 100);
+        tx.sign(&thief_secret, &spent(&[&prev])).unwrap();
+        assert!(!tx.verify_outputs(&spent(&[&prev])).unwrap());
+        //the owner's key next to the thief's signature
+        tx.vin[0].pub_key = public.to_vec();
+        assert!(!tx.verify_outputs(&spent(&[&prev])).unwrap());
+    }
+
+    #[test]
+    fn test_verify_rejects_replayed_signature(){
+        let (secret, public, address) = test_keys(1);
+        let (_, _, receiver) = test_keys(2);
+        let (_, _, thief) = test_keys(3);
+        let first = Transaction::new_reward(address.clone(), String::from("first"), INITIAL_SUBSIDY).unwrap();
+        let second = Transaction::new_reward(address, String::from("second"), INITIAL_SUBSIDY).unwrap();
+        let mut paid = unsigned(&[(&first, 0, &public)], &receiver, 100);
+        paid.sign(&secret, &spent(&[&first])).unwrap();
+        assert!(paid.verify_outputs(&spent(&[&first])).unwrap());
+
+        //the signature reused on a transaction with other outputs
+        let mut redirected = unsigned(&[(&first, 0, &public)], &thief, 100);
+        redirected.vin[0].signature = paid.vin[0].signature.clone();
+        assert!(!redirected.verify_outputs(&spent(&[&first])).unwrap());
+        //the signature reused for another output of the same owner
+        let mut other_output = unsigned(&[(&second, 0, &public)], &receiver, 100);
+        other_output.vin[0].signature = paid.vin[0].signature.clone();
+        assert!(!other_output.verify_outputs(&spent(&[&second])).unwrap());
+        //signatures of two inputs swapped
+        let mut both = unsigned(&[(&first, 0, &public), (&second, 0, &public)], &receiver, 200);
+        both.sign(&secret, &spent(&[&first, &second])).unwrap();
+        both.vin.swap(0, 1);
+        let (a, b) = (both.vin[0].from.clone(), both.vin[1].from.clone());
+        both.vin[0].from = b;
+        both.vin[1].from = a;
+        assert!(!both.verify_outputs(&spent(&[&first, &second])).unwrap());
+    }
+
+    //* helper to get the options paying a fee out of the confirmed outputs, in order */
+    fn options(fee: Fee, pool: &Mempool) -> SpendOptions<'_>{
+        SpendOptions{ fee, selector: &InOrder, pending: pool, unconfirmed: false }
+    }
+
+    #[test]
+    fn test_fee_for_size(){
+        assert_eq!(Fee::Absolute(units(7)).for_size(300), units(7));
+        assert_eq!(Fee::PerByte(units(2)).for_size(300), units(600));
+        assert_eq!(Fee::PerByte(units(2)).for_size(usize::MAX), Amount::MAX);
+    }
+
+    #[test]
+    fn test_new_utxo_pays_fees(){
+        let dir = TempDir::new();
+        let (wallets, from, to, mut chain) = funded_wallets(&dir, units(100_000));
+        let pool = Mempool::open(&chain).unwrap();
+
+        //an absolute fee comes out of the change
+        let tx = Transaction::new_utxo(&wallets, &from, &to, units(30_000), &chain, &options(Fee::Absolute(units(1000)), &pool)).unwrap();
+        assert_eq!(tx.vout.len(), 2);
+        assert_eq!(tx.vout[1].value, units(69_000));
+        assert_eq!(paid_fee(&tx, units(100_000)), units(1000));
+
+        //a fee per byte covers the size of the signed transaction
+        let tx = Transaction::new_utxo(&wallets, &from, &to, units(30_000), &chain, &options(Fee::PerByte(units(2)), &pool)).unwrap();
+        let fee = paid_fee(&tx, units(100_000));
+        let needed = Fee::PerByte(units(2)).for_size(tx.size().unwrap());
+        assert!(fee >= needed);
+        assert!(fee < needed.checked_add(DUST_LIMIT).unwrap());
+
+        //the block validates with the fee going to the miner
+        let (_, _, miner) = test_keys(9);
+        chain.mine_block(&miner, vec![tx]).unwrap();
+        assert!(chain.validate_chain().is_ok());
+        let miner_hash = bitcoincash_addr::Address::decode(&miner).unwrap().body;
+        assert_eq!(Amount::checked_sum(chain.find_utxo(miner_hash).iter().map(|out| out.value)), units(100_000).checked_add(fee));
+    }
+
+    #[test]
+    fn test_new_utxo_leaves_no_dust(){
+        let dir = TempDir::new();
+        let (wallets, from, to, chain) = funded_wallets(&dir, units(100_000));
+        let pool = Mempool::open(&chain).unwrap();
+        //3 units of change would be dust, they go to the miner
+        let tx = Transaction::new_utxo(&wallets, &from, &to, units(98_997), &chain, &options(Fee::Absolute(units(1000)), &pool)).unwrap();
+        assert_eq!(tx.vout.len(), 1);
+        assert_eq!(paid_fee(&tx, units(100_000)), units(1003));
+        //the fee has to be affordable too
+        let err = Transaction::new_utxo(&wallets, &from, &to, units(99_500), &chain, &options(Fee::Absolute(units(1000)), &pool)).unwrap_err();
+        assert!(err.to_string().starts_with("NOT_ENOUGH_BALANCE"));
+        assert!(Transaction::new_utxo(&wallets, &from, &to, units(30_000), &chain, &options(Fee::PerByte(units(1000)), &pool)).is_err());
+        //nothing can't be sent
+        let err = Transaction::new_utxo(&wallets, &from, &to, Amount::ZERO, &chain, &options(Fee::Absolute(units(1000)), &pool)).unwrap_err();
+        assert!(err.to_string().starts_with("ZERO_AMOUNT"));
+    }
+
+    #[test]
+    fn test_bump_fee_takes_it_from_the_change(){
+        let dir = TempDir::new();
+        let (wallets, from, to, mut chain) = funded_wallets(&dir, units(100_000));
+        let mut pool = Mempool::open(&chain).unwrap();
+        let tx = Transaction::new_utxo(&wallets, &from, &to, units(30_000), &chain, &options(Fee::Absolute(units(1000)), &pool)).unwrap();
+        pool.add(tx.clone(), &chain).unwrap();
+
+        //the same inputs and payment, a smaller change
+        let bumped = Transaction::bump_fee(&wallets, &tx.tranc_id, Fee::Absolute(units(3000)), &chain, &pool).unwrap();
+        assert_eq!(bumped.vin.iter().map(|vin| (&vin.from, vin.vout)).collect::<Vec<_>>(), tx.vin.iter().map(|vin| (&vin.from, vin.vout)).collect::<Vec<_>>());
+        assert_eq!(bumped.vout[0].value, units(30_000));
+        assert_eq!(bumped.vout[1].value, units(67_000));
+        let err = Transaction::bump_fee(&wallets, &tx.tranc_id, Fee::Absolute(units(1000)), &chain, &pool).unwrap_err();
+        assert!(err.to_string().starts_with("FEE_NOT_HIGHER"));
+        let err = Transaction::bump_fee(&wallets, &tx.tranc_id, Fee::Absolute(units(70_001)), &chain, &pool).unwrap_err();
+        assert!(err.to_string().starts_with("NOT_ENOUGH_CHANGE"));
+        //change that would be dust goes to the miner
+        let all_in = Transaction::bump_fee(&wallets, &tx.tranc_id, Fee::Absolute(units(69_997)), &chain, &pool).unwrap();
+        assert_eq!(all_in.vout.len(), 1);
+        assert_eq!(paid_fee(&all_in, units(100_000)), units(70_000));
+        //a fee per byte covers the size of the replacement
+        let per_byte = Transaction::bump_fee(&wallets, &tx.tranc_id, Fee::PerByte(units(10)), &chain, &pool).unwrap();
+        assert!(paid_fee(&per_byte, units(100_000)) >= Fee::PerByte(units(10)).for_size(per_byte.size().unwrap()));
+
+        //the replacement evicts the original and confirms in its place
+        assert_eq!(pool.add(bumped.clone(), &chain).unwrap(), units(3000));
+        assert!(pool.get(&tx.tranc_id).is_none());
+        assert_eq!(pool.len(), 1);
+        let err = Transaction::bump_fee(&wallets, &tx.tranc_id, Fee::Absolute(units(5000)), &chain, &pool).unwrap_err();
+        assert!(err.to_string().starts_with("NOT_PENDING"));
+        let (_, _, miner) = test_keys(9);
+        pool.mine_block(&mut chain, &miner, crate::miner::mempool::MAX_BLOCK_SIZE).unwrap();
+        assert!(chain.validate_chain().is_ok());
+        assert!(pool.is_empty());
+    }
+
+    #[test]
+    fn test_sign_spending_finds_unspent_and_pending_outputs(){
+        let dir = TempDir::new();
+        let (wallets, from, to, mut chain) = funded_wallets(&dir, units(100_000));
+        let mut pool = Mempool::open(&chain).unwrap();
+        let secret = wallets.get_wallet(&from).unwrap().secret_key.clone();
+        let tx = Transaction::new_utxo(&wallets, &from, &to, units(30_000), &chain, &options(Fee::Absolute(units(1000)), &pool)).unwrap();
+        pool.add(tx.clone(), &chain).unwrap();
+        //the change of a pending transaction signs like a confirmed output
+        let mut child = Transaction{ tranc_id: String::new(), vin: vec![TrancInput{ signature: Vec::new(), from: tx.tranc_id.clone(), vout: 1, ..tx.vin[0].clone() }], vout: vec![tx.vout[1].clone()] };
+        child.sign_spending(&secret, &chain, &pool).unwrap();
+        assert!(child.verify_outputs(&tx.vout[1..]).unwrap());
+
+        //an output spent by a block is gone
+        let mut again = Transaction{ tranc_id: String::new(), vin: vec![TrancInput{ signature: Vec::new(), ..tx.vin[0].clone() }], vout: tx.vout.clone() };
+        again.sign_spending(&secret, &chain, &pool).unwrap();
+        let (_, _, miner) = test_keys(9);
+        pool.mine_block(&mut chain, &miner, crate::miner::mempool::MAX_BLOCK_SIZE).unwrap();
+        let err = again.sign_spending(&secret, &chain, &pool).unwrap_err();
+        assert!(err.to_string().starts_with("MISSING_OUTPUT"), "{}", err);
+    }
+
+    #[test]
+    fn test_bump_fee_outbids_the_descendants(){
+        let dir = TempDir::new();
+        let (wallets, from, to, chain) = funded_wallets(&dir, units(100_000));
+        let mut pool = Mempool::open(&chain).unwrap();
+        let tx = Transaction::new_utxo(&wallets, &from, &to, units(30_000), &chain, &options(Fee::Absolute(units(1000)), &pool)).unwrap();
+        pool.add(tx.clone(), &chain).unwrap();
+        //a child spending the unconfirmed change
+        let child = Transaction::new_utxo(&wallets, &from, &to, units(10_000), &chain, &SpendOptions{ unconfirmed: true, ..options(Fee::Absolute(units(2000)), &pool) }).unwrap();
+        assert_eq!(child.vin[0].from, tx.tranc_id);
+        pool.add(child.clone(), &chain).unwrap();
+
+        //beating the fee of the original alone isn't enough, the child gets evicted too
+        let err = Transaction::bump_fee(&wallets, &tx.tranc_id, Fee::Absolute(units(2500)), &chain, &pool).unwrap_err();
+        assert!(err.to_string().starts_with("FEE_NOT_HIGHER"), "{}", err);
+        //and matching both leaves out the increment for the size of the replacement
+        let err = Transaction::bump_fee(&wallets, &tx.tranc_id, Fee::Absolute(units(3000)), &chain, &pool).unwrap_err();
+        assert!(err.to_string().starts_with("FEE_NOT_HIGHER"), "{}", err);
+
+        let size = tx.size().unwrap() as u64;
+        let bumped = Transaction::bump_fee(&wallets, &tx.tranc_id, Fee::Absolute(units(3000 + size)), &chain, &pool).unwrap();
+        assert_eq!(pool.add(bumped.clone(), &chain).unwrap(), units(3000 + size));
+        assert!(pool.get(&tx.tranc_id).is_none());
+        assert!(pool.get(&child.tranc_id).is_none());
+        assert_eq!(pool.len(), 1);
+    }
+}
@@ -1,7 +1,9 @@
 //? Modules used 
 use bitcoincash_addr::Address;
+use failure::format_err; //for handling errors
 use log::debug; //for showig debug result
 use serde::{Deserialize, Serialize}; // for serialization and deserialization
+use super::amount::Amount;//value of outputs
 use super::wallet::hash_pub_key;//impoorting the hash_pub_key function from the wallet 
 
 //? Transaction input refering to previous UTXO outputs to be used as an input source
@@ -16,7 +18,7 @@ pub struct TrancInput {
 //? Represents the transaction output creating a new UTXO for the receiver
 #[derive(Debug, Deserialize, Serialize,Clone)]
 pub struct TrancOutput {
-    pub value: i32,//value transfered
+    pub value: Amount,//value transfered
     pub pub_key_hash: Vec<u8>,//the hashed public key of the recipitent
 
 }
@@ -38,14 +40,16 @@ impl TrancOutput {
     }
 
     pub fn lock(&mut self, address: &str) -> Result<(),failure::Error>{
-        let pub_key_hash = Address::decode(address).unwrap().body;//decoding the pub key hash to vec<u8> from Base58
+        let pub_key_hash = Address::decode(address)
+            .map_err(|e| format_err!("INVALID_ADDRESS {}: {:?}", address, e))?
+            .body;//decoding the pub key hash to vec<u8> from Base58
         debug!("Lock: {}",address); //Locking the transaction address
         self.pub_key_hash = pub_key_hash; //setting the recipitant address
         Ok(())
     }
 
     //* To create a new transaction output */
-    pub fn new(value: i32, address: String) -> Result<Self,failure::Error>{
+    pub fn new(value: Amount, address: String) -> Result<Self,failure::Error>{
         let mut trancoutput = TrancOutput{
             value, //setting transaction value
             pub_key_hash: Vec::new(), //initislizing
@@ -1,5 +1,5 @@
 //? Modules used in Wallet
-use std::{collections::HashMap, io}; // for generating hashmap and io errors
+use std::{collections::HashMap, io, path::Path}; // for generating hashmap and io errors
 use crypto::digest::Digest; // for hashing
 use crypto::ripemd160::Ripemd160;// to rehash the SHA256 output for getting a 160 bit(20bytes) hashed public key
 use crypto::sha2::Sha256; // Sha256 algorithm for hashing 256bits (32bytes) output
@@ -63,28 +63,29 @@ pub fn hash_pub_key(pub_key: &mut Vec<u8>){
 }
 
 //? Struct for storing multiple wallets in a hashmap and then uploading them to the sled db
-#[derive(Serialize,Deserialize,Debug,Clone,PartialEq)]
+/// The database stays open as long as the struct lives, so a process opens it only once.
+#[derive(Debug,Clone)]
 pub struct Wallets{
-    wallets: HashMap<String,Wallet> //hashmap of wallets
+    wallets: HashMap<String,Wallet>, //hashmap of wallets
+    db: sled::Db, //the wallets database
 }
 
 //? implementations of the Wallets struct
 impl Wallets {
     //* new function to new wallets struct for creating and storing wallets */
-    pub fn new() -> Result<Wallets,io::Error>{
+    /// data_dir: directory holding the databases of the chain
+    pub fn new(data_dir: &Path) -> Result<Wallets,io::Error>{
         let mut wallets = Wallets{
             wallets: HashMap::<String,Wallet>::new(), //creating an instance of wallets struct
+            db: sled::open(data_dir.join("wallets"))?, //wallets are kept next to the blocks
         };
 
-        let db = sled::open("data/wallets")?;//opening the sled database
-
-        for item in db.into_iter(){
+        for item in wallets.db.iter(){
             let i = item?; //cheking the iterator and storing it
             let address = String::from_utf8(i.0.to_vec()).unwrap();//getting the address of the wallet 
-            let wallet = bincode::deserialize::<Wallet>(&i.1.to_vec()).unwrap();//getting the wallet
+            let wallet = bincode::deserialize::<Wallet>(&i.1).unwrap();//getting the wallet
             wallets.wallets.insert(address, wallet);//storing it in the hashmap 
         }
-        drop(db);//to dispose off the called database object
         Ok(wallets)//returning the wallets function
     }
 
@@ -100,7 +101,7 @@ impl Wallets {
     //* function to get all the address or all public addresses for a user */
     pub fn get_all_addresses(&self) -> Vec<String>{
         let mut addresses: Vec<String> = Vec::new(); //declaring a string vector for storing the public addresses
-        for (address,_) in &self.wallets{
+        for address in self.wallets.keys(){
             addresses.push(address.clone());//interating and pushing the addresses into the vector
         }
         addresses //returning the address list
@@ -113,13 +114,11 @@ impl Wallets {
 
     //* to save the newly created wallet and all other data into the sled db and flush it = */
     pub fn save_all(&self)->Result<(),io::Error>{
-        let db = sled::open("data/wallets")?; //opening the sled database
         for (address,wallet) in &self.wallets{
             let data: Vec<u8> = bincode::serialize(wallet).unwrap(); //serializing the data from string to vec<u8>
-            db.insert(address, data)?;// inserting as key-value pair
+            self.db.insert(address, data)?;// inserting as key-value pair
         }
-        db.flush()?; //flusing the database
-        drop(db); //dropping the database object
+        self.db.flush()?; //flusing the database
         Ok(())
     }
 }
\ No newline at end of file